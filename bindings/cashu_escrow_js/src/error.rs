@@ -1,12 +1,24 @@
+use cashu_escrow_common::error::EscrowError;
 use wasm_bindgen::JsValue;
 
 pub type Result<T, E = JsValue> = std::result::Result<T, E>;
 
-/// Helper to replace the `E` to `Error` to `napi::Error` conversion.
+/// Converts the error into a JS `Error` named `EscrowError`.
+///
+/// The kind of the error is exposed in the `code` property, e.g. `TIMEOUT` or `POLICY`.
 #[inline]
 pub fn into_err<E>(error: E) -> JsValue
 where
-    E: std::fmt::Display,
+    E: Into<EscrowError>,
 {
-    JsValue::from_str(&error.to_string())
+    let error: EscrowError = error.into();
+    let js_error = js_sys::Error::new(&error.to_string());
+    js_error.set_name("EscrowError");
+    // setting a property on a fresh error object can't fail
+    let _ = js_sys::Reflect::set(
+        &js_error,
+        &JsValue::from_str("code"),
+        &JsValue::from_str(error.code()),
+    );
+    js_error.into()
 }
@@ -6,12 +6,19 @@ use cashu_escrow_common::nostr::NostrClient;
 use cdk::amount::SplitTarget;
 use error::{into_err, Result};
 use log::Level;
-use models::{JsTradeContract, JsTradeMode};
+use models::{parse_unit, JsEscrowCosts, JsFundingInvoice, JsTradeContract, JsTradeMode};
 use nostr_sdk::prelude::*;
+#[cfg(target_arch = "wasm32")]
+use std::sync::Arc;
+#[cfg(target_arch = "wasm32")]
+use store::{CallbackStateStore, JsWalletStorage};
 use wasm_bindgen::prelude::*;
 
 mod error;
 mod models;
+// the JS storage callbacks can only be called from the wasm thread
+#[cfg(target_arch = "wasm32")]
+mod store;
 
 #[wasm_bindgen(start)]
 pub fn start() {
@@ -42,9 +49,48 @@ pub struct JsClientEcashWallet {
 
 #[wasm_bindgen(js_class = ClientEcashWallet)]
 impl JsClientEcashWallet {
+    /// Creates an in memory wallet, the unit defaults to sat.
     #[wasm_bindgen(constructor)]
-    pub async fn new(url: &str) -> Result<JsClientEcashWallet> {
-        let inner = ClientEcashWallet::new(url).await.map_err(into_err)?;
+    pub async fn new(url: &str, unit: Option<String>) -> Result<JsClientEcashWallet> {
+        let inner = ClientEcashWallet::new(url, parse_unit(unit)?)
+            .await
+            .map_err(into_err)?;
+        Ok(Self { inner })
+    }
+
+    #[wasm_bindgen(js_name = fromMnemonic)]
+    pub async fn from_mnemonic(
+        url: &str,
+        mnemonic: &str,
+        unit: Option<String>,
+    ) -> Result<JsClientEcashWallet> {
+        let inner = ClientEcashWallet::from_mnemonic(url, parse_unit(unit)?, mnemonic)
+            .await
+            .map_err(into_err)?;
+        Ok(Self { inner })
+    }
+
+    /// Creates a new wallet which is saved to the given storage.
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen(js_name = create)]
+    pub async fn create(
+        url: &str,
+        storage: JsWalletStorage,
+        unit: Option<String>,
+    ) -> Result<JsClientEcashWallet> {
+        let store = Arc::new(CallbackStateStore::new(storage));
+        let inner = ClientEcashWallet::create(url, parse_unit(unit)?, store)
+            .await
+            .map_err(into_err)?;
+        Ok(Self { inner })
+    }
+
+    /// Opens the wallet saved to the given storage.
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen(js_name = open)]
+    pub async fn open(storage: JsWalletStorage) -> Result<JsClientEcashWallet> {
+        let store = Arc::new(CallbackStateStore::new(storage));
+        let inner = ClientEcashWallet::open(store).await.map_err(into_err)?;
         Ok(Self { inner })
     }
 
@@ -59,9 +105,63 @@ impl JsClientEcashWallet {
         Ok(quote.id)
     }
 
-    #[wasm_bindgen(getter, js_name = tradePublicKey)]
-    pub fn trade_pubkey(&self) -> String {
-        self.inner.trade_pubkey.clone()
+    /// Requests a Lightning invoice from the mint which funds the wallet once paid.
+    #[wasm_bindgen(js_name = requestFunding)]
+    pub async fn request_funding(&self, amount: u64) -> Result<JsFundingInvoice> {
+        let inner = self
+            .inner
+            .request_funding(amount.into())
+            .await
+            .map_err(into_err)?;
+        Ok(JsFundingInvoice { inner })
+    }
+
+    /// Waits until the funding invoice is paid and returns the minted amount.
+    #[wasm_bindgen(js_name = waitForFunding)]
+    pub async fn wait_for_funding(&self, quote_id: &str, timeout_secs: u64) -> Result<u64> {
+        let minted = self
+            .inner
+            .wait_for_funding(quote_id, timeout_secs)
+            .await
+            .map_err(into_err)?;
+        Ok(minted.into())
+    }
+
+    /// Receives a cashu token into the wallet and returns the received amount.
+    #[wasm_bindgen(js_name = fundFromToken)]
+    pub async fn fund_from_token(&self, token: &str) -> Result<u64> {
+        let received = self.inner.fund_from_token(token).await.map_err(into_err)?;
+        Ok(received.into())
+    }
+
+    /// Restores the proofs of the wallet from the mint, returns the restored amount.
+    #[wasm_bindgen(js_name = restore)]
+    pub async fn restore(&self) -> Result<u64> {
+        let restored = self.inner.restore().await.map_err(into_err)?;
+        Ok(restored.into())
+    }
+
+    #[wasm_bindgen(getter, js_name = mnemonic)]
+    pub fn mnemonic(&self) -> String {
+        self.inner.mnemonic().to_string()
+    }
+
+    /// Hands out the ecash public key for a new trade.
+    #[wasm_bindgen(js_name = newTradePublicKey)]
+    pub fn new_trade_pubkey(&self) -> Result<String> {
+        let pubkey = self.inner.new_trade_pubkey().map_err(into_err)?;
+        Ok(pubkey.to_string())
+    }
+
+    /// Estimates the mint fees of the escrow, should be shown to the buyer before funding.
+    #[wasm_bindgen(js_name = estimateEscrowCosts)]
+    pub async fn estimate_escrow_costs(&self, contract: &JsTradeContract) -> Result<JsEscrowCosts> {
+        let inner = self
+            .inner
+            .estimate_escrow_costs(&contract.inner)
+            .await
+            .map_err(into_err)?;
+        Ok(JsEscrowCosts { inner })
     }
 }
 
@@ -88,6 +188,28 @@ impl JsInitEscrowClient {
         Ok(Self { inner })
     }
 
+    /// Exchanges the signed trade pubkeys with the trade partner, returns the partner's one.
+    #[wasm_bindgen(js_name = exchangeEcashPubkeys)]
+    pub async fn exchange_ecash_pubkeys(&mut self, timeout_secs: u64) -> Result<String> {
+        let pubkey = self
+            .inner
+            .exchange_ecash_pubkeys(timeout_secs)
+            .await
+            .map_err(into_err)?;
+        Ok(pubkey.to_string())
+    }
+
+    /// Signs the contract together with the trade partner, after `exchangeEcashPubkeys`.
+    /// The signed contract starts the trade with a single submission to the coordinator.
+    #[wasm_bindgen(js_name = exchangeContractSignatures)]
+    pub async fn exchange_contract_signatures(&mut self, timeout_secs: u64) -> Result<()> {
+        self.inner
+            .exchange_contract_signatures(timeout_secs)
+            .await
+            .map_err(into_err)?;
+        Ok(())
+    }
+
     #[wasm_bindgen(js_name = registerTrade)]
     pub async fn register_trade(self) -> Result<JsRegisteredEscrowClient> {
         let inner = self.inner.register_trade().await.map_err(into_err)?;
@@ -107,6 +229,26 @@ impl JsRegisteredEscrowClient {
         let inner = self.inner.exchange_trade_token().await.map_err(into_err)?;
         Ok(JsTokenExchangedEscrowClient { inner })
     }
+
+    /// Like `exchangeTradeToken`, but the seller requests the escrow token with a payment request (NUT-18).
+    #[wasm_bindgen(js_name = exchangeTradeTokenWithPaymentRequest)]
+    pub async fn exchange_trade_token_with_payment_request(
+        self,
+    ) -> Result<JsTokenExchangedEscrowClient> {
+        let inner = self
+            .inner
+            .exchange_trade_token_with_payment_request()
+            .await
+            .map_err(into_err)?;
+        Ok(JsTokenExchangedEscrowClient { inner })
+    }
+
+    /// The `creqA` payment request of the escrow, e.g. to be paid by another cashu wallet.
+    #[wasm_bindgen(js_name = paymentRequest)]
+    pub async fn payment_request(&self) -> Result<String> {
+        let request = self.inner.payment_request().await.map_err(into_err)?;
+        Ok(request.to_string())
+    }
 }
 
 #[wasm_bindgen(js_name = TokenExchangedEscrowClient)]
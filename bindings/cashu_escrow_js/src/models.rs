@@ -1,8 +1,14 @@
 use std::ops::Deref;
+use std::str::FromStr;
 
 use crate::error::{into_err, Result};
-use cashu_escrow_client::escrow_client::TradeMode;
-use cashu_escrow_common::model::TradeContract;
+use cashu_escrow_client::{
+    ecash::{fees::EscrowCosts, funding::FundingInvoice},
+    escrow_client::TradeMode,
+};
+use cashu_escrow_common::model::{FeePayer, TradeContract};
+use cashu_escrow_common::terms::TradeTerms;
+use cdk::{mint_url::MintUrl, nuts::CurrencyUnit};
 use nostr_sdk::PublicKey;
 use wasm_bindgen::prelude::*;
 
@@ -16,23 +22,119 @@ impl JsTradeContract {
     #[wasm_bindgen(constructor)]
     pub fn new(
         description: &str,
-        sat_amount: u64,
+        amount: u64,
         trade_nostr_identities: JsTradeNostrIdentities,
         time_limit: u64,
         ecash_identities: JsEcashIdentities,
+        mint_url: &str,
+        unit: Option<String>,
     ) -> Result<JsTradeContract> {
         let inner = TradeContract {
             trade_description: description.to_string(),
-            trade_amount_sat: sat_amount,
+            trade_amount: amount,
             npubkey_seller: npub_from_str(&trade_nostr_identities.seller_npub)?,
             npubkey_buyer: npub_from_str(&trade_nostr_identities.buyer_npub)?,
             npubkey_coordinator: npub_from_str(&trade_nostr_identities.coordinator_npub)?,
             time_limit,
             seller_ecash_public_key: ecash_identities.seller_pubkey,
             buyer_ecash_public_key: ecash_identities.buyer_pubkey,
+            mint_url: MintUrl::from_str(mint_url).map_err(into_err)?,
+            currency_unit: parse_unit(unit)?,
+            allowed_keysets: None,
+            fee_payer: FeePayer::default(),
+            terms: None,
+            privacy: None,
         };
         Ok(Self { inner })
     }
+
+    /// Sets who pays the redemption fee of the mint, the seller by default.
+    #[wasm_bindgen(setter, js_name = feePayer)]
+    pub fn set_fee_payer(&mut self, fee_payer: JsFeePayer) {
+        self.inner.fee_payer = fee_payer.into();
+    }
+
+    /// Sets the structured terms from their JSON, validated against the contract.
+    #[wasm_bindgen(js_name = setTerms)]
+    pub fn set_terms(&mut self, terms_json: &str) -> Result<()> {
+        let terms: TradeTerms = serde_json::from_str(terms_json).map_err(into_err)?;
+        terms.validate(&self.inner).map_err(into_err)?;
+        self.inner.terms = Some(terms);
+        Ok(())
+    }
+
+    /// Hides the description from the coordinator behind a salted hash commitment, it is
+    /// only revealed to the coordinator in a dispute.
+    #[wasm_bindgen(js_name = makePrivate)]
+    pub fn make_private(&mut self) {
+        self.inner.make_private();
+    }
+}
+
+#[wasm_bindgen(js_name = EscrowCosts)]
+pub struct JsEscrowCosts {
+    pub(crate) inner: EscrowCosts,
+}
+
+#[wasm_bindgen(js_class = EscrowCosts)]
+impl JsEscrowCosts {
+    #[wasm_bindgen(getter, js_name = tradeAmount)]
+    pub fn trade_amount(&self) -> u64 {
+        self.inner.trade_amount.into()
+    }
+
+    #[wasm_bindgen(getter, js_name = fundingFee)]
+    pub fn funding_fee(&self) -> u64 {
+        self.inner.funding_fee.into()
+    }
+
+    #[wasm_bindgen(getter, js_name = redemptionFee)]
+    pub fn redemption_fee(&self) -> u64 {
+        self.inner.redemption_fee.into()
+    }
+
+    #[wasm_bindgen(getter, js_name = escrowAmount)]
+    pub fn escrow_amount(&self) -> u64 {
+        self.inner.escrow_amount.into()
+    }
+
+    #[wasm_bindgen(getter, js_name = buyerTotal)]
+    pub fn buyer_total(&self) -> u64 {
+        self.inner.buyer_total.into()
+    }
+
+    #[wasm_bindgen(getter, js_name = sellerReceives)]
+    pub fn seller_receives(&self) -> u64 {
+        self.inner.seller_receives.into()
+    }
+}
+
+#[wasm_bindgen(js_name = FundingInvoice)]
+pub struct JsFundingInvoice {
+    pub(crate) inner: FundingInvoice,
+}
+
+#[wasm_bindgen(js_class = FundingInvoice)]
+impl JsFundingInvoice {
+    #[wasm_bindgen(getter, js_name = quoteId)]
+    pub fn quote_id(&self) -> String {
+        self.inner.quote_id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn invoice(&self) -> String {
+        self.inner.invoice.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn amount(&self) -> u64 {
+        self.inner.amount.into()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn expiry(&self) -> u64 {
+        self.inner.expiry
+    }
 }
 
 #[wasm_bindgen(js_name = TradeNostrIdentities)]
@@ -92,6 +194,29 @@ impl Deref for JsTradeMode {
     }
 }
 
+#[wasm_bindgen(js_name = FeePayer)]
+pub enum JsFeePayer {
+    Buyer,
+    Seller,
+}
+
+impl From<JsFeePayer> for FeePayer {
+    fn from(fee_payer: JsFeePayer) -> Self {
+        match fee_payer {
+            JsFeePayer::Buyer => FeePayer::Buyer,
+            JsFeePayer::Seller => FeePayer::Seller,
+        }
+    }
+}
+
+/// Parses the currency unit, defaults to sat.
+pub(crate) fn parse_unit(unit: Option<String>) -> Result<CurrencyUnit> {
+    match unit {
+        Some(unit) => CurrencyUnit::from_str(&unit).map_err(into_err),
+        None => Ok(CurrencyUnit::Sat),
+    }
+}
+
 fn npub_from_str(npub: &str) -> Result<PublicKey> {
     PublicKey::parse(npub).map_err(into_err)
 }
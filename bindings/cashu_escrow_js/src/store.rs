@@ -0,0 +1,53 @@
+use cashu_escrow_client::ecash::store::WalletStateStore;
+use cashu_escrow_common::error::{EscrowError, Result};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(typescript_custom_section)]
+const WALLET_STORAGE: &'static str = r#"
+export interface WalletStorage {
+    load(): string | undefined;
+    save(state: string): void;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    /// Storage implemented in JS, e.g. on top of `localStorage` or IndexedDB.
+    #[wasm_bindgen(typescript_type = "WalletStorage")]
+    pub type JsWalletStorage;
+
+    #[wasm_bindgen(method, catch)]
+    fn load(this: &JsWalletStorage) -> std::result::Result<Option<String>, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn save(this: &JsWalletStorage, state: &str) -> std::result::Result<(), JsValue>;
+}
+
+/// Saves the wallet through the callbacks of a [`JsWalletStorage`].
+pub(crate) struct CallbackStateStore {
+    storage: JsWalletStorage,
+}
+
+impl CallbackStateStore {
+    pub(crate) fn new(storage: JsWalletStorage) -> Self {
+        Self { storage }
+    }
+}
+
+// wasm is single threaded, the store never leaves the thread it was created on
+unsafe impl Send for CallbackStateStore {}
+unsafe impl Sync for CallbackStateStore {}
+
+impl WalletStateStore for CallbackStateStore {
+    fn load(&self) -> Result<Option<String>> {
+        self.storage.load().map_err(storage_err)
+    }
+
+    fn save(&self, state: &str) -> Result<()> {
+        self.storage.save(state).map_err(storage_err)
+    }
+}
+
+fn storage_err(error: JsValue) -> EscrowError {
+    EscrowError::Storage(format!("{:?}", error))
+}
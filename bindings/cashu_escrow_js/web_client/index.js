@@ -3,11 +3,13 @@ import { ClientEcashWallet, NostrClient, TradeContract, TradeNostrIdentities, Ec
 async function createWallet() {
     console.log("Creating wallet...");
     const escrowWallet = await new ClientEcashWallet("http://localhost:3338");
+    // every trade uses its own ecash key
+    const tradePublicKey = escrowWallet.newTradePublicKey();
     console.log("returning wallet created...");
-    return escrowWallet;
+    return { escrowWallet, tradePublicKey };
 }
 
-async function runTradePipeline(role, escrowWallet, partnerPubkey) {
+async function runTradePipeline(role, { escrowWallet, tradePublicKey }, partnerPubkey) {
     const mode = role === "buyer" ? TradeMode.Buyer : TradeMode.Seller;
     
     if (mode == TradeMode.Buyer) {
@@ -24,8 +26,8 @@ async function runTradePipeline(role, escrowWallet, partnerPubkey) {
     const tradeNostrIdentities = new TradeNostrIdentities(buyerNpub, sellerNpub, coordinatorNpub);
     const timeLimit = BigInt(3 * 24 * 60 * 60);
     const {buyerEcashPubkey, sellerEcashPubkey} = mode === TradeMode.Buyer ? 
-        {buyerEcashPubkey: escrowWallet.tradePublicKey, sellerEcashPubkey: partnerPubkey} : 
-        {buyerEcashPubkey: partnerPubkey, sellerEcashPubkey: escrowWallet.tradePublicKey};
+        {buyerEcashPubkey: tradePublicKey, sellerEcashPubkey: partnerPubkey} : 
+        {buyerEcashPubkey: partnerPubkey, sellerEcashPubkey: tradePublicKey};
     
     console.log("Creating a TradeContract...");
     const ecashIdentities = new EcashIdentities(buyerEcashPubkey, sellerEcashPubkey);
@@ -35,6 +37,7 @@ async function runTradePipeline(role, escrowWallet, partnerPubkey) {
         tradeNostrIdentities,
         timeLimit,
         ecashIdentities,
+        "http://localhost:3338",
     );
     console.log("Created a TradeContract", tradeContract);
     document.getElementById('contract').checked = true;
@@ -0,0 +1,126 @@
+use super::*;
+
+use cashu_escrow_common::model::FeePayer;
+use cdk::nuts::KeySetInfo;
+use std::fmt;
+
+/// Mint fees (NUT-02) over the lifecycle of an escrow, in the unit of the contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscrowCosts {
+    pub trade_amount: Amount,
+    /// Fee of swapping the buyer's proofs into the escrow proofs.
+    pub funding_fee: Amount,
+    /// Fee of swapping the escrow proofs into the receiver's wallet.
+    pub redemption_fee: Amount,
+    /// Value of the escrow token.
+    pub escrow_amount: Amount,
+    /// Amount the buyer spends from the wallet, including the funding fee.
+    pub buyer_total: Amount,
+    /// Amount the seller receives after redeeming the escrow token.
+    pub seller_receives: Amount,
+}
+
+impl fmt::Display for EscrowCosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "trade amount: {}, funding fee: {}, redemption fee: {}, buyer pays: {}, seller receives: {}",
+            self.trade_amount,
+            self.funding_fee,
+            self.redemption_fee,
+            self.buyer_total,
+            self.seller_receives
+        )
+    }
+}
+
+impl ClientEcashWallet {
+    /// Estimates the fees of funding and redeeming the escrow of the contract.
+    ///
+    /// The funding fee is computed for the proofs the wallet would spend in the swap into the
+    /// escrow proofs, see [`ClientEcashWallet::create_escrow_token`]. If the wallet can't cover
+    /// the escrow yet, it is estimated for proofs minted for exactly the needed amount.
+    pub async fn estimate_escrow_costs(&self, contract: &TradeContract) -> Result<EscrowCosts> {
+        self.check_contract_mint(contract)?;
+        let active_keyset = self.wallet.get_active_mint_keyset().await?;
+        let trade_amount = Amount::from(contract.trade_amount);
+        let redemption_fee = redemption_fee(&active_keyset, trade_amount)?;
+        let escrow_amount = match contract.fee_payer {
+            FeePayer::Buyer => checked_add(trade_amount, redemption_fee)?,
+            FeePayer::Seller => trade_amount,
+        };
+
+        let funding_fee = match self.select_escrow_inputs(escrow_amount).await {
+            Ok(selected) => self.wallet.get_proofs_fee(&selected).await?,
+            Err(EscrowError::Mint(cdk::Error::InsufficientFunds)) => {
+                swap_fee(&active_keyset, escrow_amount)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(EscrowCosts {
+            trade_amount,
+            funding_fee,
+            redemption_fee,
+            escrow_amount,
+            buyer_total: checked_add(escrow_amount, funding_fee)?,
+            seller_receives: Amount::from(
+                u64::from(escrow_amount).saturating_sub(u64::from(redemption_fee)),
+            ),
+        })
+    }
+
+    /// Value of the escrow token, the trade amount plus the redemption fee if the buyer pays it.
+    pub async fn escrow_amount(&self, contract: &TradeContract) -> Result<Amount> {
+        let trade_amount = Amount::from(contract.trade_amount);
+        match contract.fee_payer {
+            FeePayer::Buyer => {
+                let active_keyset = self.wallet.get_active_mint_keyset().await?;
+                checked_add(trade_amount, redemption_fee(&active_keyset, trade_amount)?)
+            }
+            FeePayer::Seller => Ok(trade_amount),
+        }
+    }
+}
+
+/// Fee of redeeming proofs worth `amount` plus the fee itself.
+///
+/// The escrow proofs are split into the [`escrow_denominations`], so adding the fee can add
+/// proofs which raise the fee again. The fee only grows, hence the loop ends after a few rounds.
+fn redemption_fee(keyset: &KeySetInfo, amount: Amount) -> Result<Amount> {
+    let mut fee = Amount::ZERO;
+    loop {
+        let proof_count = escrow_denominations(checked_add(amount, fee)?).len();
+        let required = input_fee(keyset, proof_count)?;
+        if required <= fee {
+            return Ok(fee);
+        }
+        fee = required;
+    }
+}
+
+/// Input fee of swapping proofs worth `amount` split into powers of two.
+fn swap_fee(keyset: &KeySetInfo, amount: Amount) -> Result<Amount> {
+    input_fee(keyset, amount.split().len())
+}
+
+/// Fee of spending `proof_count` proofs of the keyset, the fee rate is set by the mint.
+fn input_fee(keyset: &KeySetInfo, proof_count: usize) -> Result<Amount> {
+    keyset
+        .input_fee_ppk
+        .checked_mul(proof_count as u64)
+        .map(|fee_ppk| Amount::from(fee_ppk.div_ceil(1000)))
+        .ok_or(EscrowError::Validation(format!(
+            "Input fee of {} ppk of keyset {} is out of range",
+            keyset.input_fee_ppk, keyset.id
+        )))
+}
+
+fn checked_add(amount: Amount, fee: Amount) -> Result<Amount> {
+    amount
+        .checked_add(fee)
+        .ok_or(EscrowError::Validation(format!(
+            "{} plus the fee of {} exceed the maximum amount",
+            amount, fee
+        )))
+}
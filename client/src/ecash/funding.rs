@@ -0,0 +1,89 @@
+use super::*;
+
+use cashu_escrow_common::token::decode_token;
+use cdk::nuts::MintQuoteState;
+use nostr_sdk::async_utility::thread;
+use nostr_sdk::Timestamp;
+use std::time::Duration;
+
+/// Interval in which the mint is asked whether a funding invoice was paid.
+const FUNDING_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Lightning invoice which funds the wallet once paid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FundingInvoice {
+    pub quote_id: String,
+    /// BOLT11 invoice to be paid by the buyer.
+    pub invoice: String,
+    pub amount: Amount,
+    /// Unix timestamp after which the invoice can't be paid anymore, 0 if unknown.
+    pub expiry: u64,
+}
+
+impl ClientEcashWallet {
+    /// Requests an invoice from the mint, see [`ClientEcashWallet::wait_for_funding`].
+    pub async fn request_funding(&self, amount: Amount) -> Result<FundingInvoice> {
+        let quote = self.wallet.mint_quote(amount).await?;
+        Ok(FundingInvoice {
+            quote_id: quote.id,
+            invoice: quote.request,
+            amount: quote.amount,
+            expiry: quote.expiry,
+        })
+    }
+
+    /// Polls the mint until the invoice of the quote is paid, then mints the ecash.
+    ///
+    /// Returns the minted amount.
+    pub async fn wait_for_funding(&self, quote_id: &str, timeout_secs: u64) -> Result<Amount> {
+        let deadline = Timestamp::now().as_u64() + timeout_secs;
+        loop {
+            let quote = self.wallet.mint_quote_state(quote_id).await?;
+            match quote.state {
+                MintQuoteState::Paid => {
+                    let minted = self.wallet.mint(quote_id, SplitTarget::None, None).await?;
+                    debug!("Funded the wallet with {}", minted);
+                    return Ok(minted);
+                }
+                MintQuoteState::Issued => {
+                    return Err(EscrowError::Validation(format!(
+                        "The ecash of quote {} was already issued",
+                        quote_id
+                    )));
+                }
+                MintQuoteState::Unpaid | MintQuoteState::Pending => {}
+            }
+            let now = Timestamp::now().as_u64();
+            if quote
+                .expiry
+                .is_some_and(|expiry| expiry != 0 && now > expiry)
+            {
+                return Err(EscrowError::Timeout(format!(
+                    "The invoice of quote {} expired unpaid",
+                    quote_id
+                )));
+            }
+            if now >= deadline {
+                return Err(EscrowError::Timeout(format!(
+                    "The invoice of quote {} wasn't paid within {} seconds",
+                    quote_id, timeout_secs
+                )));
+            }
+            thread::sleep(FUNDING_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Funds the wallet with a `cashuA` or `cashuB` token, e.g. pasted by the buyer.
+    ///
+    /// The token has to be issued by the mint and in the unit of the wallet. Its proofs are
+    /// swapped, so the sender can't spend them anymore. Returns the received amount.
+    pub async fn fund_from_token(&self, encoded_token: &str) -> Result<Amount> {
+        let token = decode_token(encoded_token)?;
+        let received = self
+            .wallet
+            .receive(&token.to_string(), SplitTarget::None, &[], &[])
+            .await?;
+        debug!("Funded the wallet with {} from a token", received);
+        Ok(received)
+    }
+}
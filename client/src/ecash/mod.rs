@@ -1,103 +1,598 @@
+pub mod fees;
+pub mod funding;
+pub mod payout;
+pub mod store;
+pub mod validation;
+
 use super::*;
 
+use crate::keystore::{generate_mnemonic, parse_mnemonic, Keystore};
+use bip39::Mnemonic;
 use cashu_escrow_common::model::{EscrowRegistration, TradeContract};
 use cdk::{
     amount::SplitTarget,
-    cdk_database::WalletMemoryDatabase,
-    nuts::{Conditions, CurrencyUnit, PublicKey, SecretKey, SigFlag, SpendingConditions, Token},
-    secp256k1::rand::Rng,
-    wallet::{SendKind, Wallet},
+    cdk_database::{self, WalletDatabase, WalletMemoryDatabase},
+    dhke::construct_proofs,
+    nuts::{
+        CurrencyUnit, PreMintSecrets, Proof, Proofs, PublicKey, SecretKey, SpendingConditions,
+        State, SwapRequest, Token,
+    },
+    types::ProofInfo,
+    wallet::Wallet,
+    Amount, HttpClient,
 };
+use nostr_sdk::bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv};
+use nostr_sdk::bitcoin::Network;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use store::{PersistentWalletDatabase, TradeKeys, WalletSecrets, WalletStateStore};
+use zeroize::Zeroizing;
+
+/// Derivation path of the trade keys, the index of the trade key is appended as hardened child.
+///
+/// Uses the cashu purpose of NUT-13 with a separate branch, so trade keys never collide
+/// with the blinding secrets of the wallet.
+pub const TRADE_KEY_DERIVATION_PATH: &str = "m/129372'/1'";
+
+/// Number of unrecorded trade key indices searched for a key, e.g. after a restore.
+const TRADE_KEY_LOOKAHEAD: u32 = 100;
 
 #[derive(Debug)]
 pub struct ClientEcashWallet {
-    _secret: SecretKey,
+    /// Wiped from memory when the wallet is dropped.
+    mnemonic: Mnemonic,
+    /// Key of the [`TRADE_KEY_DERIVATION_PATH`], derived once as stretching the mnemonic
+    /// into the seed is expensive.
+    trade_key_branch: Xpriv,
+    trade_keys: Mutex<TradeKeys>,
+    /// Keys of trade partners and coordinators we searched for, they aren't searched again.
+    foreign_pubkeys: Mutex<HashSet<PublicKey>>,
+    localstore: Option<Arc<PersistentWalletDatabase>>,
     pub wallet: Wallet,
-    pub trade_pubkey: String,
 }
 
 impl ClientEcashWallet {
-    pub async fn new(mint_url: &str) -> anyhow::Result<Self> {
-        let localstore = WalletMemoryDatabase::default();
-        let _secret = SecretKey::generate();
-        let trade_pubkey: String = _secret.public_key().to_string();
-        let seed = rand::thread_rng().gen::<[u8; 32]>();
-        info!("Trade ecash pubkey: {}", trade_pubkey);
-
-        let wallet = Wallet::new(
-            mint_url,
-            CurrencyUnit::Sat,
-            Arc::new(localstore),
-            &seed,
-            None,
+    /// Creates a wallet with a new random mnemonic which only lives in memory.
+    ///
+    /// The wallet holds ecash of the given unit, see [`ClientEcashWallet::available_units`].
+    pub async fn new(mint_url: &str, unit: CurrencyUnit) -> Result<Self> {
+        Self::assemble(mint_url, unit, generate_mnemonic()?, None)
+    }
+
+    /// Creates an in memory wallet from an existing BIP39 mnemonic.
+    pub async fn from_mnemonic(mint_url: &str, unit: CurrencyUnit, mnemonic: &str) -> Result<Self> {
+        Self::assemble(mint_url, unit, parse_mnemonic(mnemonic)?, None)
+    }
+
+    /// Creates a new wallet which is saved to the store, fails if the store already holds one.
+    pub async fn create(
+        mint_url: &str,
+        unit: CurrencyUnit,
+        store: Arc<dyn WalletStateStore>,
+    ) -> Result<Self> {
+        Self::create_persistent(mint_url, unit, generate_mnemonic()?, store)
+    }
+
+    /// Like [`ClientEcashWallet::create`], but uses an existing BIP39 mnemonic,
+    /// e.g. to move the wallet to a new device before calling [`ClientEcashWallet::restore`].
+    pub async fn create_from_mnemonic(
+        mint_url: &str,
+        unit: CurrencyUnit,
+        mnemonic: &str,
+        store: Arc<dyn WalletStateStore>,
+    ) -> Result<Self> {
+        Self::create_persistent(mint_url, unit, parse_mnemonic(mnemonic)?, store)
+    }
+
+    /// Like [`ClientEcashWallet::create`], but uses the mnemonic of the keystore.
+    ///
+    /// The mnemonic isn't saved to the store, the wallet is opened with
+    /// [`ClientEcashWallet::open_with_keystore`].
+    pub async fn create_with_keystore(
+        mint_url: &str,
+        unit: CurrencyUnit,
+        keystore: &Keystore,
+        store: Arc<dyn WalletStateStore>,
+    ) -> Result<Self> {
+        let mnemonic = parse_mnemonic(&keystore.mnemonic())?;
+        let localstore = PersistentWalletDatabase::create(
+            store,
+            WalletSecrets {
+                mint_url: mint_url.to_string(),
+                unit,
+                mnemonic: None,
+            },
+        )?;
+        Self::assemble(mint_url, unit, mnemonic, Some(Arc::new(localstore)))
+    }
+
+    /// Opens the wallet saved to the store, including its proofs and trade key.
+    pub async fn open(store: Arc<dyn WalletStateStore>) -> Result<Self> {
+        let localstore = PersistentWalletDatabase::open(store)?;
+        let secrets = localstore.secrets()?;
+        let mnemonic = Zeroizing::new(secrets.mnemonic.ok_or(EscrowError::Validation(
+            "The wallet mnemonic is kept in a keystore, open the wallet with it".to_string(),
+        ))?);
+        Self::assemble(
+            &secrets.mint_url,
+            secrets.unit,
+            parse_mnemonic(&mnemonic)?,
+            Some(Arc::new(localstore)),
+        )
+    }
+
+    /// Opens the wallet saved to the store with the mnemonic of the keystore.
+    ///
+    /// A mnemonic saved to the store by [`ClientEcashWallet::create`] has to match the
+    /// one of the keystore, it is removed from the store, so it is only kept encrypted.
+    pub async fn open_with_keystore(
+        store: Arc<dyn WalletStateStore>,
+        keystore: &Keystore,
+    ) -> Result<Self> {
+        let localstore = PersistentWalletDatabase::open(store)?;
+        let secrets = localstore.secrets()?;
+        let mnemonic = parse_mnemonic(&keystore.mnemonic())?;
+        if let Some(stored) = secrets.mnemonic.map(Zeroizing::new) {
+            if parse_mnemonic(&stored)? != mnemonic {
+                return Err(EscrowError::Validation(
+                    "The wallet was created with another mnemonic than the one of the keystore"
+                        .to_string(),
+                ));
+            }
+            localstore.remove_mnemonic()?;
+        }
+        Self::assemble(
+            &secrets.mint_url,
+            secrets.unit,
+            mnemonic,
+            Some(Arc::new(localstore)),
+        )
+    }
+
+    fn create_persistent(
+        mint_url: &str,
+        unit: CurrencyUnit,
+        mnemonic: Mnemonic,
+        store: Arc<dyn WalletStateStore>,
+    ) -> Result<Self> {
+        let localstore = PersistentWalletDatabase::create(
+            store,
+            WalletSecrets {
+                mint_url: mint_url.to_string(),
+                unit,
+                mnemonic: Some(mnemonic.to_string()),
+            },
         )?;
+        Self::assemble(mint_url, unit, mnemonic, Some(Arc::new(localstore)))
+    }
+
+    fn assemble(
+        mint_url: &str,
+        unit: CurrencyUnit,
+        mnemonic: Mnemonic,
+        localstore: Option<Arc<PersistentWalletDatabase>>,
+    ) -> Result<Self> {
+        let seed = Zeroizing::new(mnemonic.to_seed(""));
+        let (database, trade_keys): (
+            Arc<dyn WalletDatabase<Err = cdk_database::Error> + Send + Sync>,
+            TradeKeys,
+        ) = match &localstore {
+            Some(localstore) => (localstore.clone(), localstore.trade_keys()?),
+            None => (
+                Arc::new(WalletMemoryDatabase::default()),
+                TradeKeys::default(),
+            ),
+        };
+        let wallet = Wallet::new(mint_url, unit, database, seed.as_ref(), None)?;
+        let trade_key_branch = derive_trade_key_branch(seed.as_ref())?;
 
         Ok(Self {
-            _secret,
+            mnemonic,
+            trade_key_branch,
+            trade_keys: Mutex::new(trade_keys),
+            foreign_pubkeys: Mutex::new(HashSet::new()),
+            localstore,
             wallet,
-            trade_pubkey,
         })
     }
 
-    fn assemble_escrow_conditions(
+    /// The currency units the mint issues ecash in.
+    pub async fn available_units(&self) -> Result<Vec<CurrencyUnit>> {
+        let mut units: Vec<CurrencyUnit> = Vec::new();
+        for keyset in self.wallet.get_mint_keysets().await? {
+            if keyset.active && !units.contains(&keyset.unit) {
+                units.push(keyset.unit);
+            }
+        }
+        Ok(units)
+    }
+
+    /// The BIP39 mnemonic the wallet seed and all trade keys are derived from.
+    pub fn mnemonic(&self) -> Zeroizing<String> {
+        Zeroizing::new(self.mnemonic.to_string())
+    }
+
+    /// Derives the P2PK key of the trade with the given index.
+    pub fn trade_key(&self, index: u32) -> Result<SecretKey> {
+        derive_trade_key(&self.trade_key_branch, index)
+    }
+
+    /// Hands out the key for a new trade, every [`TradeContract`] should use its own key.
+    ///
+    /// The key is recorded, so it is found again when the escrow proofs are signed.
+    pub fn new_trade_pubkey(&self) -> Result<PublicKey> {
+        let mut trade_keys = self.lock_trade_keys()?;
+        let index = trade_keys.next_index;
+        let pubkey = self.trade_key(index)?.public_key();
+        let mut updated = trade_keys.clone();
+        updated.record(pubkey, index);
+        // only recorded once saved, so the wallet and the store agree on the next index
+        self.save_trade_keys(&updated)?;
+        *trade_keys = updated;
+        info!("Trade ecash pubkey: {}", pubkey);
+        Ok(pubkey)
+    }
+
+    /// Looks up the secret of one of our trade keys.
+    ///
+    /// Keys which were not recorded, e.g. on a restored wallet, are searched beyond the last known index.
+    pub fn trade_secret(&self, trade_pubkey: &PublicKey) -> Result<SecretKey> {
+        self.own_trade_secrets(&HashSet::from([*trade_pubkey]))?
+            .remove(trade_pubkey)
+            .ok_or(EscrowError::Validation(format!(
+                "No trade key found for {}",
+                trade_pubkey
+            )))
+    }
+
+    /// The secrets of those pubkeys which are our trade keys.
+    ///
+    /// The unrecorded indices are searched once for all pubkeys, pubkeys not found are
+    /// remembered as foreign and skipped by later lookups.
+    fn own_trade_secrets(
+        &self,
+        pubkeys: &HashSet<PublicKey>,
+    ) -> Result<HashMap<PublicKey, SecretKey>> {
+        let mut trade_keys = self.lock_trade_keys()?;
+        let mut foreign_pubkeys = self
+            .foreign_pubkeys
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?;
+        let mut secrets = HashMap::new();
+        let mut unknown = HashSet::new();
+        for pubkey in pubkeys {
+            match trade_keys.indices.get(pubkey) {
+                Some(index) => {
+                    secrets.insert(*pubkey, self.trade_key(*index)?);
+                }
+                None if !foreign_pubkeys.contains(pubkey) => {
+                    unknown.insert(*pubkey);
+                }
+                None => {}
+            }
+        }
+        if unknown.is_empty() {
+            return Ok(secrets);
+        }
+        let mut found = false;
+        for index in 0..trade_keys.next_index + TRADE_KEY_LOOKAHEAD {
+            let secret = self.trade_key(index)?;
+            let pubkey = secret.public_key();
+            if unknown.remove(&pubkey) {
+                trade_keys.record(pubkey, index);
+                secrets.insert(pubkey, secret);
+                found = true;
+                if unknown.is_empty() {
+                    break;
+                }
+            }
+        }
+        if found {
+            self.save_trade_keys(&trade_keys)?;
+        }
+        foreign_pubkeys.extend(unknown);
+        Ok(secrets)
+    }
+
+    fn lock_trade_keys(&self) -> Result<std::sync::MutexGuard<'_, TradeKeys>> {
+        self.trade_keys
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))
+    }
+
+    fn save_trade_keys(&self, trade_keys: &TradeKeys) -> Result<()> {
+        match &self.localstore {
+            Some(localstore) => localstore.set_trade_keys(trade_keys.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Our trade keys among the keys the escrow proofs are locked or refundable to.
+    ///
+    /// The first vector contains the keys of the lock, the second the refund keys.
+    fn escrow_signing_keys(
+        &self,
+        escrow_token: &Token,
+    ) -> Result<(Vec<SecretKey>, Vec<SecretKey>)> {
+        let mut lock_keys = HashSet::new();
+        let mut refund_keys = HashSet::new();
+        for proof in escrow_token.proofs().into_values().flatten() {
+            let conditions = SpendingConditions::try_from(&proof.secret)?;
+            lock_keys.extend(conditions.pubkeys().unwrap_or_default());
+            refund_keys.extend(conditions.refund_keys().unwrap_or_default());
+        }
+        let secrets = self.own_trade_secrets(&lock_keys.union(&refund_keys).copied().collect())?;
+        let own_keys = |pubkeys: HashSet<PublicKey>| -> Vec<SecretKey> {
+            pubkeys
+                .iter()
+                .filter_map(|pubkey| secrets.get(pubkey).cloned())
+                .collect()
+        };
+        Ok((own_keys(lock_keys), own_keys(refund_keys)))
+    }
+
+    /// Restores the proofs of the wallet from the mint (NUT-13).
+    ///
+    /// Returns the restored amount.
+    pub async fn restore(&self) -> Result<Amount> {
+        let restored = self.wallet.restore().await?;
+        debug!("Restored {} from the mint", restored);
+        Ok(restored)
+    }
+
+    /// Spending conditions of the escrow token, see [`ClientEcashWallet::create_escrow_token`].
+    pub fn assemble_escrow_conditions(
         contract: &TradeContract,
         escrow_registration: &EscrowRegistration,
-    ) -> anyhow::Result<SpendingConditions> {
-        let seller_pubkey = PublicKey::from_str(&contract.seller_ecash_public_key)?;
-        let buyer_pubkey = PublicKey::from_str(&contract.buyer_ecash_public_key)?;
-        let coordinator_escrow_pubkey = escrow_registration.coordinator_escrow_pubkey;
-        let start_timestamp = escrow_registration.escrow_start_time;
-
-        let locktime = start_timestamp.as_u64() + contract.time_limit;
-
-        let spending_conditions = SpendingConditions::new_p2pk(
-            seller_pubkey,
-            Some(Conditions::new(
-                Some(locktime),
-                Some(vec![buyer_pubkey, coordinator_escrow_pubkey]),
-                Some(vec![buyer_pubkey]),
-                Some(2),
-                Some(SigFlag::SigAll),
-            )?),
-        );
-        Ok(spending_conditions)
+    ) -> Result<SpendingConditions> {
+        contract.escrow_conditions(
+            &escrow_registration.coordinator_escrow_pubkey,
+            escrow_registration.escrow_start_time,
+        )
     }
 
     pub async fn create_escrow_token(
         &self,
         contract: &TradeContract,
         escrow_registration: &EscrowRegistration,
-    ) -> anyhow::Result<Token> {
+    ) -> Result<Token> {
         trace!(
             "create escrow token, current balance: {}",
             self.wallet.total_balance().await?
         );
+        self.check_contract_mint(contract)?;
+        if let Some(allowed_keysets) = &contract.allowed_keysets {
+            // the escrow proofs are swapped into the active keyset of the mint
+            let active_keyset = self.wallet.get_active_mint_keyset().await?;
+            if !allowed_keysets.contains(&active_keyset.id) {
+                return Err(EscrowError::Validation(format!(
+                    "The active keyset {} of the mint is not allowed by the contract",
+                    active_keyset.id
+                )));
+            }
+        }
         let spending_conditions = Self::assemble_escrow_conditions(contract, escrow_registration)?;
-        let token = self
-            .wallet
-            .send(
-                contract.trade_amount_sat.into(),
-                Some(contract.trade_description.clone()),
-                Some(spending_conditions),
-                &SplitTarget::None,
-                &SendKind::OnlineExact,
-                true,
-            )
+        let locktime = escrow_registration.escrow_start_time.as_u64() + contract.time_limit;
+        if locktime < nostr_sdk::Timestamp::now().as_u64() {
+            return Err(EscrowError::Validation(
+                "The time limit of the escrow already expired".to_string(),
+            ));
+        }
+        let escrow_amount = self.escrow_amount(contract).await?;
+        let proofs = self
+            .swap_into_escrow(escrow_amount, &spending_conditions)
             .await?;
-        Ok(token)
+        Ok(Token::new(
+            self.wallet.mint_url.clone(),
+            proofs,
+            Some(contract.trade_description.clone()),
+            Some(self.wallet.unit),
+        ))
+    }
+
+    /// Proofs of the wallet spent on funding an escrow worth `escrow_amount`.
+    async fn select_escrow_inputs(&self, escrow_amount: Amount) -> Result<Proofs> {
+        let proofs = self.wallet.get_proofs().await?;
+        Ok(self
+            .wallet
+            .select_proofs_to_swap(escrow_amount, proofs)
+            .await?)
     }
 
-    pub fn validate_escrow_token(
+    /// Swaps proofs of the wallet into the escrow proofs locked to `conditions` and the change.
+    ///
+    /// The swap of the cdk wallet splits locked outputs into powers of two, the escrow proofs
+    /// need the [`escrow_denominations`]. Both are swapped at once, so the escrow is never
+    /// funded partially and the swap fee is paid once.
+    async fn swap_into_escrow(
         &self,
-        escrow_token: &Token,
-        contract: &TradeContract,
-        escrow_registration: &EscrowRegistration,
-    ) -> anyhow::Result<()> {
-        let spending_conditions = Self::assemble_escrow_conditions(contract, escrow_registration)?;
+        escrow_amount: Amount,
+        conditions: &SpendingConditions,
+    ) -> Result<Proofs> {
+        let input_proofs = self.select_escrow_inputs(escrow_amount).await?;
+        // derives the change outputs and marks the inputs as pending
+        let pre_swap = self
+            .wallet
+            .create_swap(
+                Some(escrow_amount),
+                SplitTarget::None,
+                input_proofs.clone(),
+                Some(conditions.clone()),
+                // the redemption fee is already part of the escrow amount
+                false,
+            )
+            .await?;
+        let keyset_id = pre_swap.pre_mint_secrets.keyset_id;
+        let mut outputs = PreMintSecrets::with_conditions(
+            keyset_id,
+            escrow_amount,
+            &SplitTarget::Values(escrow_denominations(escrow_amount)),
+            conditions,
+        )?;
+        outputs.combine(PreMintSecrets {
+            secrets: pre_swap
+                .pre_mint_secrets
+                .secrets
+                .into_iter()
+                .filter(|pre_mint| SpendingConditions::try_from(&pre_mint.secret).is_err())
+                .collect(),
+            keyset_id,
+        });
+        outputs.sort_secrets();
+
+        let swap_request = SwapRequest::new(input_proofs.clone(), outputs.blinded_messages());
+        let swap_response = HttpClient::new()
+            .post_swap(self.wallet.mint_url.clone().try_into()?, swap_request)
+            .await?;
+        let keys = self.wallet.get_keyset_keys(keyset_id).await?;
+        let proofs = construct_proofs(
+            swap_response.signatures,
+            outputs.rs(),
+            outputs.secrets(),
+            &keys,
+        )
+        .map_err(cdk::Error::from)?;
         self.wallet
-            .verify_token_p2pk(escrow_token, spending_conditions)?;
+            .localstore
+            .increment_keyset_counter(&keyset_id, pre_swap.derived_secret_count)
+            .await
+            .map_err(cdk::Error::from)?;
+
+        let (escrow_proofs, change_proofs): (Proofs, Proofs) = proofs
+            .into_iter()
+            .partition(|proof| SpendingConditions::try_from(&proof.secret).is_ok());
+        let mut added_proofs = Vec::new();
+        for (proofs, state) in [
+            (&escrow_proofs, State::Reserved),
+            (&change_proofs, State::Unspent),
+        ] {
+            for proof in proofs {
+                added_proofs.push(ProofInfo::new(
+                    proof.clone(),
+                    self.wallet.mint_url.clone(),
+                    state,
+                    self.wallet.unit,
+                )?);
+            }
+        }
+        let spent_ys = input_proofs
+            .iter()
+            .map(Proof::y)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        self.wallet
+            .localstore
+            .update_proofs(added_proofs, spent_ys)
+            .await
+            .map_err(cdk::Error::from)?;
+        Ok(escrow_proofs)
+    }
+
+    /// Ensures the wallet operates on the mint and unit agreed in the contract.
+    fn check_contract_mint(&self, contract: &TradeContract) -> Result<()> {
+        if self.wallet.mint_url != contract.mint_url || self.wallet.unit != contract.currency_unit {
+            return Err(EscrowError::Validation(format!(
+                "The wallet uses {} {}, the contract requires {} {}",
+                self.wallet.mint_url, self.wallet.unit, contract.mint_url, contract.currency_unit
+            )));
+        }
         Ok(())
     }
+
+    /// Adds the signature of our trade key to the escrow proofs, e.g. to release them to the seller.
+    pub fn sign_escrow_token(&self, escrow_token: &Token) -> Result<Token> {
+        let (lock_keys, _) = self.escrow_signing_keys(escrow_token)?;
+        if lock_keys.is_empty() {
+            return Err(EscrowError::Validation(
+                "The escrow token is not locked to any of our trade keys".to_string(),
+            ));
+        }
+        let mut signed_proofs = Vec::new();
+        for mut proof in escrow_token.proofs().into_values().flatten() {
+            for secret in &lock_keys {
+                proof.sign_p2pk(secret.clone())?;
+            }
+            signed_proofs.push(proof);
+        }
+        let mint_url = escrow_token
+            .proofs()
+            .into_keys()
+            .next()
+            .ok_or(EscrowError::Validation(
+                "Escrow token contains no proofs".to_string(),
+            ))?;
+        Ok(Token::new(
+            mint_url,
+            signed_proofs,
+            escrow_token.memo().clone(),
+            *escrow_token.unit(),
+        ))
+    }
+
+    /// Adds our signature to the escrow proofs and swaps them into the wallet.
+    ///
+    /// The proofs must already carry the second signature, e.g. the buyer's after a release
+    /// or the coordinator's after a verdict. After the locktime the buyer can redeem them
+    /// with the refund key alone.
+    pub async fn redeem_escrow_token(&self, escrow_token: &Token) -> Result<Amount> {
+        let (lock_keys, refund_keys) = self.escrow_signing_keys(escrow_token)?;
+        // the wallet only signs with the lock keys, refunds have to be signed up front
+        let signing_keys: Vec<SecretKey> =
+            lock_keys.into_iter().chain(refund_keys.clone()).collect();
+        let mut redeemed = Amount::ZERO;
+        for mut proofs in escrow_token.proofs().into_values() {
+            for proof in proofs.iter_mut() {
+                for refund_key in &refund_keys {
+                    proof.sign_p2pk(refund_key.clone())?;
+                }
+            }
+            redeemed += self
+                .wallet
+                .receive_proofs(proofs, SplitTarget::None, &signing_keys, &[])
+                .await?;
+        }
+        Ok(redeemed)
+    }
+}
+
+/// Denominations of the escrow proofs, every split of the amount is payable with a subset.
+///
+/// Each denomination is at most one more than the sum of the smaller ones, so a split is
+/// found by taking the largest proofs which still fit.
+pub fn escrow_denominations(amount: Amount) -> Vec<Amount> {
+    let (series, remainder) = escrow_parts(amount);
+    let mut denominations: Vec<Amount> = series.split();
+    denominations.extend(remainder.split());
+    denominations.sort_by(|a, b| b.cmp(a));
+    denominations
+}
+
+/// The largest amount `2^n - 1` which fits, it splits into all powers of two below `2^n`,
+/// and the remainder.
+fn escrow_parts(amount: Amount) -> (Amount, Amount) {
+    let amount = u64::from(amount);
+    let series = match amount.checked_add(1) {
+        Some(next) => (1u64 << next.ilog2()) - 1,
+        None => u64::MAX,
+    };
+    (Amount::from(series), Amount::from(amount - series))
+}
+
+fn derive_trade_key_branch(seed: &[u8]) -> Result<Xpriv> {
+    let path = DerivationPath::from_str(TRADE_KEY_DERIVATION_PATH).map_err(key_error)?;
+    Xpriv::new_master(Network::Bitcoin, seed)
+        .and_then(|master| master.derive_priv(&nostr_sdk::SECP256K1, &path))
+        .map_err(key_error)
+}
+
+fn derive_trade_key(branch: &Xpriv, index: u32) -> Result<SecretKey> {
+    let child = ChildNumber::from_hardened_idx(index).map_err(key_error)?;
+    let xpriv = branch
+        .derive_priv(&nostr_sdk::SECP256K1, &[child])
+        .map_err(key_error)?;
+    Ok(SecretKey::from_slice(&xpriv.private_key.secret_bytes())?)
+}
+
+fn key_error(e: nostr_sdk::bitcoin::bip32::Error) -> EscrowError {
+    EscrowError::Validation(e.to_string())
 }
@@ -0,0 +1,292 @@
+use super::*;
+
+use async_trait::async_trait;
+use cdk::nuts::MeltQuoteState;
+use serde::Deserialize;
+use std::fmt;
+
+/// Number of invoices requested from a Lightning address until the fee reserve fits.
+const MAX_INVOICE_ATTEMPTS: usize = 3;
+
+/// Resolves a Lightning address to a BOLT11 invoice over the given amount.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait LightningAddressResolver: Send + Sync {
+    async fn resolve(&self, address: &str, amount_msat: u64) -> Result<String>;
+}
+
+/// Resolves Lightning addresses with LNURL-pay (LUD-06, LUD-16).
+#[derive(Debug, Default)]
+pub struct LnurlResolver {
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PayRequest {
+    callback: String,
+    min_sendable: u64,
+    max_sendable: u64,
+}
+
+#[derive(Deserialize)]
+struct PayRequestInvoice {
+    pr: Option<String>,
+    reason: Option<String>,
+}
+
+impl LnurlResolver {
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        let response = self
+            .http
+            .get(url)
+            .query(query)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| EscrowError::Transport(format!("LNURL request failed: {}", e)))?;
+        response
+            .json()
+            .await
+            .map_err(|e| EscrowError::Protocol(format!("Invalid LNURL response: {}", e)))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl LightningAddressResolver for LnurlResolver {
+    async fn resolve(&self, address: &str, amount_msat: u64) -> Result<String> {
+        let (user, domain) = address
+            .split_once('@')
+            .ok_or(EscrowError::Validation(format!(
+                "Invalid Lightning address {}",
+                address
+            )))?;
+        let pay_request: PayRequest = self
+            .get(
+                &format!("https://{}/.well-known/lnurlp/{}", domain, user),
+                &[],
+            )
+            .await?;
+        if !(pay_request.min_sendable..=pay_request.max_sendable).contains(&amount_msat) {
+            return Err(EscrowError::Validation(format!(
+                "{} accepts between {} and {} msat, not {} msat",
+                address, pay_request.min_sendable, pay_request.max_sendable, amount_msat
+            )));
+        }
+        let invoice: PayRequestInvoice = self
+            .get(
+                &pay_request.callback,
+                &[("amount", amount_msat.to_string())],
+            )
+            .await?;
+        invoice.pr.ok_or(EscrowError::Protocol(format!(
+            "{} returned no invoice: {}",
+            address,
+            invoice.reason.unwrap_or_default()
+        )))
+    }
+}
+
+/// Where the seller wants to receive the proceeds of the escrow.
+#[derive(Clone)]
+pub enum LightningDestination {
+    /// A BOLT11 invoice, its amount plus the fee reserve must be covered by the proceeds.
+    Invoice(String),
+    /// A Lightning address, the invoice is requested for the proceeds minus the fee reserve.
+    Address {
+        address: String,
+        resolver: Arc<dyn LightningAddressResolver>,
+    },
+}
+
+impl LightningDestination {
+    /// A Lightning address resolved with LNURL-pay.
+    pub fn address(address: &str) -> Self {
+        Self::Address {
+            address: address.to_string(),
+            resolver: Arc::new(LnurlResolver::default()),
+        }
+    }
+}
+
+impl fmt::Debug for LightningDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invoice(invoice) => f.debug_tuple("Invoice").field(invoice).finish(),
+            Self::Address { address, .. } => f.debug_tuple("Address").field(address).finish(),
+        }
+    }
+}
+
+/// Outcome of paying out ecash over Lightning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayoutStatus {
+    Paid {
+        amount: Amount,
+        fee_paid: Amount,
+        preimage: Option<String>,
+    },
+    /// The mint hasn't settled the payment yet, see [`ClientEcashWallet::check_lightning_payout`].
+    Pending { quote_id: String },
+    /// The payment failed, the proceeds remain as ecash in the wallet.
+    Failed { reason: String },
+}
+
+/// Result of redeeming the escrow token and paying out the proceeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settlement {
+    pub redeemed: Amount,
+    pub payout: PayoutStatus,
+}
+
+impl ClientEcashWallet {
+    /// Redeems the escrow token and melts the proceeds to the Lightning destination.
+    ///
+    /// Errors are only returned if the redemption fails. If the payout fails afterwards the
+    /// settlement reports it and the proceeds stay in the wallet, so it can be retried.
+    pub async fn settle_to_lightning(
+        &self,
+        escrow_token: &Token,
+        destination: &LightningDestination,
+    ) -> Result<Settlement> {
+        let redeemed = self.redeem_escrow_token(escrow_token).await?;
+        let payout = match self.pay_lightning(destination, redeemed).await {
+            Ok(payout) => payout,
+            Err(e) => {
+                warn!(
+                    "Lightning payout failed, keeping {} as ecash: {}",
+                    redeemed, e
+                );
+                PayoutStatus::Failed {
+                    reason: e.to_string(),
+                }
+            }
+        };
+        Ok(Settlement { redeemed, payout })
+    }
+
+    /// Melts at most `available` of the wallet balance to the destination.
+    ///
+    /// The mint reserves a fee for routing the payment, unused parts of it are returned as change.
+    pub async fn pay_lightning(
+        &self,
+        destination: &LightningDestination,
+        available: Amount,
+    ) -> Result<PayoutStatus> {
+        let quote = match destination {
+            LightningDestination::Invoice(invoice) => {
+                let quote = self.wallet.melt_quote(invoice.clone(), None).await?;
+                if quote_total(&quote)? > available {
+                    return Err(EscrowError::Validation(format!(
+                        "Invoice of {} with a fee reserve of {} exceeds the available {}",
+                        quote.amount, quote.fee_reserve, available
+                    )));
+                }
+                quote
+            }
+            LightningDestination::Address { address, resolver } => {
+                self.quote_lightning_address(address, resolver.as_ref(), available)
+                    .await?
+            }
+        };
+        debug!(
+            "Paying {} with a fee reserve of {} over Lightning",
+            quote.amount, quote.fee_reserve
+        );
+
+        // the proofs are selected here to know what the melt spends, the mint returns
+        // the unused fee reserve as change
+        let proofs = self.wallet.get_proofs().await?;
+        let input_proofs = self
+            .wallet
+            .select_proofs_to_swap(quote_total(&quote)?, proofs)
+            .await?;
+        let inputs = proofs_value(&input_proofs)?;
+        let melted = self.wallet.melt_proofs(&quote.id, input_proofs).await?;
+        Ok(match melted.state {
+            MeltQuoteState::Paid => {
+                let change = proofs_value(melted.change.as_deref().unwrap_or_default())?;
+                let fee_paid = u64::from(inputs)
+                    .checked_sub(u64::from(change))
+                    .and_then(|spent| spent.checked_sub(u64::from(quote.amount)))
+                    .ok_or(EscrowError::Validation(format!(
+                        "The change of {} and the payment of {} exceed the melted {}",
+                        change, quote.amount, inputs
+                    )))?;
+                PayoutStatus::Paid {
+                    amount: quote.amount,
+                    fee_paid: Amount::from(fee_paid),
+                    preimage: melted.preimage,
+                }
+            }
+            MeltQuoteState::Pending => PayoutStatus::Pending { quote_id: quote.id },
+            MeltQuoteState::Unpaid => PayoutStatus::Failed {
+                reason: "The mint could not pay the invoice".to_string(),
+            },
+        })
+    }
+
+    /// Asks the mint for the state of a pending payout.
+    pub async fn check_lightning_payout(&self, quote_id: &str) -> Result<MeltQuoteState> {
+        Ok(self.wallet.melt_quote_status(quote_id).await?.state)
+    }
+
+    async fn quote_lightning_address(
+        &self,
+        address: &str,
+        resolver: &dyn LightningAddressResolver,
+        available: Amount,
+    ) -> Result<cdk::wallet::types::MeltQuote> {
+        if self.wallet.unit != CurrencyUnit::Sat {
+            return Err(EscrowError::Validation(format!(
+                "Lightning addresses can only be paid from sat wallets, not {}",
+                self.wallet.unit
+            )));
+        }
+        let mut amount = available;
+        for _ in 0..MAX_INVOICE_ATTEMPTS {
+            let amount_msat =
+                u64::from(amount)
+                    .checked_mul(1000)
+                    .ok_or(EscrowError::Validation(format!(
+                        "{} exceeds the maximum amount of a Lightning payment",
+                        amount
+                    )))?;
+            let invoice = resolver.resolve(address, amount_msat).await?;
+            let quote = self.wallet.melt_quote(invoice, None).await?;
+            if quote_total(&quote)? <= available {
+                return Ok(quote);
+            }
+            // request a smaller invoice, leaving room for the fee reserve
+            amount =
+                Amount::from(u64::from(available).saturating_sub(u64::from(quote.fee_reserve)));
+            if amount == Amount::ZERO {
+                break;
+            }
+        }
+        Err(EscrowError::Validation(format!(
+            "{} is not enough to pay {} including the fee reserve",
+            available, address
+        )))
+    }
+}
+
+/// Amount of the quote plus the fee reserve, which the melt has to cover.
+fn quote_total(quote: &cdk::wallet::types::MeltQuote) -> Result<Amount> {
+    quote
+        .amount
+        .checked_add(quote.fee_reserve)
+        .ok_or(EscrowError::Validation(format!(
+            "{} plus the fee reserve of {} exceed the maximum amount",
+            quote.amount, quote.fee_reserve
+        )))
+}
+
+fn proofs_value(proofs: &[Proof]) -> Result<Amount> {
+    Ok(Amount::try_sum(proofs.iter().map(|proof| proof.amount)).map_err(cdk::Error::from)?)
+}
@@ -0,0 +1,442 @@
+use super::*;
+
+use async_trait::async_trait;
+use cdk::{
+    cdk_database::{self, WalletDatabase},
+    mint_url::MintUrl,
+    nuts::{Id, KeySetInfo, Keys, MintInfo, SpendingConditions, State},
+    types::ProofInfo,
+    wallet::{MeltQuote, MintQuote},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Backend the serialized wallet is written to, e.g. a file or the browser storage.
+///
+/// The whole wallet is saved as one document after every change.
+pub trait WalletStateStore: Send + Sync {
+    /// Returns `None` if no wallet was saved yet.
+    fn load(&self) -> Result<Option<String>>;
+    fn save(&self, state: &str) -> Result<()>;
+}
+
+/// Keeps the wallet in a JSON file.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct FileStateStore {
+    path: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStateStore {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WalletStateStore for FileStateStore {
+    fn load(&self) -> Result<Option<String>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(EscrowError::Storage(e.to_string())),
+        }
+    }
+
+    fn save(&self, state: &str) -> Result<()> {
+        // write to a temporary file first, so a crash can't leave a truncated wallet behind
+        let tmp_path = self.path.with_extension("tmp");
+        write_private_file(&tmp_path, state)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path))
+            .map_err(|e| EscrowError::Storage(e.to_string()))
+    }
+}
+
+/// Writes the file readable by its owner only, the wallet may contain the mnemonic.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_private_file(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // the mode only applies to new files, a leftover file keeps its permissions
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()
+}
+
+/// Secrets of a [`ClientEcashWallet`] which are persisted along with its database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct WalletSecrets {
+    pub mint_url: String,
+    /// Wallets saved before other units were supported hold sat.
+    #[serde(default)]
+    pub unit: CurrencyUnit,
+    /// Not saved if the mnemonic is kept in a [`crate::keystore::Keystore`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+}
+
+/// The trade keys handed out by a [`ClientEcashWallet`] with their derivation index.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(super) struct TradeKeys {
+    pub next_index: u32,
+    pub indices: HashMap<PublicKey, u32>,
+}
+
+impl TradeKeys {
+    pub fn record(&mut self, pubkey: PublicKey, index: u32) {
+        self.indices.insert(pubkey, index);
+        self.next_index = self.next_index.max(index + 1);
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct WalletState {
+    mints: HashMap<MintUrl, Option<MintInfo>>,
+    mint_keysets: HashMap<MintUrl, HashSet<Id>>,
+    keysets: HashMap<Id, KeySetInfo>,
+    mint_quotes: HashMap<String, MintQuote>,
+    melt_quotes: HashMap<String, MeltQuote>,
+    mint_keys: HashMap<Id, Keys>,
+    proofs: HashMap<PublicKey, ProofInfo>,
+    keyset_counter: HashMap<Id, u32>,
+    nostr_last_checked: HashMap<PublicKey, u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredWallet {
+    secrets: WalletSecrets,
+    #[serde(default)]
+    trade_keys: TradeKeys,
+    state: WalletState,
+}
+
+/// Wallet database which keeps its state in memory and saves it to a [`WalletStateStore`]
+/// after every change.
+pub struct PersistentWalletDatabase {
+    store: Arc<dyn WalletStateStore>,
+    wallet: Mutex<StoredWallet>,
+}
+
+impl std::fmt::Debug for PersistentWalletDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PersistentWalletDatabase").finish()
+    }
+}
+
+impl PersistentWalletDatabase {
+    /// Initializes the store with an empty wallet, fails if it already contains one.
+    pub(super) fn create(store: Arc<dyn WalletStateStore>, secrets: WalletSecrets) -> Result<Self> {
+        if store.load()?.is_some() {
+            return Err(EscrowError::Storage(
+                "The store already contains a wallet".to_string(),
+            ));
+        }
+        let database = Self {
+            store,
+            wallet: Mutex::new(StoredWallet {
+                secrets,
+                trade_keys: TradeKeys::default(),
+                state: WalletState::default(),
+            }),
+        };
+        database.save(&*database.lock()?)?;
+        Ok(database)
+    }
+
+    pub(super) fn open(store: Arc<dyn WalletStateStore>) -> Result<Self> {
+        let serialized = store.load()?.ok_or(EscrowError::Storage(
+            "No wallet found in the store".to_string(),
+        ))?;
+        let wallet: StoredWallet = serde_json::from_str(&serialized)
+            .map_err(|e| EscrowError::Storage(format!("Corrupt wallet: {}", e)))?;
+        Ok(Self {
+            store,
+            wallet: Mutex::new(wallet),
+        })
+    }
+
+    pub(super) fn secrets(&self) -> Result<WalletSecrets> {
+        Ok(self.lock()?.secrets.clone())
+    }
+
+    pub(super) fn remove_mnemonic(&self) -> Result<()> {
+        self.update(|wallet| wallet.secrets.mnemonic = None)
+    }
+
+    pub(super) fn trade_keys(&self) -> Result<TradeKeys> {
+        Ok(self.lock()?.trade_keys.clone())
+    }
+
+    pub(super) fn set_trade_keys(&self, trade_keys: TradeKeys) -> Result<()> {
+        self.update(|wallet| wallet.trade_keys = trade_keys)
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, StoredWallet>> {
+        self.wallet
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))
+    }
+
+    fn save(&self, wallet: &StoredWallet) -> Result<()> {
+        self.store.save(&serde_json::to_string(wallet)?)
+    }
+
+    /// Saves the changed wallet and only keeps the change in memory if that succeeded,
+    /// so memory and store don't diverge.
+    fn update(&self, f: impl FnOnce(&mut StoredWallet)) -> Result<()> {
+        let mut wallet = self.lock()?;
+        let mut updated = wallet.clone();
+        f(&mut updated);
+        self.save(&updated)?;
+        *wallet = updated;
+        Ok(())
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&WalletState) -> T) -> Result<T, cdk_database::Error> {
+        let wallet = self.lock().map_err(database_error)?;
+        Ok(f(&wallet.state))
+    }
+
+    /// Applies the change to the state and saves the wallet, see [`Self::update`].
+    fn write(&self, f: impl FnOnce(&mut WalletState)) -> Result<(), cdk_database::Error> {
+        self.update(|wallet| f(&mut wallet.state))
+            .map_err(database_error)
+    }
+
+    fn set_proof_states(
+        &self,
+        ys: Vec<PublicKey>,
+        state: State,
+    ) -> Result<(), cdk_database::Error> {
+        self.write(|wallet| {
+            for y in ys {
+                if let Some(proof_info) = wallet.proofs.get_mut(&y) {
+                    proof_info.state = state;
+                }
+            }
+        })
+    }
+}
+
+fn database_error(e: EscrowError) -> cdk_database::Error {
+    cdk_database::Error::Database(Box::new(e))
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl WalletDatabase for PersistentWalletDatabase {
+    type Err = cdk_database::Error;
+
+    async fn add_mint(
+        &self,
+        mint_url: MintUrl,
+        mint_info: Option<MintInfo>,
+    ) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.mints.insert(mint_url, mint_info);
+        })
+    }
+
+    async fn remove_mint(&self, mint_url: MintUrl) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.mints.remove(&mint_url);
+        })
+    }
+
+    async fn get_mint(&self, mint_url: MintUrl) -> Result<Option<MintInfo>, Self::Err> {
+        self.read(|wallet| wallet.mints.get(&mint_url).cloned().flatten())
+    }
+
+    async fn get_mints(&self) -> Result<HashMap<MintUrl, Option<MintInfo>>, Self::Err> {
+        self.read(|wallet| wallet.mints.clone())
+    }
+
+    async fn update_mint_url(
+        &self,
+        old_mint_url: MintUrl,
+        new_mint_url: MintUrl,
+    ) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            for proof_info in wallet.proofs.values_mut() {
+                if proof_info.mint_url == old_mint_url {
+                    proof_info.mint_url = new_mint_url.clone();
+                }
+            }
+            for quote in wallet.mint_quotes.values_mut() {
+                if quote.mint_url == old_mint_url {
+                    quote.mint_url = new_mint_url.clone();
+                }
+            }
+        })
+    }
+
+    async fn add_mint_keysets(
+        &self,
+        mint_url: MintUrl,
+        keysets: Vec<KeySetInfo>,
+    ) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            for keyset in keysets {
+                wallet
+                    .mint_keysets
+                    .entry(mint_url.clone())
+                    .or_default()
+                    .insert(keyset.id);
+                wallet.keysets.insert(keyset.id, keyset);
+            }
+        })
+    }
+
+    async fn get_mint_keysets(
+        &self,
+        mint_url: MintUrl,
+    ) -> Result<Option<Vec<KeySetInfo>>, Self::Err> {
+        self.read(|wallet| {
+            wallet.mint_keysets.get(&mint_url).map(|keyset_ids| {
+                keyset_ids
+                    .iter()
+                    .filter_map(|id| wallet.keysets.get(id).cloned())
+                    .collect()
+            })
+        })
+    }
+
+    async fn get_keyset_by_id(&self, keyset_id: &Id) -> Result<Option<KeySetInfo>, Self::Err> {
+        self.read(|wallet| wallet.keysets.get(keyset_id).cloned())
+    }
+
+    async fn add_mint_quote(&self, quote: MintQuote) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.mint_quotes.insert(quote.id.clone(), quote);
+        })
+    }
+
+    async fn get_mint_quote(&self, quote_id: &str) -> Result<Option<MintQuote>, Self::Err> {
+        self.read(|wallet| wallet.mint_quotes.get(quote_id).cloned())
+    }
+
+    async fn get_mint_quotes(&self) -> Result<Vec<MintQuote>, Self::Err> {
+        self.read(|wallet| wallet.mint_quotes.values().cloned().collect())
+    }
+
+    async fn remove_mint_quote(&self, quote_id: &str) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.mint_quotes.remove(quote_id);
+        })
+    }
+
+    async fn add_melt_quote(&self, quote: MeltQuote) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.melt_quotes.insert(quote.id.clone(), quote);
+        })
+    }
+
+    async fn get_melt_quote(&self, quote_id: &str) -> Result<Option<MeltQuote>, Self::Err> {
+        self.read(|wallet| wallet.melt_quotes.get(quote_id).cloned())
+    }
+
+    async fn remove_melt_quote(&self, quote_id: &str) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.melt_quotes.remove(quote_id);
+        })
+    }
+
+    async fn add_keys(&self, keys: Keys) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.mint_keys.insert(Id::from(&keys), keys);
+        })
+    }
+
+    async fn get_keys(&self, id: &Id) -> Result<Option<Keys>, Self::Err> {
+        self.read(|wallet| wallet.mint_keys.get(id).cloned())
+    }
+
+    async fn remove_keys(&self, id: &Id) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet.mint_keys.remove(id);
+        })
+    }
+
+    async fn update_proofs(
+        &self,
+        added: Vec<ProofInfo>,
+        removed_ys: Vec<PublicKey>,
+    ) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            for proof_info in added {
+                wallet.proofs.insert(proof_info.y, proof_info);
+            }
+            for y in removed_ys {
+                wallet.proofs.remove(&y);
+            }
+        })
+    }
+
+    async fn set_pending_proofs(&self, ys: Vec<PublicKey>) -> Result<(), Self::Err> {
+        self.set_proof_states(ys, State::Pending)
+    }
+
+    async fn reserve_proofs(&self, ys: Vec<PublicKey>) -> Result<(), Self::Err> {
+        self.set_proof_states(ys, State::Reserved)
+    }
+
+    async fn set_unspent_proofs(&self, ys: Vec<PublicKey>) -> Result<(), Self::Err> {
+        self.set_proof_states(ys, State::Unspent)
+    }
+
+    async fn get_proofs(
+        &self,
+        mint_url: Option<MintUrl>,
+        unit: Option<CurrencyUnit>,
+        state: Option<Vec<State>>,
+        spending_conditions: Option<Vec<SpendingConditions>>,
+    ) -> Result<Vec<ProofInfo>, Self::Err> {
+        self.read(|wallet| {
+            wallet
+                .proofs
+                .values()
+                .filter(|proof_info| {
+                    proof_info.matches_conditions(&mint_url, &unit, &state, &spending_conditions)
+                })
+                .cloned()
+                .collect()
+        })
+    }
+
+    async fn increment_keyset_counter(&self, keyset_id: &Id, count: u32) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            *wallet.keyset_counter.entry(*keyset_id).or_default() += count;
+        })
+    }
+
+    async fn get_keyset_counter(&self, keyset_id: &Id) -> Result<Option<u32>, Self::Err> {
+        self.read(|wallet| wallet.keyset_counter.get(keyset_id).cloned())
+    }
+
+    async fn get_nostr_last_checked(
+        &self,
+        verifying_key: &PublicKey,
+    ) -> Result<Option<u32>, Self::Err> {
+        self.read(|wallet| wallet.nostr_last_checked.get(verifying_key).cloned())
+    }
+
+    async fn add_nostr_last_checked(
+        &self,
+        verifying_key: PublicKey,
+        last_checked: u32,
+    ) -> Result<(), Self::Err> {
+        self.write(|wallet| {
+            wallet
+                .nostr_last_checked
+                .insert(verifying_key, last_checked);
+        })
+    }
+}
@@ -0,0 +1,257 @@
+use super::*;
+
+use cashu_escrow_common::model::FeePayer;
+use cdk::{
+    mint_url::MintUrl,
+    nuts::{Id, Proofs, State},
+};
+use std::fmt;
+
+/// Outcome of a single check of the escrow token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckResult {
+    Passed,
+    Failed(String),
+}
+
+impl CheckResult {
+    pub fn passed(&self) -> bool {
+        *self == CheckResult::Passed
+    }
+
+    fn from_condition(condition: bool, failure: impl FnOnce() -> String) -> Self {
+        if condition {
+            CheckResult::Passed
+        } else {
+            CheckResult::Failed(failure())
+        }
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckResult::Passed => write!(f, "ok"),
+            CheckResult::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// Result of the validation of an escrow token against the trade contract.
+///
+/// The seller should only deliver if [`EscrowTokenReport::is_valid`] holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscrowTokenReport {
+    /// The proofs are locked to the spending conditions of the trade (NUT-11).
+    pub spending_conditions: CheckResult,
+    /// The token value equals the trade amount, plus the redemption fee if the buyer pays it.
+    pub amount: CheckResult,
+    /// All proofs were issued by the agreed mint.
+    pub mint: CheckResult,
+    /// The token is denominated in the agreed unit.
+    pub unit: CheckResult,
+    /// All proofs come from the keysets allowed by the contract.
+    pub keysets: CheckResult,
+    /// The mint reports all proofs as unspent (NUT-07).
+    pub unspent: CheckResult,
+    /// The DLEQ proofs of the mint signatures are valid (NUT-12).
+    pub dleq: CheckResult,
+}
+
+impl EscrowTokenReport {
+    pub fn is_valid(&self) -> bool {
+        self.checks().iter().all(|(_, result)| result.passed())
+    }
+
+    pub fn checks(&self) -> [(&'static str, &CheckResult); 7] {
+        [
+            ("spending conditions", &self.spending_conditions),
+            ("amount", &self.amount),
+            ("mint", &self.mint),
+            ("unit", &self.unit),
+            ("keysets", &self.keysets),
+            ("unspent", &self.unspent),
+            ("dleq", &self.dleq),
+        ]
+    }
+}
+
+impl fmt::Display for EscrowTokenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let checks: Vec<String> = self
+            .checks()
+            .iter()
+            .map(|(name, result)| format!("{}: {}", name, result))
+            .collect();
+        write!(f, "{}", checks.join(", "))
+    }
+}
+
+impl EscrowTokenReport {
+    /// Checks the token against the contract without asking the mint.
+    ///
+    /// `redemption_fee` is the fee of redeeming the token at the agreed mint, the token has to
+    /// cover it if the buyer pays it. The state and the DLEQ proofs are reported as unchecked,
+    /// see [`ClientEcashWallet::validate_escrow_token`].
+    pub fn check_terms(
+        escrow_token: &Token,
+        contract: &TradeContract,
+        escrow_registration: &EscrowRegistration,
+        redemption_fee: Option<Amount>,
+    ) -> Self {
+        let token_proofs = escrow_token.proofs();
+
+        // compared as a whole, the signature flag included
+        let spending_conditions =
+            match ClientEcashWallet::assemble_escrow_conditions(contract, escrow_registration) {
+                Ok(conditions) => {
+                    let foreign_conditions = token_proofs
+                        .values()
+                        .flatten()
+                        .filter(|proof| {
+                            SpendingConditions::try_from(&proof.secret).ok().as_ref()
+                                != Some(&conditions)
+                        })
+                        .count();
+                    CheckResult::from_condition(foreign_conditions == 0, || {
+                        format!(
+                            "{} proofs are not locked to the conditions of the trade",
+                            foreign_conditions
+                        )
+                    })
+                }
+                Err(e) => CheckResult::Failed(e.to_string()),
+            };
+
+        let foreign_mints: Vec<&MintUrl> = token_proofs
+            .keys()
+            .filter(|mint_url| **mint_url != contract.mint_url)
+            .collect();
+        let mint = CheckResult::from_condition(foreign_mints.is_empty(), || {
+            format!(
+                "proofs from {:?} instead of {}",
+                foreign_mints, contract.mint_url
+            )
+        });
+
+        let trade_amount = Amount::from(contract.trade_amount);
+        let amount = match (escrow_token.value(), contract.fee_payer, redemption_fee) {
+            (Ok(value), FeePayer::Seller, _) => {
+                CheckResult::from_condition(value == trade_amount, || {
+                    format!("token value {} instead of {}", value, trade_amount)
+                })
+            }
+            (Ok(value), FeePayer::Buyer, Some(redemption_fee)) => {
+                match trade_amount.checked_add(redemption_fee) {
+                    Some(required) => CheckResult::from_condition(value >= required, || {
+                        format!(
+                            "token value {} doesn't cover {} and the redemption fee of {}",
+                            value, trade_amount, redemption_fee
+                        )
+                    }),
+                    None => CheckResult::Failed(format!(
+                        "{} and the redemption fee of {} exceed the maximum amount",
+                        trade_amount, redemption_fee
+                    )),
+                }
+            }
+            (Ok(_), FeePayer::Buyer, None) => {
+                CheckResult::Failed("fees can only be checked at the agreed mint".to_string())
+            }
+            (Err(e), _, _) => CheckResult::Failed(e.to_string()),
+        };
+
+        let token_unit = (*escrow_token.unit()).unwrap_or_default();
+        let unit = CheckResult::from_condition(token_unit == contract.currency_unit, || {
+            format!("{} instead of {}", token_unit, contract.currency_unit)
+        });
+
+        let keysets = match &contract.allowed_keysets {
+            Some(allowed_keysets) => {
+                let foreign_keysets: HashSet<Id> = token_proofs
+                    .values()
+                    .flatten()
+                    .map(|proof| proof.keyset_id)
+                    .filter(|keyset_id| !allowed_keysets.contains(keyset_id))
+                    .collect();
+                CheckResult::from_condition(foreign_keysets.is_empty(), || {
+                    format!(
+                        "proofs from keysets {:?} which are not allowed",
+                        foreign_keysets
+                    )
+                })
+            }
+            None => CheckResult::Passed,
+        };
+
+        EscrowTokenReport {
+            spending_conditions,
+            amount,
+            mint,
+            unit,
+            keysets,
+            unspent: CheckResult::Failed(UNCHECKED.to_string()),
+            dleq: CheckResult::Failed(UNCHECKED.to_string()),
+        }
+    }
+}
+
+/// Reason of the checks [`EscrowTokenReport::check_terms`] leaves to the mint.
+const UNCHECKED: &str = "not checked with the mint";
+
+impl ClientEcashWallet {
+    /// Checks the escrow token against the contract and asks the mint for the state of its proofs.
+    ///
+    /// Failed checks are part of the report, errors are only returned if the mint can't be reached
+    /// or the wallet doesn't use the mint agreed in the contract.
+    pub async fn validate_escrow_token(
+        &self,
+        escrow_token: &Token,
+        contract: &TradeContract,
+        escrow_registration: &EscrowRegistration,
+    ) -> Result<EscrowTokenReport> {
+        self.check_contract_mint(contract)?;
+
+        let token_proofs = escrow_token.proofs();
+        let at_agreed_mint = token_proofs
+            .keys()
+            .all(|mint_url| *mint_url == contract.mint_url);
+        // the fees of the keysets are only known for the agreed mint
+        let redemption_fee = match contract.fee_payer {
+            FeePayer::Buyer if at_agreed_mint => {
+                self.wallet.get_mint_keysets().await?;
+                let proofs: Proofs = token_proofs.values().flatten().cloned().collect();
+                Some(self.wallet.get_proofs_fee(&proofs).await?)
+            }
+            _ => None,
+        };
+        let mut report = EscrowTokenReport::check_terms(
+            escrow_token,
+            contract,
+            escrow_registration,
+            redemption_fee,
+        );
+
+        report.unspent = if at_agreed_mint {
+            let proofs = token_proofs.into_values().flatten().collect();
+            let states = self.wallet.check_proofs_spent(proofs).await?;
+            let not_unspent = states
+                .iter()
+                .filter(|proof_state| proof_state.state != State::Unspent)
+                .count();
+            CheckResult::from_condition(not_unspent == 0, || {
+                format!("{} of {} proofs are not unspent", not_unspent, states.len())
+            })
+        } else {
+            CheckResult::Failed("state can only be checked at the agreed mint".to_string())
+        };
+
+        report.dleq = match self.wallet.verify_token_dleq(escrow_token).await {
+            Ok(()) => CheckResult::Passed,
+            Err(e) => CheckResult::Failed(e.to_string()),
+        };
+
+        debug!("Escrow token validation: {}", report);
+        Ok(report)
+    }
+}
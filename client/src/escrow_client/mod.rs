@@ -1,13 +1,39 @@
+pub mod negotiation;
+
 use super::*;
 
 use cashu_escrow_common::{
-    model::{EscrowRegistration, TradeContract},
-    nostr::NostrClient,
+    evidence::{Attachment, EvidenceSubmission},
+    model::{
+        ContractRejection, ContractSignatures, DisputeClaim, DisputeFailure, DisputeVerdict,
+        EcashPubkeyAnnouncement, EscrowRegistration, EscrowRelease, SignedTradeContract,
+        TradeContract, Verdict,
+    },
+    nostr::{chat::DisputeChat, NostrClient},
+    payment_request::{PaymentRequest, PaymentRequestPayload, Transport, TransportKind},
+    token::encode_token,
+};
+use cdk::{
+    nuts::{Id, PublicKey as EcashPubkey, Token},
+    secret::Secret,
+    Amount,
+};
+use ecash::{
+    payout::{LightningDestination, Settlement},
+    ClientEcashWallet,
 };
-use cdk::nuts::Token;
-use ecash::ClientEcashWallet;
+use negotiation::{ContractNegotiation, NegotiationAnswer};
+use nostr_sdk::{
+    nips::nip19::{FromBech32, Nip19Profile, ToBech32},
+    secp256k1::schnorr::Signature,
+    util::hex,
+    Timestamp,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum TradeMode {
     Buyer,
     Seller,
@@ -18,6 +44,8 @@ pub struct InitEscrowClient {
     ecash_wallet: ClientEcashWallet,
     escrow_contract: TradeContract,
     trade_mode: TradeMode,
+    /// Signatures of both traders over the contract, submitted with it to the coordinator.
+    contract_signatures: Option<ContractSignatures>,
 }
 
 /// Initial Escrow Client state.
@@ -33,25 +61,224 @@ impl InitEscrowClient {
             ecash_wallet,
             escrow_contract,
             trade_mode,
+            contract_signatures: None,
+        }
+    }
+
+    pub fn escrow_contract(&self) -> &TradeContract {
+        &self.escrow_contract
+    }
+
+    pub fn contract_signatures(&self) -> Option<&ContractSignatures> {
+        self.contract_signatures.as_ref()
+    }
+
+    /// Sets the signatures of a contract the traders agreed on before, e.g. from persisted state.
+    pub fn set_contract_signatures(&mut self, signatures: ContractSignatures) -> Result<()> {
+        signatures.verify(&self.escrow_contract)?;
+        self.contract_signatures = Some(signatures);
+        Ok(())
+    }
+
+    fn partner_npub(&self) -> nostr_sdk::PublicKey {
+        match self.trade_mode {
+            TradeMode::Buyer => self.escrow_contract.npubkey_seller,
+            TradeMode::Seller => self.escrow_contract.npubkey_buyer,
         }
     }
 
+    /// Takes the contract both traders signed, assigns our and the partner's signature to
+    /// buyer and seller.
+    fn agree_on_contract(
+        &mut self,
+        contract: TradeContract,
+        own: Signature,
+        partner: Signature,
+    ) -> ContractSignatures {
+        let signatures = match self.trade_mode {
+            TradeMode::Buyer => ContractSignatures {
+                buyer: own,
+                seller: partner,
+            },
+            TradeMode::Seller => ContractSignatures {
+                buyer: partner,
+                seller: own,
+            },
+        };
+        self.escrow_contract = contract;
+        self.contract_signatures = Some(signatures.clone());
+        signatures
+    }
+
+    /// Signs the contract together with the trade partner, without negotiating its terms.
+    ///
+    /// The buyer offers the contract, the seller accepts it if it is the same as its own.
+    /// Both traders have to run it at about the same time, after
+    /// [`InitEscrowClient::exchange_ecash_pubkeys`].
+    pub async fn exchange_contract_signatures(
+        &mut self,
+        timeout_secs: u64,
+    ) -> Result<ContractSignatures> {
+        match self.trade_mode {
+            TradeMode::Buyer => {
+                let mut negotiation = ContractNegotiation::offer(self).await?;
+                match negotiation.receive_answer(self, timeout_secs).await? {
+                    NegotiationAnswer::Accepted(signatures) => Ok(signatures),
+                    NegotiationAnswer::Countered => {
+                        negotiation
+                            .reject(self, "The contract is not negotiable".to_string())
+                            .await?;
+                        Err(EscrowError::Protocol(
+                            "The seller proposed other terms".to_string(),
+                        ))
+                    }
+                    NegotiationAnswer::Rejected(reason) => Err(EscrowError::Protocol(format!(
+                        "The seller didn't sign the contract: {}",
+                        reason
+                    ))),
+                }
+            }
+            TradeMode::Seller => {
+                let negotiation = ContractNegotiation::receive_offer(self, timeout_secs).await?;
+                // in privacy mode the buyer chooses the salt of the commitment
+                let mut own_contract = self.escrow_contract.clone();
+                own_contract.adopt_salt(negotiation.proposal());
+                if *negotiation.proposal() != own_contract {
+                    negotiation
+                        .reject(self, "The contract differs from ours".to_string())
+                        .await?;
+                    return Err(EscrowError::Validation(
+                        "The buyer offered a contract with other terms".to_string(),
+                    ));
+                }
+                negotiation.accept(self).await
+            }
+        }
+    }
+
+    /// Pre-registration handshake, hands over the trade pubkeys of buyer and seller.
+    ///
+    /// Sends our trade pubkey, signed by our nostr key, to the trade partner and puts the
+    /// partner's verified pubkey into the contract. Both traders have to run it at about
+    /// the same time. Each answers the announcements of the other and only takes the answer
+    /// to the nonce of its own announcement, so announcements of earlier handshakes are ignored.
+    ///
+    /// Returns the trade pubkey of the partner.
+    pub async fn exchange_ecash_pubkeys(&mut self, timeout_secs: u64) -> Result<EcashPubkey> {
+        let contract = &self.escrow_contract;
+        let (own_ecash_pubkey, partner_ecash_pubkey, partner_npub) = match self.trade_mode {
+            TradeMode::Buyer => (
+                &contract.buyer_ecash_public_key,
+                &contract.seller_ecash_public_key,
+                contract.npubkey_seller,
+            ),
+            TradeMode::Seller => (
+                &contract.seller_ecash_public_key,
+                &contract.buyer_ecash_public_key,
+                contract.npubkey_buyer,
+            ),
+        };
+        let own_ecash_pubkey = EcashPubkey::from_str(own_ecash_pubkey)?;
+        let known_partner_pubkey = EcashPubkey::from_str(partner_ecash_pubkey).ok();
+        let nonce = Secret::generate().to_string();
+        self.nostr_client
+            .announce_ecash_pubkey(partner_npub, own_ecash_pubkey, &nonce, None)
+            .await?;
+
+        let mut answered = HashSet::new();
+        let announcement = loop {
+            let announcement: EcashPubkeyAnnouncement = self
+                .nostr_client
+                .receive_escrow_message(timeout_secs)
+                .await?;
+            if announcement.nostr_pubkey != partner_npub
+                || announcement.recipient != self.nostr_client.public_key()
+            {
+                debug!("Ignoring the announced trade pubkey of another trade");
+                continue;
+            }
+            if let Err(e) = announcement.verify() {
+                warn!("Ignoring an announced trade pubkey: {}", e);
+                continue;
+            }
+            // the partner may have started before or after us, so each of its nonces is answered
+            if answered.insert(announcement.nonce.clone()) {
+                self.nostr_client
+                    .announce_ecash_pubkey(
+                        partner_npub,
+                        own_ecash_pubkey,
+                        &nonce,
+                        Some(&announcement.nonce),
+                    )
+                    .await?;
+            }
+            if announcement.reply_to.as_deref() == Some(nonce.as_str()) {
+                break announcement;
+            }
+            debug!("Waiting for the partner's answer to our announcement");
+        };
+        if known_partner_pubkey.is_some_and(|known| known != announcement.ecash_pubkey) {
+            return Err(EscrowError::Validation(format!(
+                "The partner announced the trade pubkey {}, the contract holds {}",
+                announcement.ecash_pubkey, partner_ecash_pubkey
+            )));
+        }
+
+        let partner_ecash_pubkey = announcement.ecash_pubkey.to_string();
+        match self.trade_mode {
+            TradeMode::Buyer => self.escrow_contract.seller_ecash_public_key = partner_ecash_pubkey,
+            TradeMode::Seller => self.escrow_contract.buyer_ecash_public_key = partner_ecash_pubkey,
+        }
+        Ok(announcement.ecash_pubkey)
+    }
+
     /// The trade initialization is the same for both buyer and seller.
     ///
     /// After this the coordinator data is set, state trade registered.
     ///
     /// After this state the trade contract is effectfull as well, possible coordinator fees must be payed.
-    pub async fn register_trade(mut self) -> anyhow::Result<RegisteredEscrowClient> {
+    ///
+    /// Fails with the reason the coordinator gives if it rejects the contract.
+    pub async fn register_trade(mut self) -> Result<RegisteredEscrowClient> {
         let coordinator_pk = &self.escrow_contract.npubkey_coordinator;
-        let contract_message = serde_json::to_string(&self.escrow_contract)?;
+        let contract_hash = hex::encode(self.escrow_contract.contract_hash()?);
+        // a signed contract starts the trade on its own, otherwise both traders have to submit it
+        let contract_message = match &self.contract_signatures {
+            Some(signatures) => serde_json::to_string(&SignedTradeContract::new(
+                self.escrow_contract.coordinator_contract()?,
+                signatures.clone(),
+            )?)?,
+            None => self.escrow_contract.canonical_json()?,
+        };
         debug!("sending contract to coordinator...");
         self.nostr_client
             .client
             .send_private_msg(*coordinator_pk, &contract_message, None)
             .await?;
 
-        let escrow_registration: EscrowRegistration =
-            self.nostr_client.receive_escrow_message(20).await?;
+        let escrow_registration = loop {
+            let answer: RegistrationAnswer = self
+                .nostr_client
+                .receive_escrow_message_from(*coordinator_pk, 20)
+                .await?;
+            // the escrow id is the hash of the contract, answers for our other trades are skipped
+            match answer {
+                RegistrationAnswer::Registration(registration)
+                    if registration.escrow_id_hex == contract_hash =>
+                {
+                    break registration
+                }
+                RegistrationAnswer::Rejection(rejection)
+                    if rejection.contract_hash == contract_hash =>
+                {
+                    return Err(EscrowError::Protocol(format!(
+                        "The coordinator rejected the contract: {}",
+                        rejection.reason
+                    )));
+                }
+                _ => debug!("Skipping the answer for another contract"),
+            }
+        };
         debug!(
             "Received registration: {}",
             &escrow_registration.escrow_id_hex
@@ -75,38 +302,42 @@ pub struct RegisteredEscrowClient {
 }
 
 impl RegisteredEscrowClient {
+    /// Resumes a registered trade, e.g. from persisted state.
+    pub fn new(
+        nostr_client: NostrClient,
+        ecash_wallet: ClientEcashWallet,
+        escrow_contract: TradeContract,
+        trade_mode: TradeMode,
+        escrow_registration: EscrowRegistration,
+    ) -> Self {
+        Self {
+            nostr_client,
+            ecash_wallet,
+            escrow_contract,
+            trade_mode,
+            escrow_registration,
+        }
+    }
+
+    pub fn escrow_registration(&self) -> &EscrowRegistration {
+        &self.escrow_registration
+    }
+
     /// Depending on the trade mode sends or receives the trade token.
     ///
     /// After this the state is token sent or received.
-    pub async fn exchange_trade_token(mut self) -> anyhow::Result<TokenExchangedEscrowClient> {
-        match self.trade_mode {
-            TradeMode::Buyer => {
-                // todo: store the sent token in next instance
-                self.send_trade_token().await?;
-                Ok(TokenExchangedEscrowClient {
-                    _nostr_client: self.nostr_client,
-                    _ecash_wallet: self.ecash_wallet,
-                    _escrow_contract: self.escrow_contract,
-                    trade_mode: self.trade_mode,
-                })
-            }
-            TradeMode::Seller => {
-                // todo: store the received token in next instance
-                self.receive_and_validate_trade_token().await?;
-                Ok(TokenExchangedEscrowClient {
-                    _nostr_client: self.nostr_client,
-                    _ecash_wallet: self.ecash_wallet,
-                    _escrow_contract: self.escrow_contract,
-                    trade_mode: self.trade_mode,
-                })
-            }
-        }
+    pub async fn exchange_trade_token(mut self) -> Result<TokenExchangedEscrowClient> {
+        let escrow_token = match self.trade_mode {
+            TradeMode::Buyer => self.send_trade_token().await?,
+            TradeMode::Seller => self.receive_and_validate_trade_token().await?,
+        };
+        Ok(TokenExchangedEscrowClient::new(self, escrow_token))
     }
 
     /// State change for the buyer. The state after that is token sent.
     ///
     /// Returns the sent trade token by this [`EscrowClient`].
-    async fn send_trade_token(&self) -> anyhow::Result<Token> {
+    async fn send_trade_token(&self) -> Result<Token> {
         let escrow_contract = &self.escrow_contract;
         let escrow_token = self
             .ecash_wallet
@@ -119,7 +350,7 @@ impl RegisteredEscrowClient {
             .client
             .send_private_msg(
                 escrow_contract.npubkey_seller,
-                &serde_json::to_string(&escrow_token)?,
+                &encode_token(&escrow_token),
                 None,
             )
             .await?;
@@ -131,29 +362,228 @@ impl RegisteredEscrowClient {
     /// State change for a seller. The state after this is token received.
     ///
     /// Returns the received trade token by this [`EscrowClient`].
-    async fn receive_and_validate_trade_token(&mut self) -> anyhow::Result<Token> {
+    async fn receive_and_validate_trade_token(&mut self) -> Result<Token> {
         let escrow_contract = &self.escrow_contract;
         let wallet = &self.ecash_wallet;
 
-        let escrow_token = self.nostr_client.receive_escrow_message(20).await?;
+        let escrow_token = self.nostr_client.receive_escrow_token(20).await?;
         trace!("Received Token, validating it...");
-        wallet.validate_escrow_token(&escrow_token, escrow_contract, &self.escrow_registration)?;
+        let report = wallet
+            .validate_escrow_token(&escrow_token, escrow_contract, &self.escrow_registration)
+            .await?;
+        if !report.is_valid() {
+            return Err(EscrowError::Validation(format!(
+                "Invalid escrow token: {}",
+                report
+            )));
+        }
         Ok(escrow_token)
     }
 }
 
+/// Escrow funding with payment requests (NUT-18), e.g. to let another cashu wallet fund the escrow.
+impl RegisteredEscrowClient {
+    /// Creates the payment request for the escrow token, delivered to the seller over nostr.
+    pub async fn payment_request(&self) -> Result<PaymentRequest> {
+        self.require_mode(TradeMode::Seller, "request the escrow payment")?;
+        let contract = &self.escrow_contract;
+        let profile = Nip19Profile {
+            public_key: self.nostr_client.public_key(),
+            relays: self
+                .nostr_client
+                .client
+                .relays()
+                .await
+                .into_keys()
+                .collect(),
+        };
+        let nprofile = profile
+            .to_bech32()
+            .map_err(|e| EscrowError::Validation(e.to_string()))?;
+        let conditions =
+            ClientEcashWallet::assemble_escrow_conditions(contract, &self.escrow_registration)?;
+        Ok(PaymentRequest {
+            payment_id: Some(self.escrow_registration.escrow_id_hex.clone()),
+            amount: Some(self.ecash_wallet.escrow_amount(contract).await?),
+            unit: Some(contract.currency_unit),
+            single_use: Some(true),
+            mints: Some(vec![contract.mint_url.clone()]),
+            description: Some(contract.trade_description.clone()),
+            transports: vec![Transport::nostr(nprofile)],
+            nut10: Some(conditions.into()),
+        })
+    }
+
+    /// Pays a payment request with the escrow token, after checking it matches the contract.
+    ///
+    /// The token is delivered over the first transport of the request, without transports
+    /// it has to be handed over out of band. Returns the escrow token.
+    pub async fn pay_payment_request(&self, request: &PaymentRequest) -> Result<Token> {
+        self.require_mode(TradeMode::Buyer, "pay the escrow")?;
+        self.check_payment_request(request).await?;
+        let escrow_token = self
+            .ecash_wallet
+            .create_escrow_token(&self.escrow_contract, &self.escrow_registration)
+            .await?;
+        let (mint, proofs) =
+            escrow_token
+                .proofs()
+                .into_iter()
+                .next()
+                .ok_or(EscrowError::Validation(
+                    "Escrow token contains no proofs".to_string(),
+                ))?;
+        let payload = PaymentRequestPayload {
+            id: request.payment_id.clone(),
+            memo: escrow_token.memo().clone(),
+            mint,
+            unit: self.escrow_contract.currency_unit,
+            proofs,
+        };
+        match request.transports.first() {
+            Some(transport) => self.deliver_payment(transport, &payload).await?,
+            None => debug!("Payment request without transport, deliver the token out of band"),
+        }
+        Ok(escrow_token)
+    }
+
+    /// Sends the payment request to the buyer and waits for the escrow token.
+    ///
+    /// The buyer's counterpart is [`RegisteredEscrowClient::exchange_trade_token_with_payment_request`].
+    pub async fn exchange_trade_token_with_payment_request(
+        mut self,
+    ) -> Result<TokenExchangedEscrowClient> {
+        let escrow_token = match self.trade_mode {
+            TradeMode::Buyer => {
+                let request = self.nostr_client.receive_payment_request(20).await?;
+                self.pay_payment_request(&request).await?
+            }
+            TradeMode::Seller => {
+                let request = self.payment_request().await?;
+                debug!("Sending payment request to the buyer: {}", request);
+                self.nostr_client
+                    .client
+                    .send_private_msg(
+                        self.escrow_contract.npubkey_buyer,
+                        &request.to_string(),
+                        None,
+                    )
+                    .await?;
+                self.receive_and_validate_trade_token().await?
+            }
+        };
+        Ok(TokenExchangedEscrowClient::new(self, escrow_token))
+    }
+
+    async fn check_payment_request(&self, request: &PaymentRequest) -> Result<()> {
+        let contract = &self.escrow_contract;
+        let escrow_amount = self.ecash_wallet.escrow_amount(contract).await?;
+        let conditions =
+            ClientEcashWallet::assemble_escrow_conditions(contract, &self.escrow_registration)?;
+        let mismatch = if request
+            .payment_id
+            .as_ref()
+            .is_some_and(|id| *id != self.escrow_registration.escrow_id_hex)
+        {
+            Some("payment id")
+        } else if request.amount != Some(escrow_amount) {
+            Some("amount")
+        } else if request
+            .unit
+            .is_some_and(|unit| unit != contract.currency_unit)
+        {
+            Some("unit")
+        } else if request
+            .mints
+            .as_ref()
+            .is_some_and(|mints| !mints.contains(&contract.mint_url))
+        {
+            Some("mint")
+        } else if request.spending_conditions()? != Some(conditions) {
+            Some("spending conditions")
+        } else {
+            None
+        };
+        match mismatch {
+            Some(field) => Err(EscrowError::Validation(format!(
+                "The {} of the payment request doesn't match the escrow",
+                field
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    async fn deliver_payment(
+        &self,
+        transport: &Transport,
+        payload: &PaymentRequestPayload,
+    ) -> Result<()> {
+        match transport.kind {
+            TransportKind::Nostr => {
+                let receiver = Nip19Profile::from_bech32(&transport.target)
+                    .map_err(|e| EscrowError::Validation(e.to_string()))?
+                    .public_key;
+                self.nostr_client
+                    .client
+                    .send_private_msg(receiver, &serde_json::to_string(payload)?, None)
+                    .await?;
+            }
+            TransportKind::Post => {
+                reqwest::Client::new()
+                    .post(&transport.target)
+                    .json(payload)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| {
+                        EscrowError::Transport(format!("Delivering the payment failed: {}", e))
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn require_mode(&self, trade_mode: TradeMode, action: &str) -> Result<()> {
+        if self.trade_mode != trade_mode {
+            return Err(EscrowError::Validation(format!(
+                "Only the {:?} can {}",
+                trade_mode, action
+            )));
+        }
+        Ok(())
+    }
+}
+
 pub struct TokenExchangedEscrowClient {
-    _nostr_client: NostrClient,
-    _ecash_wallet: ClientEcashWallet,
-    _escrow_contract: TradeContract,
+    nostr_client: NostrClient,
+    ecash_wallet: ClientEcashWallet,
+    escrow_contract: TradeContract,
     trade_mode: TradeMode,
+    escrow_registration: EscrowRegistration,
+    escrow_token: Token,
 }
 
 impl TokenExchangedEscrowClient {
+    /// Resumes a trade whose escrow token was exchanged, e.g. from persisted state.
+    pub fn new(registered: RegisteredEscrowClient, escrow_token: Token) -> Self {
+        Self {
+            nostr_client: registered.nostr_client,
+            ecash_wallet: registered.ecash_wallet,
+            escrow_contract: registered.escrow_contract,
+            trade_mode: registered.trade_mode,
+            escrow_registration: registered.escrow_registration,
+            escrow_token,
+        }
+    }
+
+    pub fn escrow_token(&self) -> &Token {
+        &self.escrow_token
+    }
+
     /// Depending on the trade mode deliver product/service or sign the token after receiving the service.
     ///
     /// The state after this operation is duties fulfilled.
-    pub async fn do_your_trade_duties(&self) -> anyhow::Result<()> {
+    pub async fn do_your_trade_duties(&self) -> Result<()> {
         // todo: as seller send product and proof of delivery (oracle) to seller.
         // await signature or begin dispute
 
@@ -168,4 +598,240 @@ impl TokenExchangedEscrowClient {
         }
         Ok(())
     }
+
+    /// Releases the escrowed funds to the seller by sending the token signed with the buyer's trade key.
+    pub async fn release_escrow(&self) -> Result<()> {
+        self.require_mode(TradeMode::Buyer, "release the escrow")?;
+        let release = EscrowRelease {
+            escrow_id_hex: self.escrow_registration.escrow_id_hex.clone(),
+            signed_token: self.ecash_wallet.sign_escrow_token(&self.escrow_token)?,
+        };
+        debug!("Sending escrow release to the seller...");
+        self.nostr_client
+            .client
+            .send_private_msg(
+                self.escrow_contract.npubkey_seller,
+                &serde_json::to_string(&release)?,
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Waits for the buyer's release and redeems the escrowed funds.
+    ///
+    /// Returns the redeemed amount.
+    pub async fn receive_release(&mut self, timeout_secs: u64) -> Result<Amount> {
+        let release = self.receive_release_message(timeout_secs).await?;
+        self.ecash_wallet
+            .redeem_escrow_token(&release.signed_token)
+            .await
+    }
+
+    /// Waits for the buyer's release, redeems the escrowed funds and pays them out over Lightning.
+    ///
+    /// A failed payout doesn't fail the settlement, the proceeds then remain in the wallet.
+    pub async fn receive_release_to_lightning(
+        &mut self,
+        timeout_secs: u64,
+        destination: &LightningDestination,
+    ) -> Result<Settlement> {
+        let release = self.receive_release_message(timeout_secs).await?;
+        self.ecash_wallet
+            .settle_to_lightning(&release.signed_token, destination)
+            .await
+    }
+
+    async fn receive_release_message(&mut self, timeout_secs: u64) -> Result<EscrowRelease> {
+        self.require_mode(TradeMode::Seller, "receive the release")?;
+        let release: EscrowRelease = self
+            .nostr_client
+            .receive_escrow_message_from(self.escrow_contract.npubkey_buyer, timeout_secs)
+            .await?;
+        if release.escrow_id_hex != self.escrow_registration.escrow_id_hex {
+            return Err(EscrowError::Protocol(
+                "Received release for another escrow".to_string(),
+            ));
+        }
+        // the signatures are only checked by the mint, the proofs have to be the escrow's
+        if proof_ids(&release.signed_token) != proof_ids(&self.escrow_token) {
+            return Err(EscrowError::Validation(
+                "The released token doesn't hold the proofs of the escrow".to_string(),
+            ));
+        }
+        Ok(release)
+    }
+
+    /// Takes back the escrowed funds after the time limit of the contract expired.
+    ///
+    /// Returns the redeemed amount.
+    pub async fn refund_escrow(&self) -> Result<Amount> {
+        self.require_mode(TradeMode::Buyer, "refund the escrow")?;
+        let locktime =
+            self.escrow_registration.escrow_start_time.as_u64() + self.escrow_contract.time_limit;
+        // the mint only takes the refund signature once the locktime has passed
+        if Timestamp::now().as_u64() <= locktime {
+            return Err(EscrowError::Validation(format!(
+                "The escrow can't be refunded before {}",
+                locktime
+            )));
+        }
+        self.ecash_wallet
+            .redeem_escrow_token(&self.escrow_token)
+            .await
+    }
+
+    fn require_mode(&self, trade_mode: TradeMode, action: &str) -> Result<()> {
+        if self.trade_mode != trade_mode {
+            return Err(EscrowError::Validation(format!(
+                "Only the {:?} can {}",
+                trade_mode, action
+            )));
+        }
+        Ok(())
+    }
+
+    /// Opens a dispute at the coordinator, or responds to a dispute opened by the trade partner.
+    ///
+    /// The escrow token is handed to the coordinator, so it can co-sign the payout of the verdict.
+    /// The private terms of a contract in privacy mode are revealed to the coordinator.
+    pub async fn open_dispute(
+        &self,
+        requested_verdict: Verdict,
+        statement: String,
+        evidence: Vec<String>,
+    ) -> Result<()> {
+        let claim = DisputeClaim {
+            escrow_id_hex: self.escrow_registration.escrow_id_hex.clone(),
+            requested_verdict,
+            statement,
+            evidence,
+            escrow_token: Some(self.escrow_token.clone()),
+            terms_opening: self.escrow_contract.terms_opening(),
+        };
+        debug!("Sending dispute claim to coordinator...");
+        self.nostr_client
+            .client
+            .send_private_msg(
+                self.escrow_contract.npubkey_coordinator,
+                &serde_json::to_string(&claim)?,
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Submits evidence for the open dispute, the attachments are sent in chunks after it.
+    ///
+    /// The coordinator takes evidence until its deadline expired or the case was handed to the
+    /// resolver, which happens once both claims are in. Answer a dispute with the evidence
+    /// first and the claim after it.
+    pub async fn submit_evidence(&self, message: String, attachments: &[Attachment]) -> Result<()> {
+        let escrow_id_hex = &self.escrow_registration.escrow_id_hex;
+        let submission = EvidenceSubmission {
+            escrow_id_hex: escrow_id_hex.clone(),
+            message,
+            attachments: attachments.iter().map(Attachment::header).collect(),
+        };
+        let coordinator = self.escrow_contract.npubkey_coordinator;
+        debug!("Sending evidence to coordinator...");
+        self.nostr_client
+            .client
+            .send_private_msg(coordinator, &serde_json::to_string(&submission)?, None)
+            .await?;
+        for chunk in attachments
+            .iter()
+            .flat_map(|attachment| attachment.chunks(escrow_id_hex))
+        {
+            self.nostr_client
+                .client
+                .send_private_msg(coordinator, &serde_json::to_string(&chunk)?, None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// The chat with the trade partner and the coordinator about the dispute.
+    pub fn dispute_chat(&self) -> DisputeChat {
+        self.nostr_client.dispute_chat(
+            &self.escrow_registration.escrow_id_hex,
+            vec![
+                self.escrow_contract.npubkey_buyer,
+                self.escrow_contract.npubkey_seller,
+                self.escrow_contract.npubkey_coordinator,
+            ],
+        )
+    }
+
+    /// Waits for the coordinator's verdict and redeems the co-signed proofs assigned to us.
+    ///
+    /// Only messages of the coordinator of the contract are taken, verdicts of other escrows
+    /// are skipped. Fails if the coordinator couldn't resolve the dispute.
+    ///
+    /// Returns the verdict and the redeemed amount.
+    pub async fn receive_verdict(&mut self, timeout_secs: u64) -> Result<(DisputeVerdict, Amount)> {
+        let escrow_id_hex = &self.escrow_registration.escrow_id_hex;
+        let verdict = loop {
+            let outcome: DisputeOutcome = self
+                .nostr_client
+                .receive_escrow_message_from(self.escrow_contract.npubkey_coordinator, timeout_secs)
+                .await?;
+            match outcome {
+                DisputeOutcome::Verdict(verdict) if verdict.escrow_id_hex == *escrow_id_hex => {
+                    break verdict
+                }
+                DisputeOutcome::Failure(failure) if failure.escrow_id_hex == *escrow_id_hex => {
+                    return Err(EscrowError::Protocol(format!(
+                        "The coordinator couldn't resolve the dispute: {}",
+                        failure.reason
+                    )));
+                }
+                _ => debug!("Skipping the outcome of the dispute of another escrow"),
+            }
+        };
+        debug!("Received verdict: {:?}", verdict.verdict);
+        let redeemed = match &verdict.cosigned_token {
+            Some(cosigned_token) => {
+                self.ecash_wallet
+                    .redeem_escrow_token(cosigned_token)
+                    .await?
+            }
+            None => Amount::ZERO,
+        };
+        Ok((verdict, redeemed))
+    }
+}
+
+/// Answer of the coordinator to a submitted contract, see [`InitEscrowClient::register_trade`].
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RegistrationAnswer {
+    Registration(EscrowRegistration),
+    Rejection(ContractRejection),
+}
+
+/// Message of the coordinator about a dispute, see [`TokenExchangedEscrowClient::receive_verdict`].
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DisputeOutcome {
+    Verdict(DisputeVerdict),
+    Failure(DisputeFailure),
+}
+
+/// Identifies the proofs of a token independent of their witnesses.
+fn proof_ids(token: &Token) -> HashSet<(String, Id, Amount, String)> {
+    token
+        .proofs()
+        .into_iter()
+        .flat_map(|(mint_url, proofs)| {
+            proofs.into_iter().map(move |proof| {
+                (
+                    mint_url.to_string(),
+                    proof.keyset_id,
+                    proof.amount,
+                    proof.secret.to_string(),
+                )
+            })
+        })
+        .collect()
 }
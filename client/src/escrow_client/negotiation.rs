@@ -0,0 +1,252 @@
+use super::*;
+
+use cashu_escrow_common::model::{ContractProposal, ContractSignatures, NegotiationMessage};
+use nostr_sdk::util::hex;
+use rand::RngCore;
+
+/// Answer of the trade partner to our latest proposal.
+#[derive(Debug, Clone)]
+pub enum NegotiationAnswer {
+    /// The negotiation ended with the contract signed by both traders.
+    Accepted(ContractSignatures),
+    /// The partner proposed other terms, see [`ContractNegotiation::proposal`].
+    Countered,
+    Rejected(String),
+}
+
+/// Negotiation of the contract terms with the trade partner, before the contract is
+/// registered at the coordinator.
+///
+/// One trader starts with [`ContractNegotiation::offer`], the other one with
+/// [`ContractNegotiation::receive_offer`]. The traders answer each other's proposals until
+/// one of them accepts, both then hold the same contract signed by both of them, which
+/// replaces the contract of the [`InitEscrowClient`] and is submitted with their signatures.
+#[derive(Debug)]
+pub struct ContractNegotiation {
+    negotiation_id: String,
+    /// The latest proposal, by us or by the partner.
+    proposal: ContractProposal,
+    proposed_by_us: bool,
+}
+
+impl ContractNegotiation {
+    /// Starts the negotiation by offering the contract of the client to the trade partner.
+    ///
+    /// The contract has to hold the trade pubkeys of both traders, see
+    /// [`InitEscrowClient::exchange_ecash_pubkeys`].
+    pub async fn offer(client: &InitEscrowClient) -> Result<Self> {
+        let contract = client.escrow_contract.clone();
+        check_ecash_pubkeys(&contract)?;
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        let negotiation_id = hex::encode(id);
+        let negotiation = Self {
+            proposal: ContractProposal {
+                negotiation_id: negotiation_id.clone(),
+                signature: client.nostr_client.sign_contract(&contract)?,
+                contract,
+            },
+            negotiation_id,
+            proposed_by_us: true,
+        };
+        negotiation
+            .send(
+                client,
+                &NegotiationMessage::Offer(negotiation.proposal.clone()),
+            )
+            .await?;
+        Ok(negotiation)
+    }
+
+    /// Waits for the offer of the trade partner.
+    ///
+    /// Only offers which differ from the contract of the client in the negotiated terms
+    /// are taken, see [`TradeContract::check_negotiable_changes`].
+    pub async fn receive_offer(client: &mut InitEscrowClient, timeout_secs: u64) -> Result<Self> {
+        let partner = client.partner_npub();
+        loop {
+            let message: NegotiationMessage = client
+                .nostr_client
+                .receive_escrow_message(timeout_secs)
+                .await?;
+            let NegotiationMessage::Offer(proposal) = message else {
+                debug!("Ignoring a negotiation message without offer");
+                continue;
+            };
+            let contract = &proposal.contract;
+            if let Err(e) = contract
+                .check_negotiable_changes(&client.escrow_contract)
+                .and_then(|_| check_ecash_pubkeys(contract))
+                .and_then(|_| contract.verify_signature(&partner, &proposal.signature))
+            {
+                warn!("Ignoring an offer: {}", e);
+                continue;
+            }
+            return Ok(Self {
+                negotiation_id: proposal.negotiation_id.clone(),
+                proposal,
+                proposed_by_us: false,
+            });
+        }
+    }
+
+    /// The latest proposed contract.
+    pub fn proposal(&self) -> &TradeContract {
+        &self.proposal.contract
+    }
+
+    /// `true` if the latest proposal is ours, so it is the partner's turn.
+    pub fn proposed_by_us(&self) -> bool {
+        self.proposed_by_us
+    }
+
+    /// Waits for the partner's answer to our latest proposal.
+    pub async fn receive_answer(
+        &mut self,
+        client: &mut InitEscrowClient,
+        timeout_secs: u64,
+    ) -> Result<NegotiationAnswer> {
+        if !self.proposed_by_us {
+            return Err(EscrowError::Protocol(
+                "The proposal of the partner hasn't been answered yet".to_string(),
+            ));
+        }
+        let partner = client.partner_npub();
+        loop {
+            let message: NegotiationMessage = client
+                .nostr_client
+                .receive_escrow_message(timeout_secs)
+                .await?;
+            if message.negotiation_id() != self.negotiation_id {
+                debug!("Ignoring a message of another negotiation");
+                continue;
+            }
+            match message {
+                NegotiationMessage::Accept {
+                    contract_hash,
+                    signature,
+                    ..
+                } => {
+                    let contract = &self.proposal.contract;
+                    if contract_hash != hex::encode(contract.contract_hash()?) {
+                        warn!("Ignoring the acceptance of an outdated proposal");
+                        continue;
+                    }
+                    if let Err(e) = contract.verify_signature(&partner, &signature) {
+                        warn!("Ignoring an invalid acceptance: {}", e);
+                        continue;
+                    }
+                    return Ok(NegotiationAnswer::Accepted(client.agree_on_contract(
+                        contract.clone(),
+                        self.proposal.signature,
+                        signature,
+                    )));
+                }
+                NegotiationMessage::Counter(proposal) => {
+                    if let Err(e) = proposal
+                        .contract
+                        .check_negotiable_changes(&self.proposal.contract)
+                        .and_then(|_| {
+                            proposal
+                                .contract
+                                .verify_signature(&partner, &proposal.signature)
+                        })
+                    {
+                        warn!("Ignoring an invalid counter-offer: {}", e);
+                        continue;
+                    }
+                    self.proposal = proposal;
+                    self.proposed_by_us = false;
+                    return Ok(NegotiationAnswer::Countered);
+                }
+                NegotiationMessage::Reject { reason, .. } => {
+                    return Ok(NegotiationAnswer::Rejected(reason))
+                }
+                NegotiationMessage::Offer(_) => {
+                    debug!("Ignoring a repeated offer");
+                }
+            }
+        }
+    }
+
+    /// Accepts the partner's proposal, returns the signatures of both traders.
+    pub async fn accept(self, client: &mut InitEscrowClient) -> Result<ContractSignatures> {
+        self.require_partner_proposal()?;
+        let contract = &self.proposal.contract;
+        let signature = client.nostr_client.sign_contract(contract)?;
+        self.send(
+            client,
+            &NegotiationMessage::Accept {
+                negotiation_id: self.negotiation_id.clone(),
+                contract_hash: hex::encode(contract.contract_hash()?),
+                signature,
+            },
+        )
+        .await?;
+        Ok(client.agree_on_contract(contract.clone(), signature, self.proposal.signature))
+    }
+
+    /// Answers the partner's proposal with other terms, see
+    /// [`TradeContract::check_negotiable_changes`].
+    pub async fn counter(
+        &mut self,
+        client: &InitEscrowClient,
+        contract: TradeContract,
+    ) -> Result<()> {
+        self.require_partner_proposal()?;
+        contract.check_negotiable_changes(&self.proposal.contract)?;
+        let proposal = ContractProposal {
+            negotiation_id: self.negotiation_id.clone(),
+            signature: client.nostr_client.sign_contract(&contract)?,
+            contract,
+        };
+        self.send(client, &NegotiationMessage::Counter(proposal.clone()))
+            .await?;
+        self.proposal = proposal;
+        self.proposed_by_us = true;
+        Ok(())
+    }
+
+    pub async fn reject(self, client: &InitEscrowClient, reason: String) -> Result<()> {
+        self.send(
+            client,
+            &NegotiationMessage::Reject {
+                negotiation_id: self.negotiation_id.clone(),
+                reason,
+            },
+        )
+        .await
+    }
+
+    fn require_partner_proposal(&self) -> Result<()> {
+        if self.proposed_by_us {
+            return Err(EscrowError::Protocol(
+                "Waiting for the partner's answer to our proposal".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn send(&self, client: &InitEscrowClient, message: &NegotiationMessage) -> Result<()> {
+        client
+            .nostr_client
+            .client
+            .send_private_msg(client.partner_npub(), serde_json::to_string(message)?, None)
+            .await?;
+        Ok(())
+    }
+}
+
+fn check_ecash_pubkeys(contract: &TradeContract) -> Result<()> {
+    for ecash_pubkey in [
+        &contract.buyer_ecash_public_key,
+        &contract.seller_ecash_public_key,
+    ] {
+        EcashPubkey::from_str(ecash_pubkey).map_err(|_| {
+            EscrowError::Validation(
+                "The contract needs the trade pubkeys of both traders".to_string(),
+            )
+        })?;
+    }
+    Ok(())
+}
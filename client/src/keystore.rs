@@ -0,0 +1,234 @@
+use super::*;
+
+use bip39::Mnemonic;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use nostr_sdk::nips::nip49::{EncryptedSecretKey, KeySecurity};
+use nostr_sdk::prelude::{FromBech32, ToBech32};
+use nostr_sdk::util::hex;
+use nostr_sdk::{Keys, PublicKey, SecretKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Version of the keystore file format.
+const KEYSTORE_VERSION: u8 = 1;
+
+/// scrypt work factor (log2 of N), the same default NIP-49 uses.
+const SCRYPT_LOG_N: u8 = 16;
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+
+/// The secrets of a trader: the nostr keys and the mnemonic of the ecash wallet.
+///
+/// Only lives in memory, see [`Keystore::lock`] to persist it. The secrets are wiped
+/// from memory when the keystore is dropped.
+pub struct Keystore {
+    nostr_keys: Keys,
+    mnemonic: Mnemonic,
+}
+
+impl std::fmt::Debug for Keystore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keystore")
+            .field("nostr_pubkey", &self.nostr_keys.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Keystore {
+    /// Generates new nostr keys and a new wallet mnemonic.
+    pub fn generate() -> Result<Self> {
+        Ok(Self {
+            nostr_keys: Keys::generate(),
+            mnemonic: generate_mnemonic()?,
+        })
+    }
+
+    /// Imports existing secrets, a new wallet mnemonic is generated if none is given.
+    ///
+    /// The nostr secret key may be given as nsec or as hex.
+    pub fn import(nostr_secret_key: &str, mnemonic: Option<&str>) -> Result<Self> {
+        let nostr_keys = Keys::from_str(nostr_secret_key)
+            .map_err(|e| EscrowError::Validation(format!("Invalid nostr secret key: {}", e)))?;
+        let mnemonic = match mnemonic {
+            Some(mnemonic) => parse_mnemonic(mnemonic)?,
+            None => generate_mnemonic()?,
+        };
+        Ok(Self {
+            nostr_keys,
+            mnemonic,
+        })
+    }
+
+    pub fn nostr_keys(&self) -> &Keys {
+        &self.nostr_keys
+    }
+
+    /// The BIP39 mnemonic of the ecash wallet.
+    pub fn mnemonic(&self) -> Zeroizing<String> {
+        Zeroizing::new(self.mnemonic.to_string())
+    }
+
+    pub fn export_nsec(&self) -> Result<Zeroizing<String>> {
+        let secret_key = self.nostr_keys.secret_key();
+        Ok(Zeroizing::new(secret_key.to_bech32()?))
+    }
+
+    /// Exports the nostr secret key encrypted with the passphrase (NIP-49).
+    pub fn export_ncryptsec(&self, passphrase: &str) -> Result<String> {
+        Ok(encrypt_nostr_key(self.nostr_keys.secret_key(), passphrase)?.to_bech32()?)
+    }
+
+    /// Encrypts the secrets with the passphrase, e.g. to save them to a file.
+    pub fn lock(&self, passphrase: &str) -> Result<EncryptedKeystore> {
+        let nostr = encrypt_nostr_key(self.nostr_keys.secret_key(), passphrase)?;
+        let mnemonic = Zeroizing::new(self.mnemonic.to_string());
+        Ok(EncryptedKeystore {
+            version: KEYSTORE_VERSION,
+            nostr_pubkey: self.nostr_keys.public_key(),
+            nostr: nostr.to_bech32()?,
+            wallet: EncryptedSecret::encrypt(mnemonic.as_bytes(), passphrase)?,
+        })
+    }
+}
+
+/// A [`Keystore`] encrypted with a passphrase, serialized as JSON.
+///
+/// The nostr secret key is kept as NIP-49 `ncryptsec`, so it can also be imported by
+/// other nostr clients. The wallet mnemonic is encrypted the same way, with a key
+/// derived by scrypt and XChaCha20-Poly1305.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedKeystore {
+    pub version: u8,
+    /// Nostr pubkey of the trader, readable without the passphrase.
+    pub nostr_pubkey: PublicKey,
+    nostr: String,
+    wallet: EncryptedSecret,
+}
+
+impl EncryptedKeystore {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let keystore: Self = serde_json::from_str(json)
+            .map_err(|e| EscrowError::Storage(format!("Corrupt keystore: {}", e)))?;
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(EscrowError::Storage(format!(
+                "Unsupported keystore version {}",
+                keystore.version
+            )));
+        }
+        Ok(keystore)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Decrypts the secrets, fails if the passphrase is wrong.
+    pub fn unlock(&self, passphrase: &str) -> Result<Keystore> {
+        let nostr = EncryptedSecretKey::from_bech32(&self.nostr)
+            .map_err(|e| EscrowError::Storage(format!("Corrupt keystore: {}", e)))?;
+        let secret_key = nostr
+            .to_secret_key(passphrase)
+            .map_err(|_| wrong_passphrase())?;
+        let nostr_keys = Keys::new(secret_key);
+        if nostr_keys.public_key() != self.nostr_pubkey {
+            return Err(EscrowError::Storage(
+                "The keystore holds the secret key of another pubkey".to_string(),
+            ));
+        }
+        let mnemonic = self.wallet.decrypt(passphrase)?;
+        let mnemonic = std::str::from_utf8(&mnemonic)
+            .map_err(|e| EscrowError::Storage(format!("Corrupt keystore: {}", e)))?;
+        Ok(Keystore {
+            nostr_keys,
+            mnemonic: parse_mnemonic(mnemonic)?,
+        })
+    }
+}
+
+/// A secret encrypted with XChaCha20-Poly1305 under a key derived from a passphrase by scrypt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EncryptedSecret {
+    log_n: u8,
+    /// Hex encoded.
+    salt: String,
+    /// Hex encoded.
+    nonce: String,
+    /// Hex encoded, including the authentication tag.
+    ciphertext: String,
+}
+
+impl EncryptedSecret {
+    fn encrypt(secret: &[u8], passphrase: &str) -> Result<Self> {
+        let mut salt = [0u8; SALT_SIZE];
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let key = derive_key(passphrase, &salt, SCRYPT_LOG_N)?;
+        let ciphertext = XChaCha20Poly1305::new(&(*key).into())
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: secret,
+                    aad: &[KEYSTORE_VERSION],
+                },
+            )
+            .map_err(|e| EscrowError::Validation(format!("Encryption failed: {}", e)))?;
+        Ok(Self {
+            log_n: SCRYPT_LOG_N,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    fn decrypt(&self, passphrase: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let salt = hex::decode(&self.salt)?;
+        let nonce: [u8; NONCE_SIZE] = hex::decode(&self.nonce)?
+            .try_into()
+            .map_err(|_| EscrowError::Storage("Corrupt keystore: invalid nonce".to_string()))?;
+        let key = derive_key(passphrase, &salt, self.log_n)?;
+        let secret = XChaCha20Poly1305::new(&(*key).into())
+            .decrypt(
+                &nonce.into(),
+                Payload {
+                    msg: &hex::decode(&self.ciphertext)?,
+                    aad: &[KEYSTORE_VERSION],
+                },
+            )
+            .map_err(|_| wrong_passphrase())?;
+        Ok(Zeroizing::new(secret))
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8) -> Result<Zeroizing<[u8; 32]>> {
+    let params = scrypt::Params::new(log_n, 8, 1, 32)
+        .map_err(|e| EscrowError::Validation(format!("Invalid scrypt parameters: {}", e)))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, key.as_mut())
+        .map_err(|e| EscrowError::Validation(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+fn encrypt_nostr_key(secret_key: &SecretKey, passphrase: &str) -> Result<EncryptedSecretKey> {
+    EncryptedSecretKey::new(secret_key, passphrase, SCRYPT_LOG_N, KeySecurity::Medium)
+        .map_err(|e| EscrowError::Validation(format!("Encryption failed: {}", e)))
+}
+
+fn wrong_passphrase() -> EscrowError {
+    EscrowError::Validation("Wrong passphrase".to_string())
+}
+
+pub(crate) fn generate_mnemonic() -> Result<Mnemonic> {
+    let mut entropy = Zeroizing::new([0u8; 16]);
+    rand::thread_rng().fill_bytes(entropy.as_mut());
+    Mnemonic::from_entropy(entropy.as_ref()).map_err(|e| EscrowError::Validation(e.to_string()))
+}
+
+pub(crate) fn parse_mnemonic(mnemonic: &str) -> Result<Mnemonic> {
+    Mnemonic::parse(mnemonic)
+        .map_err(|e| EscrowError::Validation(format!("Invalid mnemonic: {}", e)))
+}
@@ -1,5 +1,8 @@
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
+use cashu_escrow_common::error::{EscrowError, Result};
+
 pub mod ecash;
 pub mod escrow_client;
+pub mod keystore;
@@ -1,12 +1,27 @@
 use cashu_escrow_client::ecash::ClientEcashWallet;
+use cashu_escrow_common::{
+    error::Result,
+    model::{EscrowRegistration, FeePayer, TradeContract},
+};
 use cdk::{
     amount::{Amount, SplitTarget},
+    lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret},
+    mint_url::MintUrl,
+    nuts::{CurrencyUnit, PublicKey, SecretKey, Token},
+    secp256k1::{
+        hashes::{sha256, Hash},
+        Secp256k1,
+    },
     wallet::{SendKind, Wallet},
 };
+use nostr_sdk::{Keys, Timestamp};
+use std::str::FromStr;
+
+pub(super) const MINT_URL: &str = "http://localhost:3338";
 
 #[inline]
-pub(super) async fn create_wallet() -> anyhow::Result<ClientEcashWallet> {
-    ClientEcashWallet::new("http://localhost:3338").await
+pub(super) async fn create_wallet() -> Result<ClientEcashWallet> {
+    ClientEcashWallet::new(MINT_URL, CurrencyUnit::Sat).await
 }
 
 pub(super) async fn check_mint_and_send(wallet: Wallet) {
@@ -30,3 +45,80 @@ pub(super) async fn check_mint_and_send(wallet: Wallet) {
         .await;
     assert!(token_result.is_ok());
 }
+
+/// Creates a contract between the two wallets and funds the escrow from the buyer's wallet.
+pub(super) async fn fund_escrow(
+    buyer: &ClientEcashWallet,
+    seller: &ClientEcashWallet,
+    trade_amount: u64,
+) -> Result<Token> {
+    fund_escrow_with(buyer, seller, trade_amount, &SecretKey::generate(), 60).await
+}
+
+/// Like [`fund_escrow`], with the escrow key of the coordinator and the time limit of the trade.
+pub(super) async fn fund_escrow_with(
+    buyer: &ClientEcashWallet,
+    seller: &ClientEcashWallet,
+    trade_amount: u64,
+    coordinator_key: &SecretKey,
+    time_limit: u64,
+) -> Result<Token> {
+    let contract = trade_contract(
+        &buyer.new_trade_pubkey()?,
+        &seller.new_trade_pubkey()?,
+        trade_amount,
+        time_limit,
+    );
+    let registration = EscrowRegistration::new(
+        "00".repeat(32),
+        coordinator_key.public_key(),
+        Timestamp::now(),
+    );
+    let costs = buyer.estimate_escrow_costs(&contract).await?;
+    let mint_quote = buyer.wallet.mint_quote(costs.buyer_total).await?;
+    buyer
+        .wallet
+        .mint(&mint_quote.id, SplitTarget::None, None)
+        .await?;
+    buyer.create_escrow_token(&contract, &registration).await
+}
+
+pub(super) fn trade_contract(
+    buyer_ecash_pubkey: &PublicKey,
+    seller_ecash_pubkey: &PublicKey,
+    trade_amount: u64,
+    time_limit: u64,
+) -> TradeContract {
+    TradeContract {
+        trade_description: "Test trade".to_string(),
+        trade_amount,
+        npubkey_seller: Keys::generate().public_key(),
+        npubkey_buyer: Keys::generate().public_key(),
+        npubkey_coordinator: Keys::generate().public_key(),
+        time_limit,
+        seller_ecash_public_key: seller_ecash_pubkey.to_string(),
+        buyer_ecash_public_key: buyer_ecash_pubkey.to_string(),
+        mint_url: MintUrl::from_str(MINT_URL).unwrap(),
+        currency_unit: CurrencyUnit::Sat,
+        allowed_keysets: None,
+        fee_payer: FeePayer::Buyer,
+        terms: None,
+        privacy: None,
+    }
+}
+
+/// A regtest invoice, the fake wallet of the test mint pays any invoice.
+pub(super) fn fake_invoice(amount_sat: u64) -> String {
+    let secp = Secp256k1::new();
+    let node_key = SecretKey::generate();
+    InvoiceBuilder::new(Currency::Regtest)
+        .description("escrow payout".to_string())
+        .payment_hash(sha256::Hash::hash(&rand::random::<[u8; 32]>()))
+        .payment_secret(PaymentSecret(rand::random()))
+        .amount_milli_satoshis(amount_sat * 1000)
+        .current_timestamp()
+        .min_final_cltv_expiry_delta(144)
+        .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &node_key))
+        .unwrap()
+        .to_string()
+}
@@ -1,6 +1,30 @@
 mod common;
 
-use common::{check_mint_and_send, create_wallet};
+use cashu_escrow_client::ecash::{
+    escrow_denominations,
+    payout::{LightningDestination, PayoutStatus},
+    store::{FileStateStore, WalletStateStore},
+    validation::{CheckResult, EscrowTokenReport},
+    ClientEcashWallet,
+};
+use cashu_escrow_client::keystore::{EncryptedKeystore, Keystore};
+use cashu_escrow_common::{
+    error::EscrowError,
+    model::{EscrowRegistration, FeePayer, TradeContract},
+};
+use cdk::{
+    amount::SplitTarget,
+    mint_url::MintUrl,
+    nuts::{nut10, CurrencyUnit, Id, Proof, SecretKey, SigFlag, SpendingConditions, Token},
+    secret::Secret,
+    wallet::SendKind,
+    Amount,
+};
+use common::{
+    check_mint_and_send, create_wallet, fake_invoice, fund_escrow, fund_escrow_with, trade_contract,
+};
+use nostr_sdk::Timestamp;
+use std::{str::FromStr, sync::Arc};
 
 #[tokio::test]
 async fn send_minted_ecash() {
@@ -10,3 +34,487 @@ async fn send_minted_ecash() {
     let wallet = wallet_result.unwrap().wallet;
     check_mint_and_send(wallet).await;
 }
+
+#[tokio::test]
+async fn reopen_persisted_wallet() {
+    let path = std::env::temp_dir().join(format!("escrow-wallet-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let store = Arc::new(FileStateStore::new(&path));
+    let keyset_id = Id::from_str("009a1f293253e41e").unwrap();
+
+    let wallet =
+        ClientEcashWallet::create("http://localhost:3338", CurrencyUnit::Sat, store.clone())
+            .await
+            .unwrap();
+    wallet
+        .wallet
+        .localstore
+        .increment_keyset_counter(&keyset_id, 3)
+        .await
+        .unwrap();
+    let trade_pubkey = wallet.new_trade_pubkey().unwrap();
+    assert!(
+        ClientEcashWallet::create("http://localhost:3338", CurrencyUnit::Sat, store.clone())
+            .await
+            .is_err()
+    );
+
+    let reopened = ClientEcashWallet::open(store).await.unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // the wallet file holds the mnemonic
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        reopened.trade_secret(&trade_pubkey).unwrap().public_key(),
+        trade_pubkey
+    );
+    assert_ne!(reopened.new_trade_pubkey().unwrap(), trade_pubkey);
+    assert_eq!(
+        reopened
+            .wallet
+            .localstore
+            .get_keyset_counter(&keyset_id)
+            .await
+            .unwrap(),
+        Some(3)
+    );
+}
+
+/// Store whose saves fail once `failing` is set.
+#[derive(Default)]
+struct FlakyStore {
+    state: std::sync::Mutex<Option<String>>,
+    failing: std::sync::atomic::AtomicBool,
+}
+
+impl WalletStateStore for FlakyStore {
+    fn load(&self) -> cashu_escrow_common::error::Result<Option<String>> {
+        Ok(self.state.lock().unwrap().clone())
+    }
+
+    fn save(&self, state: &str) -> cashu_escrow_common::error::Result<()> {
+        if self.failing.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(EscrowError::Storage("Disk full".to_string()));
+        }
+        *self.state.lock().unwrap() = Some(state.to_string());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn keep_wallet_unchanged_if_saving_fails() {
+    let store = Arc::new(FlakyStore::default());
+    let keyset_id = Id::from_str("009a1f293253e41e").unwrap();
+    let wallet =
+        ClientEcashWallet::create("http://localhost:3338", CurrencyUnit::Sat, store.clone())
+            .await
+            .unwrap();
+    let localstore = &wallet.wallet.localstore;
+    localstore
+        .increment_keyset_counter(&keyset_id, 3)
+        .await
+        .unwrap();
+
+    store
+        .failing
+        .store(true, std::sync::atomic::Ordering::SeqCst);
+    assert!(localstore
+        .increment_keyset_counter(&keyset_id, 2)
+        .await
+        .is_err());
+    assert!(wallet.new_trade_pubkey().is_err());
+    assert_eq!(
+        localstore.get_keyset_counter(&keyset_id).await.unwrap(),
+        Some(3)
+    );
+}
+
+#[tokio::test]
+async fn open_wallet_with_keystore() {
+    let keystore = Keystore::generate().unwrap();
+    let encrypted =
+        EncryptedKeystore::from_json(&keystore.lock("correct horse").unwrap().to_json().unwrap())
+            .unwrap();
+    assert!(encrypted.unlock("wrong horse").is_err());
+    let unlocked = encrypted.unlock("correct horse").unwrap();
+    assert_eq!(unlocked.nostr_keys(), keystore.nostr_keys());
+    assert_eq!(unlocked.mnemonic(), keystore.mnemonic());
+
+    let path = std::env::temp_dir().join(format!("escrow-keystore-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let store = Arc::new(FileStateStore::new(&path));
+    let wallet = ClientEcashWallet::create_with_keystore(
+        "http://localhost:3338",
+        CurrencyUnit::Sat,
+        &unlocked,
+        store.clone(),
+    )
+    .await
+    .unwrap();
+    let trade_pubkey = wallet.new_trade_pubkey().unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(!saved.contains(keystore.mnemonic().as_str()));
+    assert!(ClientEcashWallet::open(store.clone()).await.is_err());
+
+    let reopened = ClientEcashWallet::open_with_keystore(store, &keystore)
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        reopened.trade_secret(&trade_pubkey).unwrap().public_key(),
+        trade_pubkey
+    );
+}
+
+#[tokio::test]
+async fn derive_trade_keys_from_mnemonic() {
+    let wallet = create_wallet().await.unwrap();
+    let recovered = ClientEcashWallet::from_mnemonic(
+        "http://localhost:3338",
+        CurrencyUnit::Sat,
+        &wallet.mnemonic(),
+    )
+    .await
+    .unwrap();
+
+    let first_trade_pubkey = wallet.new_trade_pubkey().unwrap();
+    let second_trade_pubkey = wallet.new_trade_pubkey().unwrap();
+    assert_ne!(first_trade_pubkey, second_trade_pubkey);
+    // unrecorded keys of the restored wallet are found by searching ahead
+    assert_eq!(
+        recovered
+            .trade_secret(&second_trade_pubkey)
+            .unwrap()
+            .public_key(),
+        second_trade_pubkey
+    );
+    // keys of others are searched once and remembered as foreign
+    let foreign_pubkey = SecretKey::generate().public_key();
+    assert!(recovered.trade_secret(&foreign_pubkey).is_err());
+    assert!(recovered.trade_secret(&foreign_pubkey).is_err());
+    assert_eq!(
+        recovered.trade_key(7).unwrap(),
+        wallet.trade_key(7).unwrap()
+    );
+    assert_ne!(wallet.trade_key(7).unwrap(), wallet.trade_key(8).unwrap());
+    assert!(ClientEcashWallet::from_mnemonic(
+        "http://localhost:3338",
+        CurrencyUnit::Sat,
+        "not a mnemonic"
+    )
+    .await
+    .is_err());
+}
+
+/// Any two of seller, buyer and coordinator can spend the escrow, the outputs need no signatures.
+#[test]
+fn lock_escrow_to_two_input_signatures() {
+    let (buyer, seller, coordinator) = (
+        SecretKey::generate(),
+        SecretKey::generate(),
+        SecretKey::generate(),
+    );
+    let contract = trade_contract(&buyer.public_key(), &seller.public_key(), 100, 60);
+    let registration =
+        EscrowRegistration::new("00".repeat(32), coordinator.public_key(), Timestamp::now());
+    let conditions =
+        ClientEcashWallet::assemble_escrow_conditions(&contract, &registration).unwrap();
+    let SpendingConditions::P2PKConditions {
+        conditions: Some(lock),
+        ..
+    } = &conditions
+    else {
+        panic!("escrow is not locked with P2PK conditions");
+    };
+    assert_eq!(lock.sig_flag, SigFlag::SigInputs);
+
+    let escrow_proof = || {
+        Proof::new(
+            Amount::from(64),
+            Id::from_str("009a1f293253e41e").unwrap(),
+            Secret::try_from(nut10::Secret::from(conditions.clone())).unwrap(),
+            SecretKey::generate().public_key(),
+        )
+    };
+    for signers in [
+        [&buyer, &seller],
+        [&coordinator, &seller],
+        [&coordinator, &buyer],
+    ] {
+        let mut proof = escrow_proof();
+        for signer in signers {
+            proof.sign_p2pk(signer.clone()).unwrap();
+        }
+        assert!(proof.verify_p2pk().is_ok());
+    }
+    let mut proof = escrow_proof();
+    proof.sign_p2pk(seller.clone()).unwrap();
+    assert!(proof.verify_p2pk().is_err());
+}
+
+#[test]
+fn split_escrow_into_payable_denominations() {
+    let denominations: Vec<u64> = escrow_denominations(Amount::from(5000))
+        .into_iter()
+        .map(u64::from)
+        .collect();
+    assert_eq!(
+        denominations,
+        vec![2048, 1024, 512, 512, 256, 256, 128, 128, 64, 32, 16, 8, 8, 4, 2, 1, 1]
+    );
+    assert!(escrow_denominations(Amount::ZERO).is_empty());
+    assert_eq!(escrow_denominations(Amount::from(7)).len(), 3);
+}
+
+/// Escrow token over `amount` from the mint of the contract, locked to the coordinator key.
+fn escrow_token(
+    contract: &TradeContract,
+    registration: &EscrowRegistration,
+    coordinator_key: &SecretKey,
+    amount: u64,
+) -> Token {
+    let conditions = contract
+        .escrow_conditions(
+            &coordinator_key.public_key(),
+            registration.escrow_start_time,
+        )
+        .unwrap();
+    let proofs = escrow_denominations(Amount::from(amount))
+        .into_iter()
+        .map(|amount| {
+            Proof::new(
+                amount,
+                Id::from_str("009a1f293253e41e").unwrap(),
+                Secret::try_from(nut10::Secret::from(conditions.clone())).unwrap(),
+                SecretKey::generate().public_key(),
+            )
+        })
+        .collect();
+    Token::new(
+        contract.mint_url.clone(),
+        proofs,
+        None,
+        Some(contract.currency_unit),
+    )
+}
+
+/// The checks failing on a token, apart from the ones which need the mint.
+fn failed_checks(report: &EscrowTokenReport) -> Vec<&'static str> {
+    report
+        .checks()
+        .into_iter()
+        .filter(|(name, result)| !["unspent", "dleq"].contains(name) && !result.passed())
+        .map(|(name, _)| name)
+        .collect()
+}
+
+#[test]
+fn check_escrow_token_terms() {
+    let (buyer, seller, coordinator) = (
+        SecretKey::generate(),
+        SecretKey::generate(),
+        SecretKey::generate(),
+    );
+    let mut contract = trade_contract(&buyer.public_key(), &seller.public_key(), 100, 60);
+    let registration =
+        EscrowRegistration::new("00".repeat(32), coordinator.public_key(), Timestamp::now());
+    let token = escrow_token(&contract, &registration, &coordinator, 102);
+    let fee = Some(Amount::from(2));
+
+    let report = EscrowTokenReport::check_terms(&token, &contract, &registration, fee);
+    assert!(failed_checks(&report).is_empty());
+    // the state and the DLEQ proofs are left to the mint
+    assert!(!report.is_valid());
+    assert!(!report.dleq.passed());
+
+    // the buyer's token has to cover the redemption fee
+    let short = escrow_token(&contract, &registration, &coordinator, 101);
+    let report = EscrowTokenReport::check_terms(&short, &contract, &registration, fee);
+    assert_eq!(failed_checks(&report), ["amount"]);
+    let report = EscrowTokenReport::check_terms(&token, &contract, &registration, None);
+    assert_eq!(failed_checks(&report), ["amount"]);
+
+    // the seller's token holds exactly the trade amount
+    contract.fee_payer = FeePayer::Seller;
+    let report = EscrowTokenReport::check_terms(&token, &contract, &registration, fee);
+    assert_eq!(failed_checks(&report), ["amount"]);
+    let exact = escrow_token(&contract, &registration, &coordinator, 100);
+    let report = EscrowTokenReport::check_terms(&exact, &contract, &registration, None);
+    assert!(failed_checks(&report).is_empty());
+}
+
+#[test]
+fn reject_escrow_token_of_foreign_mint() {
+    let (buyer, seller, coordinator) = (
+        SecretKey::generate(),
+        SecretKey::generate(),
+        SecretKey::generate(),
+    );
+    let contract = trade_contract(&buyer.public_key(), &seller.public_key(), 100, 60);
+    let registration =
+        EscrowRegistration::new("00".repeat(32), coordinator.public_key(), Timestamp::now());
+    let foreign_contract = TradeContract {
+        mint_url: MintUrl::from_str("https://mint.example.com").unwrap(),
+        ..contract.clone()
+    };
+    let token = escrow_token(&foreign_contract, &registration, &coordinator, 102);
+
+    let report =
+        EscrowTokenReport::check_terms(&token, &contract, &registration, Some(Amount::from(2)));
+    assert_eq!(failed_checks(&report), ["mint"]);
+    assert!(matches!(report.mint, CheckResult::Failed(_)));
+}
+
+#[test]
+fn reject_escrow_token_without_coordinator_key() {
+    let (buyer, seller, coordinator) = (
+        SecretKey::generate(),
+        SecretKey::generate(),
+        SecretKey::generate(),
+    );
+    let contract = trade_contract(&buyer.public_key(), &seller.public_key(), 100, 60);
+    let registration =
+        EscrowRegistration::new("00".repeat(32), coordinator.public_key(), Timestamp::now());
+    // locked to a key the coordinator doesn't have
+    let token = escrow_token(&contract, &registration, &SecretKey::generate(), 102);
+
+    let report =
+        EscrowTokenReport::check_terms(&token, &contract, &registration, Some(Amount::from(2)));
+    assert_eq!(failed_checks(&report), ["spending conditions"]);
+}
+
+/// The winner of a dispute redeems the escrow token co-signed by the coordinator.
+#[tokio::test]
+async fn redeem_cosigned_escrow_token() {
+    let buyer = create_wallet().await.unwrap();
+    let seller = create_wallet().await.unwrap();
+    let coordinator_key = SecretKey::generate();
+    let escrow_token = fund_escrow_with(&buyer, &seller, 100, &coordinator_key, 60)
+        .await
+        .unwrap();
+
+    let mut proofs: Vec<Proof> = escrow_token.proofs().into_values().flatten().collect();
+    for proof in proofs.iter_mut() {
+        proof.sign_p2pk(coordinator_key.clone()).unwrap();
+    }
+    let cosigned_token = Token::new(
+        escrow_token.proofs().into_keys().next().unwrap(),
+        proofs,
+        None,
+        Some(CurrencyUnit::Sat),
+    );
+    let redeemed = seller.redeem_escrow_token(&cosigned_token).await.unwrap();
+    assert!(redeemed >= Amount::from(100));
+    assert_eq!(seller.wallet.total_balance().await.unwrap(), redeemed);
+    // the escrow proofs are spent
+    assert!(buyer.redeem_escrow_token(&cosigned_token).await.is_err());
+}
+
+/// The seller redeems the escrow token released by the buyer.
+#[tokio::test]
+async fn release_escrow_to_seller() {
+    let buyer = create_wallet().await.unwrap();
+    let seller = create_wallet().await.unwrap();
+    let escrow_token = fund_escrow(&buyer, &seller, 100).await.unwrap();
+    // a single signature doesn't unlock the escrow
+    assert!(seller.redeem_escrow_token(&escrow_token).await.is_err());
+
+    let released_token = buyer.sign_escrow_token(&escrow_token).unwrap();
+    let redeemed = seller.redeem_escrow_token(&released_token).await.unwrap();
+    assert!(redeemed >= Amount::from(100));
+    assert_eq!(seller.wallet.total_balance().await.unwrap(), redeemed);
+}
+
+/// The buyer takes back the escrowed funds with the refund key after the locktime.
+#[tokio::test]
+async fn refund_escrow_after_locktime() {
+    let buyer = create_wallet().await.unwrap();
+    let seller = create_wallet().await.unwrap();
+    let escrow_token = fund_escrow_with(&buyer, &seller, 100, &SecretKey::generate(), 1)
+        .await
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+    let balance_before = buyer.wallet.total_balance().await.unwrap();
+    let refunded = buyer.redeem_escrow_token(&escrow_token).await.unwrap();
+    assert!(refunded >= Amount::from(100));
+    assert_eq!(
+        buyer.wallet.total_balance().await.unwrap(),
+        balance_before + refunded
+    );
+}
+
+#[tokio::test]
+async fn settle_escrow_to_lightning() {
+    let buyer = create_wallet().await.unwrap();
+    let seller = create_wallet().await.unwrap();
+    let escrow_token = fund_escrow(&buyer, &seller, 100).await.unwrap();
+    let payout = LightningDestination::Invoice(fake_invoice(50));
+    // without the buyer's release the escrow can't be settled
+    assert!(seller
+        .settle_to_lightning(&escrow_token, &payout)
+        .await
+        .is_err());
+    let released_token = buyer.sign_escrow_token(&escrow_token).unwrap();
+
+    let too_large = LightningDestination::Invoice(fake_invoice(1000));
+    let settlement = seller
+        .settle_to_lightning(&released_token, &too_large)
+        .await
+        .unwrap();
+    assert!(settlement.redeemed >= Amount::from(100));
+    assert!(matches!(settlement.payout, PayoutStatus::Failed { .. }));
+    // the proceeds stay in the wallet and can be paid out again
+    assert_eq!(
+        seller.wallet.total_balance().await.unwrap(),
+        settlement.redeemed
+    );
+    // the escrow proofs were spent by the settlement
+    assert!(seller.redeem_escrow_token(&released_token).await.is_err());
+
+    let payout = seller
+        .pay_lightning(&payout, settlement.redeemed)
+        .await
+        .unwrap();
+    assert!(matches!(
+        payout,
+        PayoutStatus::Paid { amount, .. } if amount == Amount::from(50)
+    ));
+}
+
+#[tokio::test]
+async fn fund_wallet_from_invoice_and_token() {
+    let sender = create_wallet().await.unwrap();
+    let funding = sender.request_funding(Amount::from(200)).await.unwrap();
+    assert!(!funding.invoice.is_empty());
+    // the fake wallet of the test mint pays the invoice right away
+    let minted = sender
+        .wait_for_funding(&funding.quote_id, 10)
+        .await
+        .unwrap();
+    assert_eq!(minted, Amount::from(200));
+
+    let token = sender
+        .wallet
+        .send(
+            Amount::from(100),
+            None,
+            None,
+            &SplitTarget::None,
+            &SendKind::OnlineExact,
+            false,
+        )
+        .await
+        .unwrap();
+    let receiver = create_wallet().await.unwrap();
+    let received = receiver
+        .fund_from_token(&format!("  {}\n", token))
+        .await
+        .unwrap();
+    assert_eq!(received, Amount::from(100));
+    assert!(receiver.fund_from_token(&token.to_string()).await.is_err());
+}
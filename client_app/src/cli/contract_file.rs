@@ -0,0 +1,290 @@
+use super::*;
+
+use cashu_escrow_client::ecash::ClientEcashWallet;
+use cashu_escrow_common::model::TradeContract;
+use cashu_escrow_common::terms::SellerPayout;
+use cdk::mint_url::MintUrl;
+use cdk::nuts::PublicKey as EcashPubkey;
+use nostr_sdk::nips::nip19::ToBech32;
+use nostr_sdk::PublicKey as NostrPubkey;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Contract templates for common kinds of trades.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ContractTemplate {
+    PhysicalGoods,
+    DigitalGoods,
+    Services,
+}
+
+impl ContractTemplate {
+    /// The template in TOML.
+    pub fn content(self) -> &'static str {
+        match self {
+            Self::PhysicalGoods => include_str!("../../templates/physical_goods.toml"),
+            Self::DigitalGoods => include_str!("../../templates/digital_goods.toml"),
+            Self::Services => include_str!("../../templates/services.toml"),
+        }
+    }
+}
+
+/// Values of the `{{name}}` placeholders of a contract file, `None` if not known.
+pub type Placeholders = HashMap<&'static str, Option<String>>;
+
+/// Reads a contract from a JSON or TOML file, see [`parse_contract`].
+pub fn read_contract_file(
+    path: &Path,
+    placeholders: &Placeholders,
+) -> anyhow::Result<TradeContract> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Could not read {}: {}", path.display(), e))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    parse_contract(&content, is_json, placeholders)
+}
+
+/// Fills in the placeholders and deserializes the contract from JSON or TOML.
+pub fn parse_contract(
+    content: &str,
+    is_json: bool,
+    placeholders: &Placeholders,
+) -> anyhow::Result<TradeContract> {
+    let content = fill_placeholders(content, placeholders)?;
+    if is_json {
+        // contracts written before the mint was agreed were meant for the mint of the wallet
+        match placeholders.get("mint_url").cloned().flatten() {
+            Some(mint_url) => Ok(TradeContract::from_json(
+                &content,
+                &MintUrl::from_str(&mint_url)?,
+            )?),
+            None => Ok(serde_json::from_str(&content)?),
+        }
+    } else {
+        Ok(toml::from_str(&content)?)
+    }
+}
+
+/// Replaces the `{{name}}` placeholders, fails for unknown ones and ones without value.
+pub fn fill_placeholders(content: &str, placeholders: &Placeholders) -> anyhow::Result<String> {
+    let mut filled = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or(anyhow::anyhow!("Unterminated placeholder in the contract"))?;
+        let name = rest[start + 2..start + end].trim();
+        let value = placeholders
+            .get(name)
+            .ok_or(anyhow::anyhow!("Unknown placeholder {{{{{}}}}}", name))?
+            .as_ref()
+            .ok_or(anyhow::anyhow!(
+                "No value for the placeholder {{{{{}}}}}, see `contract new --help`",
+                name
+            ))?;
+        filled.push_str(&rest[..start]);
+        filled.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+/// Checks that the contract can be traded with the wallet and the nostr keys of the trader.
+///
+/// All problems are reported at once, so the contract file can be fixed in one go.
+pub fn validate_contract(
+    contract: &TradeContract,
+    mode: TradeMode,
+    trader_npub: &NostrPubkey,
+    wallet: &ClientEcashWallet,
+) -> anyhow::Result<()> {
+    let mut problems = Vec::new();
+    if contract.trade_description.trim().is_empty() {
+        problems.push("The trade description is empty".to_string());
+    }
+    if contract.trade_amount == 0 {
+        problems.push("The trade amount has to be positive, e.g. set it with --amount".to_string());
+    }
+    if contract.time_limit == 0 {
+        problems.push("The time limit has to be positive".to_string());
+    }
+    if let Err(e) = contract.validate_terms() {
+        problems.push(e.to_string());
+    }
+    if contract.npubkey_buyer == contract.npubkey_seller {
+        problems.push("Buyer and seller use the same nostr key".to_string());
+    }
+    if contract.npubkey_coordinator == contract.npubkey_buyer
+        || contract.npubkey_coordinator == contract.npubkey_seller
+    {
+        problems.push("The coordinator can't be one of the traders".to_string());
+    }
+    let (own_npub, own_ecash_pubkey, partner_ecash_pubkey) = match mode {
+        TradeMode::Buyer => (
+            &contract.npubkey_buyer,
+            &contract.buyer_ecash_public_key,
+            &contract.seller_ecash_public_key,
+        ),
+        TradeMode::Seller => (
+            &contract.npubkey_seller,
+            &contract.seller_ecash_public_key,
+            &contract.buyer_ecash_public_key,
+        ),
+    };
+    if own_npub != trader_npub {
+        problems.push(format!(
+            "The contract names another {:?} than the nsec of the trader",
+            mode
+        ));
+    }
+    match EcashPubkey::from_str(own_ecash_pubkey) {
+        Ok(pubkey) if wallet.trade_secret(&pubkey).is_ok() => {}
+        _ => problems.push(format!(
+            "The {:?} trade pubkey {} isn't one of the wallet",
+            mode, own_ecash_pubkey
+        )),
+    }
+    // the partner's trade pubkey is received by `trade register` otherwise
+    if !partner_ecash_pubkey.is_empty() {
+        if EcashPubkey::from_str(partner_ecash_pubkey).is_err() {
+            problems.push(format!(
+                "Invalid trade pubkey of the partner {}",
+                partner_ecash_pubkey
+            ));
+        } else if partner_ecash_pubkey == own_ecash_pubkey {
+            problems.push("Buyer and seller use the same trade pubkey".to_string());
+        }
+    }
+    if contract.mint_url != wallet.wallet.mint_url || contract.currency_unit != wallet.wallet.unit {
+        problems.push(format!(
+            "The contract requires {} {}, the wallet uses {} {}",
+            contract.mint_url, contract.currency_unit, wallet.wallet.mint_url, wallet.wallet.unit
+        ));
+    }
+
+    if !problems.is_empty() {
+        anyhow::bail!("Invalid contract:\n  - {}", problems.join("\n  - "));
+    }
+    Ok(())
+}
+
+/// Describes the contract from the point of view of the trader, e.g. to confirm it.
+pub fn contract_summary(contract: &TradeContract, mode: TradeMode) -> String {
+    let (role, partner_role, partner_npub, partner_ecash_pubkey) = match mode {
+        TradeMode::Buyer => (
+            "buyer",
+            "Seller",
+            &contract.npubkey_seller,
+            &contract.seller_ecash_public_key,
+        ),
+        TradeMode::Seller => (
+            "seller",
+            "Buyer",
+            &contract.npubkey_buyer,
+            &contract.buyer_ecash_public_key,
+        ),
+    };
+    let fee_payer = match contract.fee_payer {
+        FeePayer::Buyer => "buyer",
+        FeePayer::Seller => "seller",
+    };
+    let partner_ecash_pubkey = if partner_ecash_pubkey.is_empty() {
+        "not known yet, received by `trade register`"
+    } else {
+        partner_ecash_pubkey
+    };
+
+    let mut summary = String::new();
+    // writing to a String can't fail
+    let _ = writeln!(summary, "You are the {} of:", role);
+    let _ = writeln!(summary, "  {}", contract.trade_description);
+    if let Some(terms) = &contract.terms {
+        for item in &terms.line_items {
+            let _ = write!(summary, "  {} x {}", item.quantity, item.description);
+            if let Some(unit_price) = item.unit_price {
+                let _ = write!(summary, " at {} {}", unit_price, contract.currency_unit);
+            }
+            summary.push('\n');
+        }
+    }
+    let _ = writeln!(
+        summary,
+        "Amount:       {} {}, the {} pays the mint fee of redeeming the escrow",
+        contract.trade_amount, contract.currency_unit, fee_payer
+    );
+    let _ = writeln!(
+        summary,
+        "Refund:       the buyer can refund the escrow {} after the registration",
+        format_duration(contract.time_limit)
+    );
+    if let Some(terms) = &contract.terms {
+        if let Some(delivery) = &terms.delivery {
+            let _ = writeln!(
+                summary,
+                "Delivery:     {:?}, {} after the registration",
+                delivery.method,
+                format_duration(delivery.deadline)
+            );
+        }
+        if let Some(return_policy) = &terms.return_policy {
+            let returns = match (return_policy.returns_accepted, return_policy.return_window) {
+                (false, _) => "not accepted".to_string(),
+                (true, None) => "accepted".to_string(),
+                (true, Some(window)) => {
+                    format!("accepted within {} after delivery", format_duration(window))
+                }
+            };
+            let _ = writeln!(summary, "Returns:      {}", returns);
+        }
+        if let SellerPayout::Lightning { address } = &terms.seller_payout {
+            let _ = writeln!(
+                summary,
+                "Payout:       to the lightning address {}",
+                address
+            );
+        }
+        if let Some(dispute_window) = terms.dispute_window {
+            let _ = writeln!(
+                summary,
+                "Disputes:     within {} after the delivery deadline",
+                format_duration(dispute_window)
+            );
+        }
+    }
+    let _ = writeln!(
+        summary,
+        "{:<14}{}",
+        format!("{}:", partner_role),
+        partner_npub.to_bech32().unwrap_or_default()
+    );
+    let _ = writeln!(summary, "  trade key:  {}", partner_ecash_pubkey);
+    let _ = writeln!(
+        summary,
+        "Coordinator:  {}",
+        contract.npubkey_coordinator.to_bech32().unwrap_or_default()
+    );
+    let _ = write!(summary, "Mint:         {}", contract.mint_url);
+    if contract.privacy.is_some() {
+        let _ = write!(
+            summary,
+            "\nPrivacy:      description and terms are only revealed to the coordinator in a dispute"
+        );
+    }
+    summary
+}
+
+/// Formats the seconds in the largest unit of days, hours or seconds which divides them.
+pub fn format_duration(secs: u64) -> String {
+    const DAY: u64 = 24 * 60 * 60;
+    const HOUR: u64 = 60 * 60;
+    let (count, unit) = match secs {
+        s if s % DAY == 0 => (s / DAY, "day"),
+        s if s % HOUR == 0 => (s / HOUR, "hour"),
+        s => (s, "second"),
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
@@ -1,93 +1,275 @@
+pub mod contract_file;
 pub mod trade_contract;
 
-use super::*;
 use cashu_escrow_client::escrow_client::TradeMode;
-use cashu_escrow_common::cli::get_user_input;
-use cdk::nuts::nut01::PublicKey as EcashPubkey;
-use nostr_sdk::prelude::*;
-use nostr_sdk::Keys as NostrKeys;
-use nostr_sdk::PublicKey as NostrPubkey;
-use std::env;
-use std::str::FromStr;
-
-#[derive(Debug)]
-struct RawCliInput {
-    buyer_npub: String,
-    seller_npub: String,
-    partner_ecash_pubkey: String,
-    coordinator_npub: String,
-    nostr_nsec: String,
-    mode: TradeMode,
+use cashu_escrow_common::model::{FeePayer, Verdict};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use contract_file::ContractTemplate;
+use std::path::PathBuf;
+
+/// Trade over the cashu escrow service, every step runs as a separate invocation.
+///
+/// Wallet and trades are kept in the data directory. Buyer and seller have to run the
+/// steps which exchange messages at about the same time, e.g. the seller has to wait
+/// with `trade fund` while the buyer funds the escrow.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Directory holding the wallet and the trades.
+    #[arg(
+        long,
+        env = "ESCROW_DATA_DIR",
+        default_value = "escrow-data",
+        global = true
+    )]
+    pub data_dir: PathBuf,
+
+    /// Nostr secret key (nsec) of the trader, not used if the data directory holds a keystore.
+    #[arg(long, env = "NOSTR_NSEC", hide_env_values = true, global = true)]
+    pub nsec: Option<String>,
+
+    /// Passphrase of the keystore, asked for if not given.
+    #[arg(long, env = "ESCROW_PASSPHRASE", hide_env_values = true, global = true)]
+    pub passphrase: Option<String>,
+
+    /// Nostr relays, comma separated.
+    #[arg(long, env = "NOSTR_RELAYS", value_delimiter = ',', global = true)]
+    pub relays: Vec<String>,
+
+    /// Mint of a new wallet, existing wallets keep their mint.
+    #[arg(long, env = "MINT_URL", global = true)]
+    pub mint_url: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Draft trade contracts.
+    #[command(subcommand)]
+    Contract(ContractCommand),
+    /// Run the steps of a trade.
+    #[command(subcommand)]
+    Trade(TradeCommand),
+    /// Inspect the ecash wallet.
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// Manage the keystore holding the nostr key and the wallet mnemonic encrypted.
+    #[command(subcommand)]
+    Keystore(KeystoreCommand),
+    /// Monitors all trades in a terminal UI and acts on them.
+    Tui,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ContractCommand {
+    /// Creates a contract and prints the trade pubkey to pass to the trade partner.
+    ///
+    /// The contract is built from the options, a JSON or TOML file or a template. The values
+    /// of the options replace the ones of the file. The placeholders `{{buyer_npub}}`,
+    /// `{{seller_npub}}`, `{{buyer_ecash_pubkey}}` and `{{seller_ecash_pubkey}}` are filled in
+    /// with the keys of the trader and the partner, `{{coordinator_npub}}` and `{{mint_url}}`
+    /// with the coordinator and the mint of the wallet.
+    New(ContractNewArgs),
+    /// Negotiates amount, time limit and description with the trade partner.
+    ///
+    /// One trader sends the drafted contract as offer, the other one waits for it. The
+    /// traders then accept, counter or reject each other's proposals. The accepted contract
+    /// is signed by both traders and replaces the draft.
+    Negotiate {
+        name: String,
+        /// Offers the drafted contract, otherwise the offer of the partner is awaited.
+        #[arg(long)]
+        offer: bool,
+        /// Seconds to wait for each message of the partner.
+        #[arg(long, default_value_t = 300)]
+        timeout: u64,
+    },
+    /// Prints a contract template, e.g. to edit it and pass it to `contract new --file`.
+    Template {
+        #[arg(value_enum)]
+        template: ContractTemplate,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct ContractNewArgs {
+    /// Name the trade is referred to by in the other commands.
+    pub name: String,
+    #[arg(long, value_enum)]
+    pub mode: ModeArg,
+    /// JSON or TOML file holding the contract, told apart by the extension.
+    #[arg(long, conflicts_with = "template")]
+    pub file: Option<PathBuf>,
+    #[arg(long, value_enum)]
+    pub template: Option<ContractTemplate>,
+    /// Nostr pubkey (npub) of the trade partner.
+    #[arg(long)]
+    pub partner_npub: Option<String>,
+    /// Trade pubkey of the trade partner, otherwise it is received by `trade register`.
+    #[arg(long)]
+    pub partner_ecash_pubkey: Option<String>,
+    /// Nostr pubkey (npub) of the escrow coordinator.
+    #[arg(long, env = "ESCROW_NPUB")]
+    pub coordinator_npub: Option<String>,
+    /// Trade amount in sat.
+    #[arg(long, required_unless_present_any = ["file", "template"])]
+    pub amount: Option<u64>,
+    #[arg(long, required_unless_present_any = ["file", "template"])]
+    pub description: Option<String>,
+    /// Seconds after the registration until the buyer can refund the escrow [default: 3 days].
+    #[arg(long)]
+    pub time_limit: Option<u64>,
+    /// Party paying the mint fee of redeeming the escrow [default: seller].
+    #[arg(long, value_enum)]
+    pub fee_payer: Option<FeePayerArg>,
+    /// Privacy mode: the coordinator only gets a hash commitment to description and terms,
+    /// which are revealed to it in a dispute. Both traders have to use it.
+    #[arg(long)]
+    pub private: bool,
+    /// Creates the contract without asking for confirmation.
+    #[arg(long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TradeCommand {
+    /// Exchanges the trade pubkeys with the trade partner, signs the contract together with
+    /// the partner and registers it at the coordinator. Both parties have to register at about
+    /// the same time, unless the contract was signed by `contract negotiate` before.
+    Register {
+        name: String,
+        /// Trade pubkey of the trade partner, checked against the one the partner announces.
+        #[arg(long)]
+        partner_ecash_pubkey: Option<String>,
+        /// Seconds to wait for the trade pubkey and the signature of the partner.
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+    },
+    /// Buyer: funds the wallet and sends the escrow token. Seller: receives and validates it.
+    Fund { name: String },
+    /// Shows the state of the trade.
+    Status { name: String },
+    /// Buyer: releases the escrow to the seller. Seller: waits for the release and redeems it.
+    Release {
+        name: String,
+        /// Seconds the seller waits for the release.
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+        /// Seller: pays the proceeds to a BOLT11 invoice or a Lightning address.
+        #[arg(long)]
+        lightning: Option<String>,
+    },
+    /// Opens a dispute at the coordinator, or responds to the one opened by the trade partner.
+    Dispute {
+        name: String,
+        /// `buyer-wins`, `seller-wins` or `split:<buyer amount>`.
+        #[arg(long, value_parser = parse_verdict)]
+        verdict: Verdict,
+        #[arg(long)]
+        statement: String,
+        /// Evidence for the coordinator, can be repeated.
+        #[arg(long)]
+        evidence: Vec<String>,
+        /// Seconds to wait for the verdict, it isn't awaited if 0.
+        #[arg(long, default_value_t = 0)]
+        wait: u64,
+    },
+    /// Submits evidence for the open dispute, e.g. a tracking number, photos or files.
+    Evidence {
+        name: String,
+        #[arg(long)]
+        message: String,
+        /// File to attach, can be repeated.
+        #[arg(long)]
+        file: Vec<PathBuf>,
+    },
+    /// Chats with the trade partner and the coordinator about the open dispute.
+    ///
+    /// Prints the messages of the chat, after sending the message if given.
+    Chat {
+        name: String,
+        #[arg(long)]
+        message: Option<String>,
+        /// Seconds to wait for the relays to return the chat history.
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// Buyer: takes back the escrowed funds after the time limit expired.
+    Refund { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WalletCommand {
+    /// Prints the balance of the wallet.
+    Balance,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeystoreCommand {
+    /// Generates new nostr keys and a new wallet mnemonic.
+    Generate,
+    /// Imports a nostr secret key, and the mnemonic of the wallet in the data directory.
+    ///
+    /// The secret key is read from --nsec or asked for. The mnemonic is removed from the
+    /// wallet file, so it is only kept encrypted.
+    Import,
+    /// Prints the nostr secret key.
+    Export {
+        /// Encrypts the secret key with a new passphrase (NIP-49).
+        #[arg(long)]
+        ncryptsec: bool,
+        /// Also prints the wallet mnemonic.
+        #[arg(long)]
+        mnemonic: bool,
+    },
+    /// Checks the passphrase and prints the nostr pubkey.
+    Unlock,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ModeArg {
+    Buyer,
+    Seller,
+}
+
+impl From<ModeArg> for TradeMode {
+    fn from(mode: ModeArg) -> Self {
+        match mode {
+            ModeArg::Buyer => TradeMode::Buyer,
+            ModeArg::Seller => TradeMode::Seller,
+        }
+    }
 }
 
-#[derive(Debug)]
-pub struct ClientCliInput {
-    pub mode: TradeMode,
-    pub trader_nostr_keys: NostrKeys,
-    pub ecash_pubkey_partner: EcashPubkey,
-    pub coordinator_nostr_pubkey: NostrPubkey,
-    pub trade_partner_nostr_pubkey: NostrPubkey,
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum FeePayerArg {
+    Buyer,
+    Seller,
 }
 
-impl RawCliInput {
-    async fn parse() -> anyhow::Result<Self> {
-        // information would be communicated OOB in production
-        let buyer_npub: String = env::var("BUYER_NPUB")?;
-        let seller_npub: String = env::var("SELLER_NPUB")?;
-        let coordinator_npub: String = env::var("ESCROW_NPUB")?;
-
-        let partner_ecash_pubkey: String;
-        let nostr_nsec: String;
-
-        let mode = match get_user_input("Select mode: (1) buyer, (2) seller: ")
-            .await?
-            .as_str()
-        {
-            "1" => {
-                nostr_nsec = env::var("BUYER_NSEC")?;
-                partner_ecash_pubkey = get_user_input("Enter seller's ecash pubkey: ").await?;
-                TradeMode::Buyer
-            }
-            "2" => {
-                nostr_nsec = env::var("SELLER_NSEC")?;
-                partner_ecash_pubkey = get_user_input("Enter buyer's ecash pubkey: ").await?;
-                TradeMode::Seller
-            }
-            _ => {
-                panic!("Wrong trading mode selected. Select either (1) buyer or (2) seller");
-            }
-        };
-        Ok(Self {
-            buyer_npub,
-            seller_npub,
-            partner_ecash_pubkey,
-            coordinator_npub,
-            nostr_nsec,
-            mode,
-        })
+impl From<FeePayerArg> for FeePayer {
+    fn from(fee_payer: FeePayerArg) -> Self {
+        match fee_payer {
+            FeePayerArg::Buyer => FeePayer::Buyer,
+            FeePayerArg::Seller => FeePayer::Seller,
+        }
     }
 }
 
-impl ClientCliInput {
-    pub async fn parse() -> anyhow::Result<Self> {
-        let raw_input = RawCliInput::parse().await?;
-        debug!("Raw parsed CLI input: {:?}", raw_input);
-
-        let ecash_pubkey_partner = EcashPubkey::from_str(&raw_input.partner_ecash_pubkey)?;
-
-        let trader_nostr_keys = NostrKeys::from_str(&raw_input.nostr_nsec)?;
-        let coordinator_nostr_pubkey = NostrPubkey::from_str(&raw_input.coordinator_npub)?;
-        let trade_partner_nostr_pubkey = match raw_input.mode {
-            TradeMode::Buyer => NostrPubkey::from_bech32(&raw_input.seller_npub)?,
-            TradeMode::Seller => NostrPubkey::from_bech32(&raw_input.buyer_npub)?,
-        };
-
-        Ok(Self {
-            mode: raw_input.mode,
-            trader_nostr_keys,
-            ecash_pubkey_partner,
-            coordinator_nostr_pubkey,
-            trade_partner_nostr_pubkey,
-        })
+pub fn parse_verdict(verdict: &str) -> Result<Verdict, String> {
+    match verdict {
+        "buyer-wins" => Ok(Verdict::BuyerWins),
+        "seller-wins" => Ok(Verdict::SellerWins),
+        _ => {
+            let buyer_amount = verdict
+                .strip_prefix("split:")
+                .ok_or("expected buyer-wins, seller-wins or split:<buyer amount>")?;
+            let buyer_amount = buyer_amount
+                .parse()
+                .map_err(|e| format!("invalid split amount: {}", e))?;
+            Ok(Verdict::Split { buyer_amount })
+        }
     }
 }
@@ -1,51 +1,104 @@
 use super::*;
 
-use cashu_escrow_client::escrow_client::TradeMode;
 use cashu_escrow_common::model::TradeContract;
+use cdk::{mint_url::MintUrl, nuts::CurrencyUnit};
+use contract_file::{parse_contract, read_contract_file, Placeholders};
 use nostr_sdk::prelude::*;
 
-pub trait FromClientCliInput {
-    fn from_client_cli_input(
-        cli_input: &ClientCliInput,
+/// Default time until the buyer can refund contracts given by options only.
+const DEFAULT_TIME_LIMIT_SECS: u64 = 3 * 24 * 60 * 60;
+
+pub trait FromContractArgs {
+    fn from_contract_args(
+        args: &ContractNewArgs,
+        trader_npub: PublicKey,
         trade_pubkey: String,
+        mint_url: MintUrl,
     ) -> anyhow::Result<TradeContract>;
 }
 
-impl FromClientCliInput for TradeContract {
-    fn from_client_cli_input(
-        cli_input: &ClientCliInput,
+impl FromContractArgs for TradeContract {
+    fn from_contract_args(
+        args: &ContractNewArgs,
+        trader_npub: PublicKey,
         trade_pubkey: String,
+        mint_url: MintUrl,
     ) -> anyhow::Result<Self> {
-        debug!("Constructing hard coded client trade contract...");
-        let npubkey_seller: PublicKey;
-        let npubkey_buyer: PublicKey;
+        let partner_npub = args
+            .partner_npub
+            .as_deref()
+            .map(PublicKey::from_bech32)
+            .transpose()?
+            .map(|npub| npub.to_bech32())
+            .transpose()?;
+        // set by `trade register` if the partner's trade pubkey isn't known yet
+        let partner_ecash_pubkey = Some(args.partner_ecash_pubkey.clone().unwrap_or_default());
+        let own_npub = Some(trader_npub.to_bech32()?);
+        let trade_pubkey = Some(trade_pubkey);
 
-        match cli_input.mode {
-            TradeMode::Buyer => {
-                npubkey_seller = cli_input.trade_partner_nostr_pubkey;
-                npubkey_buyer = cli_input.trader_nostr_keys.public_key();
-            }
-            TradeMode::Seller => {
-                npubkey_buyer = cli_input.trade_partner_nostr_pubkey;
-                npubkey_seller = cli_input.trader_nostr_keys.public_key();
-            }
-        }
+        let (buyer_npub, seller_npub, buyer_ecash_pubkey, seller_ecash_pubkey) =
+            match TradeMode::from(args.mode) {
+                TradeMode::Buyer => (own_npub, partner_npub, trade_pubkey, partner_ecash_pubkey),
+                TradeMode::Seller => (partner_npub, own_npub, partner_ecash_pubkey, trade_pubkey),
+            };
+        let placeholders = Placeholders::from([
+            ("buyer_npub", buyer_npub),
+            ("seller_npub", seller_npub),
+            ("coordinator_npub", args.coordinator_npub.clone()),
+            ("buyer_ecash_pubkey", buyer_ecash_pubkey),
+            ("seller_ecash_pubkey", seller_ecash_pubkey),
+            ("mint_url", Some(mint_url.to_string())),
+        ]);
 
-        let (ecash_pubkey_seller, ecash_pubkey_buyer) = match cli_input.mode {
-            TradeMode::Seller => (trade_pubkey, cli_input.ecash_pubkey_partner.to_string()),
-            TradeMode::Buyer => (cli_input.ecash_pubkey_partner.to_string(), trade_pubkey),
+        let mut contract = match (&args.file, args.template) {
+            (Some(path), _) => read_contract_file(path, &placeholders)?,
+            (None, Some(template)) => parse_contract(template.content(), false, &placeholders)?,
+            (None, None) => {
+                let npub = |name: &str| -> anyhow::Result<PublicKey> {
+                    let npub = placeholders[name].as_deref().ok_or(anyhow::anyhow!(
+                        "Pass the partner's npub and the coordinator's npub"
+                    ))?;
+                    Ok(PublicKey::from_bech32(npub)?)
+                };
+                TradeContract {
+                    trade_description: String::new(),
+                    trade_amount: 0,
+                    npubkey_seller: npub("seller_npub")?,
+                    npubkey_buyer: npub("buyer_npub")?,
+                    npubkey_coordinator: npub("coordinator_npub")?,
+                    time_limit: DEFAULT_TIME_LIMIT_SECS,
+                    seller_ecash_public_key: placeholders["seller_ecash_pubkey"]
+                        .clone()
+                        .unwrap_or_default(),
+                    buyer_ecash_public_key: placeholders["buyer_ecash_pubkey"]
+                        .clone()
+                        .unwrap_or_default(),
+                    mint_url,
+                    currency_unit: CurrencyUnit::Sat,
+                    allowed_keysets: None,
+                    fee_payer: FeePayer::default(),
+                    terms: None,
+                    privacy: None,
+                }
+            }
         };
-        // hardcoded trade contract
-        Ok(TradeContract {
-            trade_description:
-                "Purchase of one Watermelon for 5000 satoshi. 3 days delivery to ...".to_string(),
-            trade_amount_sat: 5000,
-            npubkey_seller,
-            npubkey_buyer,
-            npubkey_coordinator: cli_input.coordinator_nostr_pubkey,
-            time_limit: 3 * 24 * 60 * 60,
-            seller_ecash_public_key: ecash_pubkey_seller,
-            buyer_ecash_public_key: ecash_pubkey_buyer,
-        })
+
+        // the options take precedence over the file
+        if let Some(amount) = args.amount {
+            contract.trade_amount = amount;
+        }
+        if let Some(description) = &args.description {
+            contract.trade_description = description.clone();
+        }
+        if let Some(time_limit) = args.time_limit {
+            contract.time_limit = time_limit;
+        }
+        if let Some(fee_payer) = args.fee_payer {
+            contract.fee_payer = fee_payer.into();
+        }
+        if args.private && contract.privacy.is_none() {
+            contract.make_private();
+        }
+        Ok(contract)
     }
 }
@@ -0,0 +1,649 @@
+use super::*;
+
+use cashu_escrow_client::ecash::payout::{LightningDestination, PayoutStatus};
+use cashu_escrow_client::escrow_client::negotiation::{ContractNegotiation, NegotiationAnswer};
+use cashu_escrow_client::escrow_client::{
+    RegisteredEscrowClient, TokenExchangedEscrowClient, TradeMode,
+};
+use cashu_escrow_client::keystore::Keystore;
+use cashu_escrow_common::evidence::Attachment;
+use cashu_escrow_common::model::Verdict;
+use cdk::nuts::PublicKey as EcashPubkey;
+use cdk::Amount;
+use cli::contract_file::{contract_summary, validate_contract};
+use cli::trade_contract::FromContractArgs;
+use cli::{ContractNewArgs, TradeCommand, WalletCommand};
+use nostr_sdk::util::hex;
+use nostr_sdk::Keys;
+use state::{DataDir, TradeStage, TradeState};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::sync::OnceCell;
+use zeroize::Zeroizing;
+
+/// Time the buyer has to pay the funding invoice.
+const FUNDING_TIMEOUT_SECS: u64 = 10 * 60;
+
+/// Settings shared by all commands.
+pub struct Context {
+    pub data_dir: DataDir,
+    pub nsec: Option<String>,
+    pub relays: Vec<String>,
+    pub mint_url: Option<String>,
+    pub passphrase: Option<Zeroizing<String>>,
+    /// Unlocked when first needed, `None` if the data directory holds no keystore.
+    pub keystore: OnceCell<Option<Keystore>>,
+}
+
+impl Context {
+    /// The nostr keys of the keystore, or the ones given by `--nsec` if there is no keystore.
+    pub async fn keys(&self) -> anyhow::Result<Keys> {
+        if let Some(keystore) = self.keystore().await? {
+            return Ok(keystore.nostr_keys().clone());
+        }
+        let nsec = self.nsec.as_ref().ok_or(anyhow::anyhow!(
+            "The command needs the nostr secret key, create a keystore with `keystore generate` \
+             or `keystore import`, or pass it with --nsec or NOSTR_NSEC"
+        ))?;
+        Ok(Keys::from_str(nsec)?)
+    }
+
+    /// Unlocks the keystore of the data directory, asks for the passphrase if it wasn't given.
+    pub async fn keystore(&self) -> anyhow::Result<Option<&Keystore>> {
+        let keystore = self
+            .keystore
+            .get_or_try_init(|| async {
+                let Some(encrypted) = self.data_dir.load_keystore()? else {
+                    return anyhow::Ok(None);
+                };
+                let passphrase = self.passphrase(false).await?;
+                Ok(Some(encrypted.unlock(&passphrase)?))
+            })
+            .await?;
+        Ok(keystore.as_ref())
+    }
+
+    /// The passphrase of the keystore, a new one is asked for twice.
+    pub async fn passphrase(&self, new: bool) -> anyhow::Result<Zeroizing<String>> {
+        if let Some(passphrase) = &self.passphrase {
+            return Ok(passphrase.clone());
+        }
+        if !new {
+            return keystore::read_passphrase("Keystore passphrase: ").await;
+        }
+        let passphrase = keystore::read_passphrase("New keystore passphrase: ").await?;
+        if passphrase.is_empty() {
+            anyhow::bail!("The passphrase must not be empty");
+        }
+        if keystore::read_passphrase("Repeat the passphrase: ").await? != passphrase {
+            anyhow::bail!("The passphrases don't match");
+        }
+        Ok(passphrase)
+    }
+
+    pub async fn nostr_client(&self) -> anyhow::Result<NostrClient> {
+        let relays: Vec<String> = self
+            .relays
+            .iter()
+            .map(|relay| relay.trim().to_string())
+            .filter(|relay| !relay.is_empty())
+            .collect();
+        if relays.is_empty() {
+            anyhow::bail!("No nostr relays given, pass them with --relays or NOSTR_RELAYS");
+        }
+        Ok(NostrClient::new(self.keys().await?, relays).await?)
+    }
+
+    pub async fn wallet(&self) -> anyhow::Result<ClientEcashWallet> {
+        self.data_dir
+            .open_wallet(self.mint_url.as_deref(), self.keystore().await?)
+            .await
+    }
+
+    /// Resumes the registered trade with the wallet and a new nostr connection.
+    pub async fn registered_client(
+        &self,
+        trade: &TradeState,
+    ) -> anyhow::Result<RegisteredEscrowClient> {
+        Ok(RegisteredEscrowClient::new(
+            self.nostr_client().await?,
+            self.wallet().await?,
+            trade.contract.clone(),
+            trade.mode,
+            trade.registration()?.clone(),
+        ))
+    }
+
+    pub async fn token_exchanged_client(
+        &self,
+        trade: &TradeState,
+    ) -> anyhow::Result<TokenExchangedEscrowClient> {
+        Ok(TokenExchangedEscrowClient::new(
+            self.registered_client(trade).await?,
+            trade.escrow_token()?,
+        ))
+    }
+}
+
+pub async fn new_contract(ctx: &Context, args: ContractNewArgs) -> anyhow::Result<()> {
+    let keys = ctx.keys().await?;
+    let wallet = ctx.wallet().await?;
+    let trade_pubkey = wallet.new_trade_pubkey()?;
+    let contract = TradeContract::from_contract_args(
+        &args,
+        keys.public_key(),
+        trade_pubkey.to_string(),
+        wallet.wallet.mint_url.clone(),
+    )?;
+    let mode = args.mode.into();
+    validate_contract(&contract, mode, &keys.public_key(), &wallet)?;
+
+    println!("{}", contract_summary(&contract, mode));
+    if !args.yes
+        && !get_user_input("Create this contract? [y/N]: ")
+            .await?
+            .eq_ignore_ascii_case("y")
+    {
+        println!("Discarded the contract");
+        return Ok(());
+    }
+    ctx.data_dir
+        .create_trade(&args.name, &TradeState::new(mode, contract))?;
+    println!("Created trade {}", args.name);
+    println!("The trade pubkeys are exchanged with the trade partner by `trade register`");
+    Ok(())
+}
+
+pub async fn negotiate_contract(
+    ctx: &Context,
+    name: &str,
+    offer: bool,
+    timeout: u64,
+) -> anyhow::Result<()> {
+    let mut trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Drafted])?;
+    let mut init_client = InitEscrowClient::new(
+        ctx.nostr_client().await?,
+        ctx.wallet().await?,
+        trade.contract.clone(),
+        trade.mode,
+    );
+    if EcashPubkey::from_str(partner_ecash_pubkey(&trade)).is_err() {
+        exchange_ecash_pubkeys(&mut init_client, timeout).await?;
+    }
+
+    let mut negotiation = if offer {
+        println!("Offering the contract to the trade partner...");
+        ContractNegotiation::offer(&init_client).await?
+    } else {
+        println!("Waiting for the offer of the trade partner...");
+        ContractNegotiation::receive_offer(&mut init_client, timeout).await?
+    };
+    let signatures = loop {
+        if negotiation.proposed_by_us() {
+            match negotiation
+                .receive_answer(&mut init_client, timeout)
+                .await?
+            {
+                NegotiationAnswer::Accepted(signatures) => break signatures,
+                NegotiationAnswer::Countered => println!("The trade partner proposed other terms"),
+                NegotiationAnswer::Rejected(reason) => {
+                    anyhow::bail!("The trade partner rejected the contract: {}", reason)
+                }
+            }
+            continue;
+        }
+        println!("{}", contract_summary(negotiation.proposal(), trade.mode));
+        let choice = get_user_input("[a]ccept, [c]ounter or [r]eject the contract: ").await?;
+        match choice.to_lowercase().as_str() {
+            "a" => break negotiation.accept(&mut init_client).await?,
+            "c" => {
+                let contract = ask_counter_terms(negotiation.proposal()).await?;
+                negotiation.counter(&init_client, contract).await?;
+                println!("Waiting for the answer of the trade partner...");
+            }
+            "r" => {
+                let reason = get_user_input("Reason: ").await?;
+                negotiation.reject(&init_client, reason).await?;
+                println!("Rejected the contract");
+                return Ok(());
+            }
+            _ => {}
+        }
+    };
+
+    trade.contract = init_client.escrow_contract().clone();
+    trade.signatures = Some(signatures);
+    trade.stage = TradeStage::Negotiated;
+    ctx.data_dir.save_trade(name, &trade)?;
+    println!(
+        "Agreed on the contract {}, register it with `trade register {}`",
+        hex::encode(trade.contract.contract_hash()?),
+        name
+    );
+    Ok(())
+}
+
+/// Asks for the terms of a counter-offer, the ones left empty are kept.
+async fn ask_counter_terms(proposal: &TradeContract) -> anyhow::Result<TradeContract> {
+    let mut contract = proposal.clone();
+    let amount = get_user_input(&format!(
+        "Amount in {} [{}]: ",
+        contract.currency_unit, contract.trade_amount
+    ))
+    .await?;
+    if !amount.is_empty() {
+        contract.trade_amount = amount.parse()?;
+    }
+    let time_limit = get_user_input(&format!(
+        "Time limit in seconds [{}]: ",
+        contract.time_limit
+    ))
+    .await?;
+    if !time_limit.is_empty() {
+        contract.time_limit = time_limit.parse()?;
+    }
+    let description =
+        get_user_input(&format!("Description [{}]: ", contract.trade_description)).await?;
+    if !description.is_empty() {
+        contract.trade_description = description;
+    }
+    Ok(contract)
+}
+
+pub async fn run_trade_command(ctx: &Context, command: TradeCommand) -> anyhow::Result<()> {
+    match command {
+        TradeCommand::Register {
+            name,
+            partner_ecash_pubkey,
+            timeout,
+        } => register_trade(ctx, &name, partner_ecash_pubkey, timeout).await,
+        TradeCommand::Fund { name } => fund_trade(ctx, &name).await,
+        TradeCommand::Status { name } => print_status(&ctx.data_dir.load_trade(&name)?),
+        TradeCommand::Release {
+            name,
+            timeout,
+            lightning,
+        } => release_trade(ctx, &name, timeout, lightning).await,
+        TradeCommand::Dispute {
+            name,
+            verdict,
+            statement,
+            evidence,
+            wait,
+        } => dispute_trade(ctx, &name, verdict, statement, evidence, wait).await,
+        TradeCommand::Evidence {
+            name,
+            message,
+            file,
+        } => submit_evidence(ctx, &name, message, &file).await,
+        TradeCommand::Chat {
+            name,
+            message,
+            timeout,
+        } => dispute_chat(ctx, &name, message, timeout).await,
+        TradeCommand::Refund { name } => refund_trade(ctx, &name).await,
+    }
+}
+
+pub async fn run_wallet_command(ctx: &Context, command: WalletCommand) -> anyhow::Result<()> {
+    match command {
+        WalletCommand::Balance => {
+            let wallet = ctx.wallet().await?;
+            println!(
+                "{} {} at {}",
+                wallet.wallet.total_balance().await?,
+                wallet.wallet.unit,
+                wallet.wallet.mint_url
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn register_trade(
+    ctx: &Context,
+    name: &str,
+    partner_ecash_pubkey: Option<String>,
+    timeout: u64,
+) -> anyhow::Result<()> {
+    let mut trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Drafted, TradeStage::Negotiated])?;
+    if let Some(partner_ecash_pubkey) = partner_ecash_pubkey {
+        if trade.stage == TradeStage::Negotiated {
+            anyhow::bail!("The negotiated contract already holds the partner's trade pubkey");
+        }
+        EcashPubkey::from_str(&partner_ecash_pubkey)?;
+        match trade.mode {
+            TradeMode::Buyer => trade.contract.seller_ecash_public_key = partner_ecash_pubkey,
+            TradeMode::Seller => trade.contract.buyer_ecash_public_key = partner_ecash_pubkey,
+        }
+    }
+
+    let mut init_client = InitEscrowClient::new(
+        ctx.nostr_client().await?,
+        ctx.wallet().await?,
+        trade.contract.clone(),
+        trade.mode,
+    );
+    // the keys of a negotiated contract were exchanged and signed before
+    if trade.stage == TradeStage::Drafted {
+        exchange_ecash_pubkeys(&mut init_client, timeout).await?;
+        println!("Signing the contract with the trade partner...");
+        let signatures = init_client.exchange_contract_signatures(timeout).await?;
+        // kept, so a failed registration can be retried with the signed contract
+        trade.contract = init_client.escrow_contract().clone();
+        trade.signatures = Some(signatures);
+        trade.stage = TradeStage::Negotiated;
+        ctx.data_dir.save_trade(name, &trade)?;
+    } else if let Some(signatures) = &trade.signatures {
+        init_client.set_contract_signatures(signatures.clone())?;
+    }
+
+    let registered = init_client.register_trade().await?;
+    let registration = registered.escrow_registration().clone();
+    println!("Registered escrow {}", registration.escrow_id_hex);
+    trade.registration = Some(registration);
+    trade.stage = TradeStage::Registered;
+    ctx.data_dir.save_trade(name, &trade)
+}
+
+async fn fund_trade(ctx: &Context, name: &str) -> anyhow::Result<()> {
+    let mut trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Registered])?;
+    if trade.mode == TradeMode::Buyer {
+        fund_escrow(&ctx.wallet().await?, &trade.contract).await?;
+    } else {
+        println!("Waiting for the escrow token of the buyer...");
+    }
+    let client = ctx
+        .registered_client(&trade)
+        .await?
+        .exchange_trade_token()
+        .await?;
+    trade.set_escrow_token(client.escrow_token());
+    trade.stage = TradeStage::Funded;
+    ctx.data_dir.save_trade(name, &trade)?;
+    println!("Escrow funded with {}", client.escrow_token().value()?);
+    Ok(())
+}
+
+fn print_status(trade: &TradeState) -> anyhow::Result<()> {
+    println!("{}", contract_summary(&trade.contract, trade.mode));
+    println!("Stage:        {:?}", trade.stage);
+    if trade.signatures.is_some() {
+        println!("Signed:       by buyer and seller");
+    }
+    if let Some(registration) = &trade.registration {
+        println!("Escrow id:    {}", registration.escrow_id_hex);
+    }
+    if let Some(refundable_at) = trade.refundable_at() {
+        println!("Refundable:   after {}", refundable_at);
+    }
+    if let Some(escrow_token) = &trade.escrow_token {
+        println!("Escrow:       {}", escrow_token);
+    }
+    Ok(())
+}
+
+async fn release_trade(
+    ctx: &Context,
+    name: &str,
+    timeout_secs: u64,
+    lightning: Option<String>,
+) -> anyhow::Result<()> {
+    let mut trade = ctx.data_dir.load_trade(name)?;
+    if trade.mode == TradeMode::Buyer {
+        release_escrow(ctx, name).await?;
+        println!("Released the escrow to the seller");
+        return Ok(());
+    }
+    trade.require_stage(&[TradeStage::Funded])?;
+    let mut client = ctx.token_exchanged_client(&trade).await?;
+    match lightning {
+        None => {
+            let redeemed = client.receive_release(timeout_secs).await?;
+            println!("Redeemed {} from the escrow", redeemed);
+        }
+        Some(destination) => {
+            let destination = if destination.contains('@') {
+                LightningDestination::address(&destination)
+            } else {
+                LightningDestination::Invoice(destination)
+            };
+            let settlement = client
+                .receive_release_to_lightning(timeout_secs, &destination)
+                .await?;
+            println!("Redeemed {} from the escrow", settlement.redeemed);
+            match settlement.payout {
+                PayoutStatus::Paid {
+                    amount, fee_paid, ..
+                } => println!("Paid out {} with a fee of {}", amount, fee_paid),
+                PayoutStatus::Pending { quote_id } => {
+                    println!("The payout is pending, melt quote {}", quote_id)
+                }
+                PayoutStatus::Failed { reason } => {
+                    println!(
+                        "The payout failed, the proceeds stay in the wallet: {}",
+                        reason
+                    )
+                }
+            }
+        }
+    }
+    trade.stage = TradeStage::Settled;
+    ctx.data_dir.save_trade(name, &trade)
+}
+
+async fn dispute_trade(
+    ctx: &Context,
+    name: &str,
+    verdict: Verdict,
+    statement: String,
+    evidence: Vec<String>,
+    wait_secs: u64,
+) -> anyhow::Result<()> {
+    let mut client = open_dispute(ctx, name, verdict, statement, evidence).await?;
+    println!("Sent the dispute claim to the coordinator");
+
+    if wait_secs > 0 {
+        let (verdict, redeemed) = client.receive_verdict(wait_secs).await?;
+        println!(
+            "The coordinator decided {:?}, redeemed {}",
+            verdict.verdict, redeemed
+        );
+        let mut trade = ctx.data_dir.load_trade(name)?;
+        trade.stage = TradeStage::Settled;
+        ctx.data_dir.save_trade(name, &trade)?;
+    }
+    Ok(())
+}
+
+async fn submit_evidence(
+    ctx: &Context,
+    name: &str,
+    message: String,
+    files: &[PathBuf],
+) -> anyhow::Result<()> {
+    let trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Disputed])?;
+    let attachments = files
+        .iter()
+        .map(|path| {
+            Ok(Attachment {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                mime_type: mime_type(path).to_string(),
+                content: std::fs::read(path)
+                    .map_err(|e| anyhow::anyhow!("Could not read {}: {}", path.display(), e))?,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    ctx.token_exchanged_client(&trade)
+        .await?
+        .submit_evidence(message, &attachments)
+        .await?;
+    println!(
+        "Sent the evidence with {} attachments to the coordinator",
+        attachments.len()
+    );
+    Ok(())
+}
+
+async fn dispute_chat(
+    ctx: &Context,
+    name: &str,
+    message: Option<String>,
+    timeout_secs: u64,
+) -> anyhow::Result<()> {
+    let trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Disputed])?;
+    let chat = ctx.token_exchanged_client(&trade).await?.dispute_chat();
+    if let Some(message) = message {
+        chat.send(&message).await?;
+    }
+    let contract = &trade.contract;
+    for message in chat.history(timeout_secs).await? {
+        let sender = if message.sender == contract.npubkey_buyer {
+            "buyer"
+        } else if message.sender == contract.npubkey_seller {
+            "seller"
+        } else {
+            "coordinator"
+        };
+        println!(
+            "[{}] {}: {}",
+            message.created_at.to_human_datetime(),
+            sender,
+            message.content
+        );
+    }
+    Ok(())
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("txt") => "text/plain",
+        Some("json") => "application/json",
+        Some("pdf") => "application/pdf",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn refund_trade(ctx: &Context, name: &str) -> anyhow::Result<()> {
+    let redeemed = refund_escrow(ctx, name).await?;
+    println!("Refunded {} from the escrow", redeemed);
+    Ok(())
+}
+
+/// Releases the escrow of the trade to the seller, only the buyer can release it.
+pub async fn release_escrow(ctx: &Context, name: &str) -> anyhow::Result<()> {
+    let mut trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Funded])?;
+    ctx.token_exchanged_client(&trade)
+        .await?
+        .release_escrow()
+        .await?;
+    trade.stage = TradeStage::Released;
+    ctx.data_dir.save_trade(name, &trade)
+}
+
+/// Sends a dispute claim for the trade to the coordinator.
+///
+/// Returns the client, e.g. to wait for the verdict.
+pub async fn open_dispute(
+    ctx: &Context,
+    name: &str,
+    verdict: Verdict,
+    statement: String,
+    evidence: Vec<String>,
+) -> anyhow::Result<TokenExchangedEscrowClient> {
+    let mut trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Funded, TradeStage::Disputed])?;
+    let client = ctx.token_exchanged_client(&trade).await?;
+    client.open_dispute(verdict, statement, evidence).await?;
+    trade.stage = TradeStage::Disputed;
+    ctx.data_dir.save_trade(name, &trade)?;
+    Ok(client)
+}
+
+/// Takes back the escrowed funds of the trade, returns the refunded amount.
+pub async fn refund_escrow(ctx: &Context, name: &str) -> anyhow::Result<Amount> {
+    let mut trade = ctx.data_dir.load_trade(name)?;
+    trade.require_stage(&[TradeStage::Funded, TradeStage::Disputed])?;
+    let redeemed = ctx
+        .token_exchanged_client(&trade)
+        .await?
+        .refund_escrow()
+        .await?;
+    trade.stage = TradeStage::Refunded;
+    ctx.data_dir.save_trade(name, &trade)?;
+    Ok(redeemed)
+}
+
+async fn exchange_ecash_pubkeys(
+    init_client: &mut InitEscrowClient,
+    timeout: u64,
+) -> anyhow::Result<()> {
+    println!("Exchanging the trade pubkeys with the trade partner...");
+    let partner_ecash_pubkey = init_client.exchange_ecash_pubkeys(timeout).await?;
+    println!(
+        "Received the verified trade pubkey of the partner: {}",
+        partner_ecash_pubkey
+    );
+    Ok(())
+}
+
+fn partner_ecash_pubkey(trade: &TradeState) -> &str {
+    match trade.mode {
+        TradeMode::Buyer => &trade.contract.seller_ecash_public_key,
+        TradeMode::Seller => &trade.contract.buyer_ecash_public_key,
+    }
+}
+
+/// Tops up the wallet until it covers the escrow of the contract, including the mint fees.
+async fn fund_escrow(
+    escrow_wallet: &ClientEcashWallet,
+    escrow_contract: &TradeContract,
+) -> anyhow::Result<()> {
+    let costs = escrow_wallet.estimate_escrow_costs(escrow_contract).await?;
+    info!("Escrow costs: {}", costs);
+    loop {
+        let balance = escrow_wallet.wallet.total_balance().await?;
+        if balance >= costs.buyer_total {
+            return Ok(());
+        }
+        let missing = costs.buyer_total - balance;
+        match get_user_input(&format!(
+            "Missing {} to fund the escrow. Fund with (1) Lightning invoice, (2) cashu token: ",
+            missing
+        ))
+        .await?
+        .as_str()
+        {
+            "1" => {
+                let funding = escrow_wallet.request_funding(missing).await?;
+                println!("Pay this invoice to fund the escrow: {}", funding.invoice);
+                escrow_wallet
+                    .wait_for_funding(&funding.quote_id, FUNDING_TIMEOUT_SECS)
+                    .await?;
+            }
+            "2" => {
+                let token = get_user_input("Paste the cashu token: ").await?;
+                if let Err(e) = escrow_wallet.fund_from_token(&token).await {
+                    error!("Could not receive the token: {}", e);
+                }
+            }
+            _ => warn!("Select either (1) or (2)"),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::*;
+
+use cashu_escrow_client::ecash::store::FileStateStore;
+use cashu_escrow_client::keystore::Keystore;
+use cli::KeystoreCommand;
+use nostr_sdk::ToBech32;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal;
+use std::io::{self, Write};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+/// Maximum length of a passphrase, reserved up front so the input isn't copied on reallocation.
+const MAX_INPUT_LEN: usize = 1024;
+
+pub async fn run_keystore_command(ctx: &Context, command: KeystoreCommand) -> anyhow::Result<()> {
+    match command {
+        KeystoreCommand::Generate => generate_keystore(ctx).await,
+        KeystoreCommand::Import => import_keystore(ctx).await,
+        KeystoreCommand::Export {
+            ncryptsec,
+            mnemonic,
+        } => export_keystore(ctx, ncryptsec, mnemonic).await,
+        KeystoreCommand::Unlock => {
+            let keystore = unlock(ctx).await?;
+            println!("Unlocked the keystore");
+            println!(
+                "Nostr pubkey: {}",
+                keystore.nostr_keys().public_key().to_bech32()?
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn generate_keystore(ctx: &Context) -> anyhow::Result<()> {
+    require_no_keystore(ctx)?;
+    if ctx.data_dir.wallet_path().exists() {
+        anyhow::bail!(
+            "The data directory already holds a wallet, take over its mnemonic with `keystore import`"
+        );
+    }
+    let keystore = Keystore::generate()?;
+    let passphrase = ctx.passphrase(true).await?;
+    ctx.data_dir.create_keystore(&keystore.lock(&passphrase)?)?;
+    println!(
+        "Created a keystore at {}",
+        ctx.data_dir.keystore_path().display()
+    );
+    println!(
+        "Nostr pubkey: {}",
+        keystore.nostr_keys().public_key().to_bech32()?
+    );
+    println!("Back up the wallet mnemonic, it is shown by `keystore export --mnemonic`");
+    Ok(())
+}
+
+/// Imports the nostr secret key and the mnemonic of an existing wallet.
+async fn import_keystore(ctx: &Context) -> anyhow::Result<()> {
+    require_no_keystore(ctx)?;
+    let nsec = match &ctx.nsec {
+        Some(nsec) => Zeroizing::new(nsec.clone()),
+        None => read_passphrase("Nostr secret key (nsec): ").await?,
+    };
+    let wallet_path = ctx.data_dir.wallet_path();
+    let store = Arc::new(FileStateStore::new(&wallet_path));
+    let mnemonic = if wallet_path.exists() {
+        // takes over the mnemonic which is still saved in the wallet file
+        ClientEcashWallet::open(store.clone()).await?.mnemonic()
+    } else {
+        read_passphrase("Wallet mnemonic, empty to generate a new one: ").await?
+    };
+    let keystore = Keystore::import(&nsec, Some(mnemonic.as_str()).filter(|m| !m.is_empty()))?;
+    let passphrase = ctx.passphrase(true).await?;
+    ctx.data_dir.create_keystore(&keystore.lock(&passphrase)?)?;
+    println!(
+        "Created a keystore at {}",
+        ctx.data_dir.keystore_path().display()
+    );
+    if wallet_path.exists() {
+        ClientEcashWallet::open_with_keystore(store, &keystore).await?;
+        println!("Removed the mnemonic from {}", wallet_path.display());
+    }
+    println!(
+        "Nostr pubkey: {}",
+        keystore.nostr_keys().public_key().to_bech32()?
+    );
+    Ok(())
+}
+
+async fn export_keystore(ctx: &Context, ncryptsec: bool, mnemonic: bool) -> anyhow::Result<()> {
+    let keystore = unlock(ctx).await?;
+    if ncryptsec {
+        // the exported key is encrypted with a passphrase of its own
+        let passphrase = ctx.passphrase(true).await?;
+        println!("{}", keystore.export_ncryptsec(&passphrase)?);
+    } else {
+        println!("{}", keystore.export_nsec()?.as_str());
+    }
+    if mnemonic {
+        println!("{}", keystore.mnemonic().as_str());
+    }
+    Ok(())
+}
+
+async fn unlock(ctx: &Context) -> anyhow::Result<&Keystore> {
+    ctx.keystore().await?.ok_or(anyhow::anyhow!(
+        "No keystore in the data directory, create one with `keystore generate` or `keystore import`"
+    ))
+}
+
+fn require_no_keystore(ctx: &Context) -> anyhow::Result<()> {
+    let path = ctx.data_dir.keystore_path();
+    if path.exists() {
+        anyhow::bail!("There already is a keystore at {}", path.display());
+    }
+    Ok(())
+}
+
+/// Reads a secret from the terminal without echoing it.
+pub async fn read_passphrase(prompt: &str) -> anyhow::Result<Zeroizing<String>> {
+    let prompt = prompt.to_owned();
+    tokio::task::spawn_blocking(move || {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+        terminal::enable_raw_mode()?;
+        let input = read_hidden_line();
+        terminal::disable_raw_mode()?;
+        println!();
+        input
+    })
+    .await?
+}
+
+fn read_hidden_line() -> anyhow::Result<Zeroizing<String>> {
+    let mut input = Zeroizing::new(String::with_capacity(MAX_INPUT_LEN));
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(input),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                anyhow::bail!("Cancelled")
+            }
+            KeyCode::Char(c) if input.len() < MAX_INPUT_LEN - 4 => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            _ => {}
+        }
+    }
+}
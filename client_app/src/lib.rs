@@ -0,0 +1,19 @@
+//! Command line and terminal client for trading over the escrow service.
+//!
+//! The binary only parses the arguments and dispatches to the [`commands`], the terminal UI
+//! lives in [`tui`].
+
+pub mod cli;
+pub mod commands;
+pub mod keystore;
+pub mod state;
+pub mod tui;
+
+use cashu_escrow_client::ecash::ClientEcashWallet;
+use cashu_escrow_client::escrow_client::InitEscrowClient;
+use cashu_escrow_common::cli::get_user_input;
+use cashu_escrow_common::model::TradeContract;
+use cashu_escrow_common::nostr::NostrClient;
+use commands::Context;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
@@ -0,0 +1,223 @@
+use cashu_escrow_client::ecash::store::FileStateStore;
+use cashu_escrow_client::ecash::ClientEcashWallet;
+use cashu_escrow_client::escrow_client::TradeMode;
+use cashu_escrow_client::keystore::{EncryptedKeystore, Keystore};
+use cashu_escrow_common::model::{ContractSignatures, EscrowRegistration, TradeContract};
+use cashu_escrow_common::token::{decode_token, encode_token};
+use cdk::nuts::{CurrencyUnit, Token};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Progress of a trade, persisted between the invocations of the CLI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStage {
+    Drafted,
+    /// Buyer and seller agreed on the contract and signed it.
+    Negotiated,
+    Registered,
+    /// The escrow token was sent by the buyer or received by the seller.
+    Funded,
+    Released,
+    Disputed,
+    Settled,
+    Refunded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeState {
+    pub mode: TradeMode,
+    pub stage: TradeStage,
+    pub contract: TradeContract,
+    /// Signatures of buyer and seller, once the contract was negotiated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signatures: Option<ContractSignatures>,
+    pub registration: Option<EscrowRegistration>,
+    /// Encoded `cashuB` token, so it can be inspected in any cashu wallet.
+    pub escrow_token: Option<String>,
+}
+
+impl TradeState {
+    pub fn new(mode: TradeMode, contract: TradeContract) -> Self {
+        Self {
+            mode,
+            stage: TradeStage::Drafted,
+            contract,
+            signatures: None,
+            registration: None,
+            escrow_token: None,
+        }
+    }
+
+    pub fn registration(&self) -> anyhow::Result<&EscrowRegistration> {
+        self.registration
+            .as_ref()
+            .ok_or(anyhow::anyhow!("The trade isn't registered yet"))
+    }
+
+    pub fn escrow_token(&self) -> anyhow::Result<Token> {
+        let encoded = self
+            .escrow_token
+            .as_ref()
+            .ok_or(anyhow::anyhow!("The escrow isn't funded yet"))?;
+        Ok(decode_token(encoded)?)
+    }
+
+    pub fn set_escrow_token(&mut self, escrow_token: &Token) {
+        self.escrow_token = Some(encode_token(escrow_token));
+    }
+
+    /// Fails unless the trade is in one of the stages, e.g. before running a command.
+    pub fn require_stage(&self, stages: &[TradeStage]) -> anyhow::Result<()> {
+        if !stages.contains(&self.stage) {
+            anyhow::bail!(
+                "The trade is {:?}, the command needs it to be one of {:?}",
+                self.stage,
+                stages
+            );
+        }
+        Ok(())
+    }
+
+    /// Unix timestamp from which on the buyer can refund the escrow, once the trade is registered.
+    pub fn refundable_at(&self) -> Option<u64> {
+        self.registration
+            .as_ref()
+            .map(|registration| registration.escrow_start_time.as_u64() + self.contract.time_limit)
+    }
+}
+
+/// Directory holding the wallet, the keystore and one JSON file per trade.
+pub struct DataDir {
+    path: PathBuf,
+}
+
+impl DataDir {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(path.join("trades"))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Opens the wallet, a new one is created for the mint if there is none yet.
+    ///
+    /// With a keystore the wallet uses its mnemonic, which then isn't saved to the wallet file.
+    pub async fn open_wallet(
+        &self,
+        mint_url: Option<&str>,
+        keystore: Option<&Keystore>,
+    ) -> anyhow::Result<ClientEcashWallet> {
+        let wallet_path = self.wallet_path();
+        let store = Arc::new(FileStateStore::new(&wallet_path));
+        if wallet_path.exists() {
+            return Ok(match keystore {
+                Some(keystore) => ClientEcashWallet::open_with_keystore(store, keystore).await?,
+                None => ClientEcashWallet::open(store).await?,
+            });
+        }
+        let mint_url = mint_url.ok_or(anyhow::anyhow!(
+            "No wallet in {}, pass a mint url to create one",
+            self.path.display()
+        ))?;
+        let wallet = match keystore {
+            Some(keystore) => {
+                ClientEcashWallet::create_with_keystore(
+                    mint_url,
+                    CurrencyUnit::Sat,
+                    keystore,
+                    store,
+                )
+                .await?
+            }
+            None => ClientEcashWallet::create(mint_url, CurrencyUnit::Sat, store).await?,
+        };
+        println!(
+            "Created a new wallet at {}, keep a backup of it",
+            wallet_path.display()
+        );
+        Ok(wallet)
+    }
+
+    pub fn wallet_path(&self) -> PathBuf {
+        self.path.join("wallet.json")
+    }
+
+    /// Returns `None` if no keystore was generated or imported yet.
+    pub fn load_keystore(&self) -> anyhow::Result<Option<EncryptedKeystore>> {
+        match fs::read_to_string(self.keystore_path()) {
+            Ok(json) => Ok(Some(EncryptedKeystore::from_json(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves a new keystore, an existing one is never overwritten.
+    pub fn create_keystore(&self, keystore: &EncryptedKeystore) -> anyhow::Result<()> {
+        let path = self.keystore_path();
+        if path.exists() {
+            anyhow::bail!("There already is a keystore at {}", path.display());
+        }
+        fs::write(&path, keystore.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn keystore_path(&self) -> PathBuf {
+        self.path.join("keystore.json")
+    }
+
+    pub fn load_trade(&self, name: &str) -> anyhow::Result<TradeState> {
+        let path = self.trade_path(name)?;
+        let state = fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Could not read trade {}: {}", name, e))?;
+        Ok(serde_json::from_str(&state)?)
+    }
+
+    /// All trades, sorted by their name.
+    pub fn list_trades(&self) -> anyhow::Result<Vec<(String, TradeState)>> {
+        let mut trades = Vec::new();
+        for entry in fs::read_dir(self.path.join("trades"))? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    trades.push((name.to_string(), self.load_trade(name)?));
+                }
+            }
+        }
+        trades.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(trades)
+    }
+
+    /// Saves a new trade, fails if there is one with the same name.
+    pub fn create_trade(&self, name: &str, trade: &TradeState) -> anyhow::Result<()> {
+        if self.trade_path(name)?.exists() {
+            anyhow::bail!("Trade {} already exists", name);
+        }
+        self.save_trade(name, trade)
+    }
+
+    pub fn save_trade(&self, name: &str, trade: &TradeState) -> anyhow::Result<()> {
+        let path = self.trade_path(name)?;
+        // write to a temporary file first, like the wallet store
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(trade)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn trade_path(&self, name: &str) -> anyhow::Result<PathBuf> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("Trade names may only contain letters, digits, '-' and '_'");
+        }
+        Ok(self.path.join("trades").join(format!("{}.json", name)))
+    }
+}
@@ -0,0 +1,218 @@
+use super::*;
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::widgets::TableState;
+use std::collections::VecDeque;
+
+/// Number of lines kept in the message log.
+const LOG_SIZE: usize = 200;
+
+/// Action on a trade, run after the user confirmed it.
+#[derive(Debug, Clone)]
+pub enum Action {
+    Release {
+        name: String,
+    },
+    Dispute {
+        name: String,
+        verdict: Verdict,
+        statement: String,
+    },
+    Refund {
+        name: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum Dialog {
+    Confirm {
+        prompt: String,
+        action: Action,
+    },
+    /// First step of a dispute, asks for the requested verdict.
+    DisputeVerdict {
+        name: String,
+        input: String,
+    },
+    /// Second step of a dispute, asks for the statement.
+    DisputeStatement {
+        name: String,
+        verdict: Verdict,
+        input: String,
+    },
+}
+
+pub struct App {
+    pub trades: Vec<(String, TradeState)>,
+    pub table_state: TableState,
+    pub log: VecDeque<String>,
+    pub balance: Option<String>,
+    pub dialog: Option<Dialog>,
+    pub should_quit: bool,
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self {
+            trades: Vec::new(),
+            table_state: TableState::default().with_selected(0),
+            log: VecDeque::new(),
+            balance: None,
+            dialog: None,
+            should_quit: false,
+        }
+    }
+
+    pub fn set_trades(&mut self, trades: Vec<(String, TradeState)>) {
+        let selected = self.table_state.selected().unwrap_or_default();
+        self.table_state
+            .select(Some(selected.min(trades.len().saturating_sub(1))));
+        self.trades = trades;
+    }
+
+    pub fn selected_trade(&self) -> Option<&(String, TradeState)> {
+        self.trades.get(self.table_state.selected()?)
+    }
+
+    pub fn push_log(&mut self, line: String) {
+        let time = Timestamp::now().to_human_datetime();
+        // keep the time of day of the ISO 8601 date
+        let time = time.get(11..19).unwrap_or(&time);
+        if self.log.len() == LOG_SIZE {
+            self.log.pop_front();
+        }
+        self.log.push_back(format!("{} {}", time, line));
+    }
+
+    /// Handles a key press, returns the action to run if one was confirmed.
+    pub fn on_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        match self.dialog.take() {
+            Some(dialog) => self.on_dialog_key(dialog, key.code),
+            None => {
+                self.on_trade_key(key.code);
+                None
+            }
+        }
+    }
+
+    fn on_trade_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.should_quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.table_state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table_state.select_previous(),
+            KeyCode::Char('r') => self.ask_release(),
+            KeyCode::Char('d') => {
+                if let Some((name, _)) = self.selected_trade() {
+                    self.dialog = Some(Dialog::DisputeVerdict {
+                        name: name.clone(),
+                        input: String::new(),
+                    });
+                }
+            }
+            KeyCode::Char('f') => self.ask_refund(),
+            _ => {}
+        }
+    }
+
+    fn ask_release(&mut self) {
+        let Some((name, trade)) = self.selected_trade() else {
+            return;
+        };
+        if trade.mode == TradeMode::Seller {
+            let line = format!(
+                "{}: the release of the buyer is redeemed as soon as it arrives",
+                name
+            );
+            self.push_log(line);
+            return;
+        }
+        self.dialog = Some(Dialog::Confirm {
+            prompt: format!(
+                "Release the escrow of {} ({} {}) to the seller?",
+                name, trade.contract.trade_amount, trade.contract.currency_unit
+            ),
+            action: Action::Release { name: name.clone() },
+        });
+    }
+
+    fn ask_refund(&mut self) {
+        let Some((name, trade)) = self.selected_trade() else {
+            return;
+        };
+        self.dialog = Some(Dialog::Confirm {
+            prompt: format!(
+                "Refund the escrow of {} ({} {})?",
+                name, trade.contract.trade_amount, trade.contract.currency_unit
+            ),
+            action: Action::Refund { name: name.clone() },
+        });
+    }
+
+    fn on_dialog_key(&mut self, dialog: Dialog, code: KeyCode) -> Option<Action> {
+        match dialog {
+            Dialog::Confirm { action, .. } if matches!(code, KeyCode::Char('y')) => Some(action),
+            Dialog::Confirm { prompt, action } => {
+                if !matches!(code, KeyCode::Char('n') | KeyCode::Esc) {
+                    self.dialog = Some(Dialog::Confirm { prompt, action });
+                }
+                None
+            }
+            Dialog::DisputeVerdict { name, mut input } => {
+                if code == KeyCode::Enter {
+                    match parse_verdict(input.trim()) {
+                        Ok(verdict) => {
+                            self.dialog = Some(Dialog::DisputeStatement {
+                                name,
+                                verdict,
+                                input: String::new(),
+                            })
+                        }
+                        Err(e) => self.push_log(format!("Invalid verdict: {}", e)),
+                    }
+                } else if edit_input(&mut input, code) {
+                    self.dialog = Some(Dialog::DisputeVerdict { name, input });
+                }
+                None
+            }
+            Dialog::DisputeStatement {
+                name,
+                verdict,
+                mut input,
+            } => {
+                if code == KeyCode::Enter {
+                    self.dialog = Some(Dialog::Confirm {
+                        prompt: format!("Open a dispute of {} requesting {:?}?", name, verdict),
+                        action: Action::Dispute {
+                            name,
+                            verdict,
+                            statement: input,
+                        },
+                    });
+                } else if edit_input(&mut input, code) {
+                    self.dialog = Some(Dialog::DisputeStatement {
+                        name,
+                        verdict,
+                        input,
+                    });
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Applies the key to the input line, returns `false` if the input was cancelled.
+fn edit_input(input: &mut String, code: KeyCode) -> bool {
+    match code {
+        KeyCode::Esc => return false,
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Char(c) => input.push(c),
+        _ => {}
+    }
+    true
+}
@@ -0,0 +1,339 @@
+mod app;
+mod ui;
+
+use super::*;
+
+use app::{Action, App};
+use cashu_escrow_client::escrow_client::TradeMode;
+use cashu_escrow_common::model::{
+    ContractRejection, DisputeFailure, DisputeVerdict, EcashPubkeyAnnouncement, EscrowRegistration,
+    EscrowRelease, NegotiationMessage, Verdict,
+};
+use cashu_escrow_common::nostr::DirectMessage;
+use cashu_escrow_common::token::decode_token;
+use cli::parse_verdict;
+use nostr_sdk::{PublicKey, Timestamp};
+use ratatui::crossterm::event::{self, Event, KeyEvent};
+use state::{TradeStage, TradeState};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+/// Interval in which the trades and the balance are reloaded, e.g. after a CLI invocation.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+enum AppEvent {
+    Key(KeyEvent),
+    Refresh,
+    Message(DirectMessage),
+    /// Outcome of an action or of an incoming message.
+    Log(String),
+    Balance(String),
+}
+
+/// Shared by the tasks of the UI.
+struct Shared {
+    ctx: Context,
+    /// Held while the wallet is open, so the wallet file isn't written by two instances.
+    wallet_lock: Mutex<()>,
+    events: mpsc::UnboundedSender<AppEvent>,
+}
+
+impl Shared {
+    fn log(&self, line: String) {
+        let _ = self.events.send(AppEvent::Log(line));
+    }
+}
+
+/// Runs the terminal UI until the user quits.
+pub async fn run(ctx: Context) -> anyhow::Result<()> {
+    // creates the wallet if needed, before the screen is taken over
+    ctx.wallet().await?;
+    let (events, mut receiver) = mpsc::unbounded_channel();
+    let shared = Arc::new(Shared {
+        ctx,
+        wallet_lock: Mutex::new(()),
+        events: events.clone(),
+    });
+    spawn_input_reader(events.clone());
+    spawn_refresh_timer(events.clone());
+    tokio::spawn(monitor_messages(shared.clone()));
+
+    let mut terminal = ratatui::init();
+    let mut app = App::new();
+    let result = async {
+        reload(&shared, &mut app);
+        while let Some(event) = receiver.recv().await {
+            match event {
+                AppEvent::Key(key) => {
+                    if let Some(action) = app.on_key(key) {
+                        tokio::spawn(run_action(shared.clone(), action));
+                    }
+                }
+                AppEvent::Refresh => {
+                    reload(&shared, &mut app);
+                    tokio::spawn(refresh_balance(shared.clone()));
+                }
+                AppEvent::Message(message) => {
+                    tokio::spawn(handle_message(shared.clone(), message));
+                }
+                AppEvent::Log(line) => {
+                    app.push_log(line);
+                    reload(&shared, &mut app);
+                }
+                AppEvent::Balance(balance) => app.balance = Some(balance),
+            }
+            if app.should_quit {
+                break;
+            }
+            terminal.draw(|frame| ui::draw(frame, &mut app))?;
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    ratatui::restore();
+    result
+}
+
+fn reload(shared: &Shared, app: &mut App) {
+    match shared.ctx.data_dir.list_trades() {
+        Ok(trades) => app.set_trades(trades),
+        Err(e) => app.push_log(format!("Could not load the trades: {}", e)),
+    }
+}
+
+fn spawn_input_reader(events: mpsc::UnboundedSender<AppEvent>) {
+    // crossterm reads blocking, so the keys are read on their own thread
+    std::thread::spawn(move || loop {
+        match event::poll(Duration::from_millis(250)) {
+            Ok(true) => {
+                if let Ok(Event::Key(key)) = event::read() {
+                    if events.send(AppEvent::Key(key)).is_err() {
+                        break;
+                    }
+                }
+            }
+            Ok(false) if events.is_closed() => break,
+            Ok(false) => {}
+            Err(_) => break,
+        }
+    });
+}
+
+fn spawn_refresh_timer(events: mpsc::UnboundedSender<AppEvent>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if events.send(AppEvent::Refresh).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+async fn refresh_balance(shared: Arc<Shared>) {
+    // skip the refresh while an action uses the wallet
+    let Ok(_guard) = shared.wallet_lock.try_lock() else {
+        return;
+    };
+    let balance = async {
+        let wallet = shared.ctx.wallet().await?;
+        anyhow::Ok(format!(
+            "{} {} at {}",
+            wallet.wallet.total_balance().await?,
+            wallet.wallet.unit,
+            wallet.wallet.mint_url
+        ))
+    }
+    .await
+    .unwrap_or_else(|e| format!("unavailable: {}", e));
+    let _ = shared.events.send(AppEvent::Balance(balance));
+}
+
+async fn monitor_messages(shared: Arc<Shared>) {
+    let mut nostr_client = match shared.ctx.nostr_client().await {
+        Ok(nostr_client) => nostr_client,
+        Err(e) => {
+            shared.log(format!("Not receiving messages: {}", e));
+            return;
+        }
+    };
+    shared.log("Listening for messages...".to_string());
+    loop {
+        match nostr_client.receive_direct_message().await {
+            Ok(message) => {
+                if shared.events.send(AppEvent::Message(message)).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                shared.log(format!("Stopped receiving messages: {}", e));
+                break;
+            }
+        }
+    }
+}
+
+async fn run_action(shared: Arc<Shared>, action: Action) {
+    let _guard = shared.wallet_lock.lock().await;
+    let ctx = &shared.ctx;
+    let line = match action {
+        Action::Release { name } => commands::release_escrow(ctx, &name)
+            .await
+            .map(|_| format!("{}: released the escrow to the seller", name)),
+        Action::Dispute {
+            name,
+            verdict,
+            statement,
+        } => commands::open_dispute(ctx, &name, verdict, statement, vec![])
+            .await
+            .map(|_| format!("{}: sent the dispute claim to the coordinator", name)),
+        Action::Refund { name } => commands::refund_escrow(ctx, &name)
+            .await
+            .map(|redeemed| format!("{}: refunded {} from the escrow", name, redeemed)),
+    };
+    shared.log(line.unwrap_or_else(|e| format!("Failed: {}", e)));
+}
+
+/// Logs the message and redeems releases and verdicts of our trades.
+async fn handle_message(shared: Arc<Shared>, message: DirectMessage) {
+    let content = &message.content;
+    let line = if let Ok(release) = serde_json::from_str::<EscrowRelease>(content) {
+        match redeem_release(&shared, &release, message.sender).await {
+            Ok(line) => line,
+            Err(e) => format!("Could not redeem the release: {}", e),
+        }
+    } else if let Ok(verdict) = serde_json::from_str::<DisputeVerdict>(content) {
+        match redeem_verdict(&shared, &verdict, message.sender).await {
+            Ok(line) => line,
+            Err(e) => format!("Could not redeem the verdict: {}", e),
+        }
+    } else if let Ok(failure) = serde_json::from_str::<DisputeFailure>(content) {
+        format!(
+            "The coordinator couldn't resolve dispute {}: {}",
+            failure.escrow_id_hex, failure.reason
+        )
+    } else if let Ok(rejection) = serde_json::from_str::<ContractRejection>(content) {
+        format!(
+            "The coordinator rejected contract {}: {}",
+            rejection.contract_hash, rejection.reason
+        )
+    } else if let Ok(announcement) = serde_json::from_str::<EcashPubkeyAnnouncement>(content) {
+        format!(
+            "Trade pubkey {} announced by {}",
+            announcement.ecash_pubkey, announcement.nostr_pubkey
+        )
+    } else if let Ok(message) = serde_json::from_str::<NegotiationMessage>(content) {
+        match message {
+            NegotiationMessage::Offer(proposal) | NegotiationMessage::Counter(proposal) => format!(
+                "Contract proposal over {} {}",
+                proposal.contract.trade_amount, proposal.contract.currency_unit
+            ),
+            NegotiationMessage::Accept { contract_hash, .. } => {
+                format!("Contract {} accepted", contract_hash)
+            }
+            NegotiationMessage::Reject { reason, .. } => format!("Contract rejected: {}", reason),
+        }
+    } else if let Ok(registration) = serde_json::from_str::<EscrowRegistration>(content) {
+        format!("Registration of escrow {}", registration.escrow_id_hex)
+    } else if let Ok(token) = decode_token(content) {
+        format!(
+            "Escrow token over {}",
+            token
+                .value()
+                .map(|amount| amount.to_string())
+                .unwrap_or_default()
+        )
+    } else {
+        let preview: String = content.chars().take(80).collect();
+        format!("Message from {}: {}", message.sender, preview)
+    };
+    shared.log(line);
+}
+
+async fn redeem_release(
+    shared: &Shared,
+    release: &EscrowRelease,
+    sender: PublicKey,
+) -> anyhow::Result<String> {
+    let _guard = shared.wallet_lock.lock().await;
+    let (name, mut trade) = find_trade(shared, &release.escrow_id_hex)?;
+    if sender != trade.contract.npubkey_buyer {
+        return Ok(format!(
+            "{}: ignored a release sent by {} instead of the buyer",
+            name, sender
+        ));
+    }
+    if trade.mode != TradeMode::Seller || trade.stage != TradeStage::Funded {
+        return Ok(format!(
+            "{}: ignored a release in stage {:?}",
+            name, trade.stage
+        ));
+    }
+    let redeemed = shared
+        .ctx
+        .wallet()
+        .await?
+        .redeem_escrow_token(&release.signed_token)
+        .await?;
+    trade.stage = TradeStage::Settled;
+    shared.ctx.data_dir.save_trade(&name, &trade)?;
+    Ok(format!(
+        "{}: the buyer released the escrow, redeemed {}",
+        name, redeemed
+    ))
+}
+
+async fn redeem_verdict(
+    shared: &Shared,
+    verdict: &DisputeVerdict,
+    sender: PublicKey,
+) -> anyhow::Result<String> {
+    let _guard = shared.wallet_lock.lock().await;
+    let (name, mut trade) = find_trade(shared, &verdict.escrow_id_hex)?;
+    if sender != trade.contract.npubkey_coordinator {
+        return Ok(format!(
+            "{}: ignored a verdict sent by {} instead of the coordinator",
+            name, sender
+        ));
+    }
+    if !matches!(trade.stage, TradeStage::Funded | TradeStage::Disputed) {
+        return Ok(format!(
+            "{}: ignored a verdict in stage {:?}",
+            name, trade.stage
+        ));
+    }
+    let redeemed = match &verdict.cosigned_token {
+        Some(cosigned_token) => {
+            shared
+                .ctx
+                .wallet()
+                .await?
+                .redeem_escrow_token(cosigned_token)
+                .await?
+        }
+        None => cdk::Amount::ZERO,
+    };
+    trade.stage = TradeStage::Settled;
+    shared.ctx.data_dir.save_trade(&name, &trade)?;
+    Ok(format!(
+        "{}: the coordinator decided {:?}, redeemed {}",
+        name, verdict.verdict, redeemed
+    ))
+}
+
+fn find_trade(shared: &Shared, escrow_id_hex: &str) -> anyhow::Result<(String, TradeState)> {
+    shared
+        .ctx
+        .data_dir
+        .list_trades()?
+        .into_iter()
+        .find(|(_, trade)| {
+            trade
+                .registration
+                .as_ref()
+                .is_some_and(|registration| registration.escrow_id_hex == escrow_id_hex)
+        })
+        .ok_or(anyhow::anyhow!("No trade with escrow {}", escrow_id_hex))
+}
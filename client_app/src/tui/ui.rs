@@ -0,0 +1,161 @@
+use super::*;
+
+use app::{App, Dialog};
+use cli::contract_file::contract_summary;
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Clear, List, ListItem, Paragraph, Row, Table, Wrap};
+use ratatui::Frame;
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [header, main, log, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(8),
+        Constraint::Length(10),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [trades, details] =
+        Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(main);
+
+    let balance = app.balance.as_deref().unwrap_or("loading...");
+    frame.render_widget(
+        Paragraph::new(format!("Cashu escrow | wallet: {}", balance))
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+        header,
+    );
+    draw_trades(frame, app, trades);
+    draw_details(frame, app, details);
+    draw_log(frame, app, log);
+    frame.render_widget(
+        Paragraph::new("↑/↓ select  r release  d dispute  f refund  q quit"),
+        footer,
+    );
+    if let Some(dialog) = &app.dialog {
+        draw_dialog(frame, dialog);
+    }
+}
+
+fn draw_trades(frame: &mut Frame, app: &mut App, area: Rect) {
+    let now = Timestamp::now().as_u64();
+    let rows = app.trades.iter().map(|(name, trade)| {
+        Row::new(vec![
+            name.clone(),
+            format!("{:?}", trade.mode),
+            format!("{:?}", trade.stage),
+            format!(
+                "{} {}",
+                trade.contract.trade_amount, trade.contract.currency_unit
+            ),
+            deadline(trade, now),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(2),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Fill(1),
+            Constraint::Fill(2),
+        ],
+    )
+    .header(
+        Row::new(["Trade", "Role", "Stage", "Amount", "Deadline"])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+    )
+    .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered().title("Trades"));
+    frame.render_stateful_widget(table, area, &mut app.table_state);
+}
+
+fn draw_details(frame: &mut Frame, app: &App, area: Rect) {
+    let text = match app.selected_trade() {
+        Some((_, trade)) => {
+            let mut text = contract_summary(&trade.contract, trade.mode);
+            text.push_str(&format!("\nStage:        {:?}", trade.stage));
+            if let Some(registration) = &trade.registration {
+                text.push_str(&format!("\nEscrow id:    {}", registration.escrow_id_hex));
+            }
+            text
+        }
+        None => "No trades yet, create one with `client_app contract new`".to_string(),
+    };
+    frame.render_widget(
+        Paragraph::new(text)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title("Details")),
+        area,
+    );
+}
+
+fn draw_log(frame: &mut Frame, app: &App, area: Rect) {
+    // show the latest lines which fit into the box
+    let visible = area.height.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = app
+        .log
+        .iter()
+        .skip(app.log.len().saturating_sub(visible))
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("Messages")),
+        area,
+    );
+}
+
+fn draw_dialog(frame: &mut Frame, dialog: &Dialog) {
+    let (title, lines) = match dialog {
+        Dialog::Confirm { prompt, .. } => (
+            "Confirm",
+            vec![Line::from(prompt.as_str()), Line::from("(y)es / (n)o")],
+        ),
+        Dialog::DisputeVerdict { input, .. } => (
+            "Dispute",
+            vec![
+                Line::from("Requested verdict: buyer-wins, seller-wins or split:<buyer amount>"),
+                Line::from(format!("> {}", input)),
+            ],
+        ),
+        Dialog::DisputeStatement { input, .. } => (
+            "Dispute",
+            vec![
+                Line::from("Statement for the coordinator:"),
+                Line::from(format!("> {}", input)),
+            ],
+        ),
+    };
+    let [area] = Layout::vertical([Constraint::Length(6)])
+        .flex(Flex::Center)
+        .areas(frame.area());
+    let [area] = Layout::horizontal([Constraint::Percentage(70)])
+        .flex(Flex::Center)
+        .areas(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+/// Time left until the buyer can refund the escrow.
+fn deadline(trade: &TradeState, now: u64) -> String {
+    match (trade.stage, trade.refundable_at()) {
+        (TradeStage::Funded | TradeStage::Disputed, Some(refundable_at)) => {
+            if refundable_at <= now {
+                "refundable".to_string()
+            } else {
+                let left = refundable_at - now;
+                let (days, hours, minutes) = (left / 86400, left % 86400 / 3600, left % 3600 / 60);
+                match days {
+                    0 => format!("refund in {}h {}m", hours, minutes),
+                    _ => format!("refund in {}d {}h", days, hours),
+                }
+            }
+        }
+        _ => "-".to_string(),
+    }
+}
@@ -0,0 +1,294 @@
+use cashu_escrow_client::ecash::ClientEcashWallet;
+use cashu_escrow_client::escrow_client::TradeMode;
+use cashu_escrow_common::model::{FeePayer, TradeContract};
+use cdk::{
+    mint_url::MintUrl,
+    nuts::{CurrencyUnit, SecretKey},
+};
+use clap::ValueEnum;
+use client_app::cli::contract_file::{
+    fill_placeholders, format_duration, parse_contract, validate_contract, ContractTemplate,
+    Placeholders,
+};
+use client_app::state::{DataDir, TradeStage, TradeState};
+use nostr_sdk::{Keys, ToBech32};
+use std::str::FromStr;
+
+const MINT_URL: &str = "http://localhost:3338";
+
+#[test]
+fn fill_contract_placeholders() {
+    let placeholders = Placeholders::from([
+        ("buyer_npub", Some("npub1buyer".to_string())),
+        ("seller_npub", None),
+    ]);
+    assert_eq!(
+        fill_placeholders(
+            "buyer = \"{{ buyer_npub }}\", {{buyer_npub}}",
+            &placeholders
+        )
+        .unwrap(),
+        "buyer = \"npub1buyer\", npub1buyer"
+    );
+    assert_eq!(
+        fill_placeholders("no placeholders", &placeholders).unwrap(),
+        "no placeholders"
+    );
+
+    let error = |content: &str| {
+        fill_placeholders(content, &placeholders)
+            .unwrap_err()
+            .to_string()
+    };
+    assert!(error("{{coordinator}}").contains("Unknown placeholder {{coordinator}}"));
+    assert!(error("seller = \"{{seller_npub}}\"").contains("No value for the placeholder"));
+    assert!(error("buyer = \"{{buyer_npub\"").contains("Unterminated placeholder"));
+}
+
+#[test]
+fn format_durations_in_largest_unit() {
+    assert_eq!(format_duration(3 * 24 * 60 * 60), "3 days");
+    assert_eq!(format_duration(24 * 60 * 60), "1 day");
+    assert_eq!(format_duration(2 * 60 * 60), "2 hours");
+    assert_eq!(format_duration(90), "90 seconds");
+    assert_eq!(format_duration(1), "1 second");
+}
+
+/// A contract of the buyer with the given wallet which passes the validation.
+fn buyer_contract(wallet: &ClientEcashWallet, buyer: &Keys) -> TradeContract {
+    TradeContract {
+        trade_description: "Test trade".to_string(),
+        trade_amount: 100,
+        npubkey_seller: Keys::generate().public_key(),
+        npubkey_buyer: buyer.public_key(),
+        npubkey_coordinator: Keys::generate().public_key(),
+        time_limit: 60,
+        seller_ecash_public_key: SecretKey::generate().public_key().to_string(),
+        buyer_ecash_public_key: wallet.new_trade_pubkey().unwrap().to_string(),
+        mint_url: MintUrl::from_str(MINT_URL).unwrap(),
+        currency_unit: CurrencyUnit::Sat,
+        allowed_keysets: None,
+        fee_payer: FeePayer::default(),
+        terms: None,
+        privacy: None,
+    }
+}
+
+#[tokio::test]
+async fn report_every_contract_problem() {
+    let wallet = ClientEcashWallet::new(MINT_URL, CurrencyUnit::Sat)
+        .await
+        .unwrap();
+    let buyer = Keys::generate();
+    let contract = buyer_contract(&wallet, &buyer);
+    let validate = |contract: &TradeContract| {
+        validate_contract(contract, TradeMode::Buyer, &buyer.public_key(), &wallet)
+    };
+    assert!(validate(&contract).is_ok());
+
+    let template = parse_contract(
+        ContractTemplate::Services.content(),
+        false,
+        &template_placeholders(&contract),
+    )
+    .unwrap();
+    let mut terms = template.terms.unwrap();
+    terms.line_items.clear();
+
+    let mut invalid_partner = contract.clone();
+    invalid_partner.seller_ecash_public_key = "not a pubkey".to_string();
+    let cases: Vec<(TradeContract, &str)> = vec![
+        (
+            TradeContract {
+                trade_description: " ".to_string(),
+                ..contract.clone()
+            },
+            "The trade description is empty",
+        ),
+        (
+            TradeContract {
+                trade_amount: 0,
+                ..contract.clone()
+            },
+            "The trade amount has to be positive",
+        ),
+        (
+            TradeContract {
+                time_limit: 0,
+                ..contract.clone()
+            },
+            "The time limit has to be positive",
+        ),
+        (
+            TradeContract {
+                terms: Some(terms),
+                ..contract.clone()
+            },
+            "No line items",
+        ),
+        (
+            TradeContract {
+                npubkey_seller: contract.npubkey_buyer,
+                ..contract.clone()
+            },
+            "Buyer and seller use the same nostr key",
+        ),
+        (
+            TradeContract {
+                npubkey_coordinator: contract.npubkey_seller,
+                ..contract.clone()
+            },
+            "The coordinator can't be one of the traders",
+        ),
+        (
+            TradeContract {
+                buyer_ecash_public_key: SecretKey::generate().public_key().to_string(),
+                ..contract.clone()
+            },
+            "isn't one of the wallet",
+        ),
+        (invalid_partner, "Invalid trade pubkey of the partner"),
+        (
+            TradeContract {
+                seller_ecash_public_key: contract.buyer_ecash_public_key.clone(),
+                ..contract.clone()
+            },
+            "Buyer and seller use the same trade pubkey",
+        ),
+        (
+            TradeContract {
+                mint_url: MintUrl::from_str("https://mint.example.com").unwrap(),
+                ..contract.clone()
+            },
+            "The contract requires https://mint.example.com",
+        ),
+    ];
+    for (contract, problem) in cases {
+        let error = validate(&contract).unwrap_err().to_string();
+        assert!(error.contains(problem), "{} not in: {}", problem, error);
+    }
+
+    let error = validate_contract(
+        &contract,
+        TradeMode::Buyer,
+        &Keys::generate().public_key(),
+        &wallet,
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("The contract names another Buyer"));
+
+    // all problems are reported at once
+    let error = validate(&TradeContract {
+        trade_amount: 0,
+        time_limit: 0,
+        ..contract.clone()
+    })
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("trade amount") && error.contains("time limit"));
+}
+
+fn template_placeholders(contract: &TradeContract) -> Placeholders {
+    Placeholders::from([
+        ("buyer_npub", contract.npubkey_buyer.to_bech32().ok()),
+        ("seller_npub", contract.npubkey_seller.to_bech32().ok()),
+        (
+            "coordinator_npub",
+            contract.npubkey_coordinator.to_bech32().ok(),
+        ),
+        (
+            "buyer_ecash_pubkey",
+            Some(contract.buyer_ecash_public_key.clone()),
+        ),
+        (
+            "seller_ecash_pubkey",
+            Some(contract.seller_ecash_public_key.clone()),
+        ),
+        ("mint_url", Some(contract.mint_url.to_string())),
+    ])
+}
+
+#[tokio::test]
+async fn parse_contract_templates() {
+    let wallet = ClientEcashWallet::new(MINT_URL, CurrencyUnit::Sat)
+        .await
+        .unwrap();
+    let buyer = Keys::generate();
+    let contract = buyer_contract(&wallet, &buyer);
+    let placeholders = template_placeholders(&contract);
+
+    for template in ContractTemplate::value_variants() {
+        let mut parsed = parse_contract(template.content(), false, &placeholders).unwrap();
+        assert_eq!(parsed.npubkey_buyer, contract.npubkey_buyer);
+        assert_eq!(parsed.mint_url, contract.mint_url);
+        assert!(parsed.terms.is_some());
+        // the templates leave the amount to the trader
+        assert_eq!(parsed.trade_amount, 0);
+        parsed.trade_amount = 100;
+        validate_contract(&parsed, TradeMode::Buyer, &buyer.public_key(), &wallet).unwrap();
+    }
+}
+
+/// A contract without trade pubkeys, enough to track the stage of a trade.
+fn draft_contract() -> TradeContract {
+    serde_json::from_value(serde_json::json!({
+        "trade_description": "Test trade",
+        "trade_amount": 100,
+        "npubkey_seller": Keys::generate().public_key(),
+        "npubkey_buyer": Keys::generate().public_key(),
+        "npubkey_coordinator": Keys::generate().public_key(),
+        "time_limit": 60,
+        "seller_ecash_public_key": "",
+        "buyer_ecash_public_key": "",
+        "mint_url": MINT_URL,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn require_trade_stage() {
+    let mut trade = TradeState::new(TradeMode::Buyer, draft_contract());
+    assert_eq!(trade.stage, TradeStage::Drafted);
+    assert!(trade
+        .require_stage(&[TradeStage::Drafted, TradeStage::Negotiated])
+        .is_ok());
+    let error = trade.require_stage(&[TradeStage::Registered]).unwrap_err();
+    assert!(error.to_string().contains("The trade is Drafted"));
+
+    trade.stage = TradeStage::Funded;
+    assert!(trade.require_stage(&[TradeStage::Drafted]).is_err());
+    assert!(trade.registration().is_err());
+    assert!(trade.escrow_token().is_err());
+    assert_eq!(trade.refundable_at(), None);
+}
+
+#[test]
+fn persist_trades_under_valid_names() {
+    let path = std::env::temp_dir().join(format!("escrow-data-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let data_dir = DataDir::new(&path).unwrap();
+    let mut trade = TradeState::new(TradeMode::Seller, draft_contract());
+
+    for name in ["", "../wallet", "trade.json", "a b", "trade/1"] {
+        assert!(data_dir.create_trade(name, &trade).is_err(), "{}", name);
+        assert!(data_dir.load_trade(name).is_err(), "{}", name);
+    }
+
+    data_dir.create_trade("trade-1_a", &trade).unwrap();
+    assert!(data_dir.create_trade("trade-1_a", &trade).is_err());
+    trade.stage = TradeStage::Funded;
+    data_dir.save_trade("trade-1_a", &trade).unwrap();
+    let loaded = data_dir.load_trade("trade-1_a").unwrap();
+    assert_eq!(loaded.stage, TradeStage::Funded);
+    assert_eq!(loaded.mode, TradeMode::Seller);
+    let names: Vec<String> = data_dir
+        .list_trades()
+        .unwrap()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(names, ["trade-1_a"]);
+    let _ = std::fs::remove_dir_all(&path);
+}
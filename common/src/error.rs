@@ -0,0 +1,101 @@
+use thiserror::Error;
+
+pub type Result<T, E = EscrowError> = std::result::Result<T, E>;
+
+/// Error type of the escrow libraries.
+///
+/// The variants allow callers to tell apart failures of the infrastructure (transport, mint),
+/// timeouts and rejected input (validation, protocol, policy).
+#[derive(Debug, Error)]
+pub enum EscrowError {
+    /// Communication with the nostr relays failed.
+    #[error("Transport error: {0}")]
+    Transport(String),
+    /// An expected message didn't arrive in time.
+    #[error("Timeout: {0}")]
+    Timeout(String),
+    /// The mint or the ecash wallet returned an error.
+    #[error("Mint error: {0}")]
+    Mint(#[from] cdk::Error),
+    /// Invalid input, e.g. a malformed key or an escrow token not matching the contract.
+    #[error("Validation error: {0}")]
+    Validation(String),
+    /// A message which doesn't fit the escrow protocol.
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    /// The request was rejected by the policy of the coordinator.
+    #[error("Policy violation: {0}")]
+    Policy(String),
+    /// Reading or writing persisted state failed.
+    #[error("Storage error: {0}")]
+    Storage(String),
+}
+
+impl EscrowError {
+    /// Stable identifier of the error kind, e.g. for the js bindings.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Transport(_) => "TRANSPORT",
+            Self::Timeout(_) => "TIMEOUT",
+            Self::Mint(_) => "MINT",
+            Self::Validation(_) => "VALIDATION",
+            Self::Protocol(_) => "PROTOCOL",
+            Self::Policy(_) => "POLICY",
+            Self::Storage(_) => "STORAGE",
+        }
+    }
+}
+
+impl From<nostr_sdk::client::Error> for EscrowError {
+    fn from(e: nostr_sdk::client::Error) -> Self {
+        Self::Transport(e.to_string())
+    }
+}
+
+impl From<nostr_sdk::key::Error> for EscrowError {
+    fn from(e: nostr_sdk::key::Error) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<nostr_sdk::nips::nip19::Error> for EscrowError {
+    fn from(e: nostr_sdk::nips::nip19::Error) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<nostr_sdk::util::hex::Error> for EscrowError {
+    fn from(e: nostr_sdk::util::hex::Error) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<cdk::nuts::nut00::Error> for EscrowError {
+    fn from(e: cdk::nuts::nut00::Error) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<cdk::nuts::nut01::Error> for EscrowError {
+    fn from(e: cdk::nuts::nut01::Error) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<cdk::nuts::nut11::Error> for EscrowError {
+    fn from(e: cdk::nuts::nut11::Error) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<cdk::mint_url::Error> for EscrowError {
+    fn from(e: cdk::mint_url::Error) -> Self {
+        Self::Validation(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for EscrowError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Protocol(e.to_string())
+    }
+}
@@ -0,0 +1,136 @@
+//! Evidence the traders submit to the coordinator during a dispute.
+//!
+//! An [`EvidenceSubmission`] carries a message, e.g. a chat log or a tracking number, and
+//! announces the attachments. The attachments follow in [`EvidenceChunk`]s, small enough for
+//! the relays. Like all the other messages they are sent as private direct messages (NIP-17),
+//! so only the coordinator can decrypt them.
+
+use crate::error::{EscrowError, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use nostr_sdk::hashes::{sha256, Hash};
+use nostr_sdk::util::hex;
+use serde::{Deserialize, Serialize};
+
+/// Maximum size of the data of an [`EvidenceChunk`] in bytes.
+pub const EVIDENCE_CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EvidenceSubmission {
+    pub escrow_id_hex: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentHeader>,
+}
+
+/// Describes an attachment, its content is sent in chunks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttachmentHeader {
+    pub name: String,
+    pub mime_type: String,
+    /// Size in bytes.
+    pub size: u64,
+    /// Hex encoded sha256 hash of the content, identifies the attachment.
+    pub sha256: String,
+    pub chunk_count: u32,
+}
+
+impl AttachmentHeader {
+    /// Checks the assembled content against size and hash of the header.
+    pub fn verify(&self, content: &[u8]) -> Result<()> {
+        if content.len() as u64 != self.size || content_hash(content) != self.sha256 {
+            return Err(EscrowError::Validation(format!(
+                "The content of attachment {} doesn't match its hash",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks the data of the chunk at `index`, all chunks but the last one are full.
+    pub fn check_chunk(&self, index: u32, data: &[u8]) -> Result<()> {
+        if index >= self.chunk_count {
+            return Err(EscrowError::Validation(format!(
+                "Chunk {} exceeds the chunks of attachment {}",
+                index, self.name
+            )));
+        }
+        let chunk_size = EVIDENCE_CHUNK_SIZE as u64;
+        let expected = self
+            .size
+            .saturating_sub(u64::from(index) * chunk_size)
+            .min(chunk_size);
+        if data.len() as u64 != expected {
+            return Err(EscrowError::Validation(format!(
+                "Chunk {} of attachment {} has {} instead of {} bytes",
+                index,
+                self.name,
+                data.len(),
+                expected
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EvidenceChunk {
+    pub escrow_id_hex: String,
+    /// Hash of the attachment the chunk belongs to, see [`AttachmentHeader::sha256`].
+    pub attachment_sha256: String,
+    pub index: u32,
+    /// Base64 encoded.
+    pub data: String,
+}
+
+impl EvidenceChunk {
+    pub fn decode_data(&self) -> Result<Vec<u8>> {
+        let data = STANDARD
+            .decode(&self.data)
+            .map_err(|e| EscrowError::Validation(format!("Invalid evidence chunk: {}", e)))?;
+        if data.len() > EVIDENCE_CHUNK_SIZE {
+            return Err(EscrowError::Validation(
+                "Evidence chunk exceeds the chunk size".to_string(),
+            ));
+        }
+        Ok(data)
+    }
+}
+
+/// A file to be submitted as evidence, e.g. a photo or a receipt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    pub mime_type: String,
+    pub content: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn header(&self) -> AttachmentHeader {
+        AttachmentHeader {
+            name: self.name.clone(),
+            mime_type: self.mime_type.clone(),
+            size: self.content.len() as u64,
+            sha256: content_hash(&self.content),
+            chunk_count: self.content.chunks(EVIDENCE_CHUNK_SIZE).count() as u32,
+        }
+    }
+
+    pub fn chunks(&self, escrow_id_hex: &str) -> Vec<EvidenceChunk> {
+        let attachment_sha256 = content_hash(&self.content);
+        self.content
+            .chunks(EVIDENCE_CHUNK_SIZE)
+            .enumerate()
+            .map(|(index, data)| EvidenceChunk {
+                escrow_id_hex: escrow_id_hex.to_string(),
+                attachment_sha256: attachment_sha256.clone(),
+                index: index as u32,
+                data: STANDARD.encode(data),
+            })
+            .collect()
+    }
+}
+
+/// Hex encoded sha256 hash of the content.
+pub fn content_hash(content: &[u8]) -> String {
+    hex::encode(sha256::Hash::hash(content).to_byte_array())
+}
@@ -1,6 +1,12 @@
 pub mod cli;
+pub mod error;
+pub mod evidence;
 pub mod model;
 pub mod nostr;
+pub mod payment_request;
+pub mod signing;
+pub mod terms;
+pub mod token;
 
 mod cdk_pubkey_serde {
     use cdk::nuts::PublicKey;
@@ -1,17 +1,364 @@
-use cdk::nuts::PublicKey as CDKPubkey;
-use nostr_sdk::{PublicKey as NostrPubkey, Timestamp};
+use crate::error::{EscrowError, Result};
+use crate::signing;
+use crate::terms::TradeTerms;
+use cdk::mint_url::MintUrl;
+use cdk::nuts::{
+    Conditions, CurrencyUnit, Id, PublicKey as CDKPubkey, SigFlag, SpendingConditions, Token,
+};
+use cdk::secret::Secret;
+use nostr_sdk::hashes::{sha256, Hash};
+use nostr_sdk::secp256k1::schnorr::Signature;
+use nostr_sdk::util::hex;
+use nostr_sdk::{Keys, PublicKey as NostrPubkey, Timestamp};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct TradeContract {
     pub trade_description: String,
-    pub trade_amount_sat: u64,
+    /// Amount of the trade in `currency_unit`.
+    #[serde(alias = "trade_amount_sat")]
+    pub trade_amount: u64,
     pub npubkey_seller: NostrPubkey,
     pub npubkey_buyer: NostrPubkey,
     pub npubkey_coordinator: NostrPubkey,
     pub time_limit: u64,
     pub seller_ecash_public_key: String,
     pub buyer_ecash_public_key: String,
+    /// The mint the escrow token has to be issued by.
+    ///
+    /// Contracts from before the mint was agreed don't have one, see [`TradeContract::from_json`].
+    pub mint_url: MintUrl,
+    /// Contracts without unit were denominated in sat.
+    #[serde(default)]
+    pub currency_unit: CurrencyUnit,
+    /// Keysets of the mint the escrow proofs may come from, `None` allows all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_keysets: Option<Vec<Id>>,
+    /// Who bears the input fees (NUT-02) the mint charges for redeeming the escrow token.
+    #[serde(default)]
+    pub fee_payer: FeePayer,
+    /// Structured obligations of the traders, in addition to the description.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms: Option<TradeTerms>,
+    /// Set in privacy mode, the coordinator then only learns the [`PrivateTerms`] in a dispute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy: Option<TermsPrivacy>,
+}
+
+impl TradeContract {
+    const SIGNING_TAG: &'static str = "cashu-escrow/trade-contract";
+
+    /// Parses the contract from JSON, contracts without mint get the mint the traders used
+    /// implicitly before, usually the one of their wallet.
+    pub fn from_json(json: &str, default_mint_url: &MintUrl) -> Result<Self> {
+        let mut contract: serde_json::Value = serde_json::from_str(json)?;
+        if let Some(fields) = contract.as_object_mut() {
+            fields
+                .entry("mint_url")
+                .or_insert_with(|| serde_json::Value::String(default_mint_url.to_string()));
+        }
+        Ok(serde_json::from_value(contract)?)
+    }
+
+    /// The JSON the contract is submitted to the coordinator as, see
+    /// [`TradeContract::coordinator_contract`].
+    pub fn canonical_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.coordinator_contract()?)?)
+    }
+
+    /// Switches to privacy mode, the private terms are committed to with a new salt.
+    pub fn make_private(&mut self) {
+        self.privacy = Some(TermsPrivacy::Salted {
+            salt: Secret::generate().to_string(),
+        });
+    }
+
+    /// Validates the structured terms, if the contract has some.
+    pub fn validate_terms(&self) -> Result<()> {
+        match &self.terms {
+            Some(terms) => terms.validate(self),
+            None => Ok(()),
+        }
+    }
+
+    pub fn private_terms(&self) -> PrivateTerms {
+        PrivateTerms {
+            trade_description: self.trade_description.clone(),
+            terms: self.terms.clone(),
+        }
+    }
+
+    /// The opening of the commitment, `None` unless this is the traders' copy of a contract
+    /// in privacy mode.
+    pub fn terms_opening(&self) -> Option<TermsOpening> {
+        match &self.privacy {
+            Some(TermsPrivacy::Salted { salt }) => Some(TermsOpening {
+                salt: salt.clone(),
+                terms: self.private_terms(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The contract as the coordinator gets it. In privacy mode the private terms are
+    /// replaced by their commitment, otherwise it is the same contract.
+    pub fn coordinator_contract(&self) -> Result<TradeContract> {
+        let mut contract = self.clone();
+        if let Some(opening) = self.terms_opening() {
+            contract.trade_description = String::new();
+            contract.terms = None;
+            contract.privacy = Some(TermsPrivacy::Committed {
+                commitment: opening.commitment()?,
+            });
+        }
+        Ok(contract)
+    }
+
+    /// Restores the traders' copy of a contract in privacy mode from the coordinator's copy,
+    /// fails if the opening doesn't match the commitment.
+    pub fn reveal(&self, opening: &TermsOpening) -> Result<TradeContract> {
+        let Some(TermsPrivacy::Committed { commitment }) = &self.privacy else {
+            return Err(EscrowError::Validation(
+                "The contract has no committed terms".to_string(),
+            ));
+        };
+        opening.verify(commitment)?;
+        let mut contract = self.clone();
+        contract.trade_description = opening.terms.trade_description.clone();
+        contract.terms = opening.terms.terms.clone();
+        contract.privacy = Some(TermsPrivacy::Salted {
+            salt: opening.salt.clone(),
+        });
+        Ok(contract)
+    }
+
+    /// Hash of the canonical JSON, which the coordinator also takes as escrow id.
+    pub fn contract_hash(&self) -> Result<[u8; 32]> {
+        Ok(sha256::Hash::hash(self.canonical_json()?.as_bytes()).to_byte_array())
+    }
+
+    /// Signs the contract hash with the trader's nostr key.
+    pub fn sign(&self, keys: &Keys) -> Result<Signature> {
+        Ok(signing::sign(
+            keys,
+            Self::SIGNING_TAG,
+            &self.contract_hash()?,
+        ))
+    }
+
+    pub fn verify_signature(&self, signer: &NostrPubkey, signature: &Signature) -> Result<()> {
+        signing::verify(signer, Self::SIGNING_TAG, &self.contract_hash()?, signature)
+    }
+
+    /// Spending conditions (NUT-11) of the escrow proofs.
+    ///
+    /// The seller and either the buyer or the coordinator have to sign. After the locktime the
+    /// buyer can redeem the proofs with the refund key alone.
+    pub fn escrow_conditions(
+        &self,
+        coordinator_escrow_pubkey: &CDKPubkey,
+        escrow_start_time: Timestamp,
+    ) -> Result<SpendingConditions> {
+        let seller_pubkey = CDKPubkey::from_hex(&self.seller_ecash_public_key)?;
+        let buyer_pubkey = CDKPubkey::from_hex(&self.buyer_ecash_public_key)?;
+        // SIG_INPUTS, with SIG_ALL the mint would require two signatures on the outputs of the
+        // redeeming swap too, but the second signer (buyer or coordinator) never sees them
+        Ok(SpendingConditions::new_p2pk(
+            seller_pubkey,
+            Some(Conditions {
+                locktime: Some(escrow_start_time.as_u64() + self.time_limit),
+                pubkeys: Some(vec![buyer_pubkey, *coordinator_escrow_pubkey]),
+                refund_keys: Some(vec![buyer_pubkey]),
+                num_sigs: Some(2),
+                sig_flag: SigFlag::SigInputs,
+            }),
+        ))
+    }
+
+    /// Takes the salt of the other contract if both are in privacy mode, the trader offering
+    /// the contract chooses it. A contract not in privacy mode keeps its `privacy`, so it
+    /// differs from a private one.
+    pub fn adopt_salt(&mut self, other: &TradeContract) {
+        if let (Some(TermsPrivacy::Salted { .. }), Some(TermsPrivacy::Salted { .. })) =
+            (&self.privacy, &other.privacy)
+        {
+            self.privacy = other.privacy.clone();
+        }
+    }
+
+    /// Checks that the contract only differs from the other one in the terms which are
+    /// negotiated: amount, time limit, description and structured terms.
+    ///
+    /// In privacy mode the salt of the commitment is taken from the proposal as well.
+    pub fn check_negotiable_changes(&self, other: &TradeContract) -> Result<()> {
+        let mut other = other.clone();
+        other.trade_amount = self.trade_amount;
+        other.time_limit = self.time_limit;
+        other.trade_description = self.trade_description.clone();
+        other.terms = self.terms.clone();
+        other.adopt_salt(self);
+        if other != *self {
+            return Err(EscrowError::Validation(
+                "Only amount, time limit, description and terms of the contract can be negotiated"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Privacy mode of a contract, the private terms are hidden from the coordinator by a salted
+/// hash commitment.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TermsPrivacy {
+    /// The traders' copy, holding the private terms and the hex encoded salt.
+    Salted { salt: String },
+    /// The coordinator's copy, the private terms are left empty.
+    Committed {
+        /// Hex encoded, see [`TermsOpening::commitment`].
+        commitment: String,
+    },
+}
+
+/// The terms of a contract which are only revealed to the coordinator in a dispute, if the
+/// contract is in privacy mode.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrivateTerms {
+    pub trade_description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms: Option<TradeTerms>,
+}
+
+/// The private terms together with the salt, sent to the coordinator with a dispute claim.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TermsOpening {
+    /// Hex encoded.
+    pub salt: String,
+    pub terms: PrivateTerms,
+}
+
+impl TermsOpening {
+    const COMMITMENT_TAG: &'static str = "cashu-escrow/terms-commitment";
+
+    /// Hex encoded tagged hash of the salt followed by the JSON of the private terms.
+    pub fn commitment(&self) -> Result<String> {
+        let mut data = hex::decode(&self.salt)?;
+        data.extend(serde_json::to_vec(&self.terms)?);
+        Ok(hex::encode(signing::tagged_hash(
+            Self::COMMITMENT_TAG,
+            &data,
+        )))
+    }
+
+    pub fn verify(&self, commitment: &str) -> Result<()> {
+        if self.commitment()? != commitment {
+            return Err(EscrowError::Validation(
+                "The revealed terms don't match the commitment of the contract".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Signatures of buyer and seller over the contract, see [`TradeContract::sign`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContractSignatures {
+    pub buyer: Signature,
+    pub seller: Signature,
+}
+
+impl ContractSignatures {
+    pub fn verify(&self, contract: &TradeContract) -> Result<()> {
+        contract.verify_signature(&contract.npubkey_buyer, &self.buyer)?;
+        contract.verify_signature(&contract.npubkey_seller, &self.seller)
+    }
+}
+
+/// A contract signed by buyer and seller, as submitted to the coordinator.
+///
+/// The coordinator starts the trade as soon as one of the traders submitted it, and keeps it
+/// as proof of the agreed terms in case of a dispute.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignedTradeContract {
+    pub contract: TradeContract,
+    pub signatures: ContractSignatures,
+}
+
+impl SignedTradeContract {
+    pub fn new(contract: TradeContract, signatures: ContractSignatures) -> Result<Self> {
+        let signed = Self {
+            contract,
+            signatures,
+        };
+        signed.verify()?;
+        Ok(signed)
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        self.signatures.verify(&self.contract)
+    }
+
+    /// The escrow id, the same as for the unsigned contract.
+    pub fn contract_hash(&self) -> Result<[u8; 32]> {
+        self.contract.contract_hash()
+    }
+}
+
+/// Messages of the negotiation between buyer and seller, which ends with a contract
+/// signed by both of them.
+///
+/// One trader sends an offer, the partner answers with a counter-offer, an acceptance or a
+/// rejection, and so on. Each proposal is signed by its proposer, the acceptance adds the
+/// second signature.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "negotiation", rename_all = "snake_case")]
+pub enum NegotiationMessage {
+    Offer(ContractProposal),
+    Counter(ContractProposal),
+    Accept {
+        negotiation_id: String,
+        /// Hex encoded hash of the accepted contract.
+        contract_hash: String,
+        signature: Signature,
+    },
+    Reject {
+        negotiation_id: String,
+        reason: String,
+    },
+}
+
+impl NegotiationMessage {
+    pub fn negotiation_id(&self) -> &str {
+        match self {
+            Self::Offer(proposal) | Self::Counter(proposal) => &proposal.negotiation_id,
+            Self::Accept { negotiation_id, .. } | Self::Reject { negotiation_id, .. } => {
+                negotiation_id
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContractProposal {
+    /// Chosen by the trader who sent the offer, the same for all messages of the negotiation.
+    pub negotiation_id: String,
+    pub contract: TradeContract,
+    /// Signature of the proposer.
+    pub signature: Signature,
+}
+
+/// Party paying the mint fees of the escrow.
+///
+/// The buyer always pays the fees of funding the escrow. If the buyer also pays the redemption,
+/// the escrow token carries the redemption fee on top of the trade amount.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeePayer {
+    Buyer,
+    /// The redemption fee is deducted from the payout, contracts without fee payer work this way.
+    #[default]
+    Seller,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -35,3 +382,156 @@ impl EscrowRegistration {
         }
     }
 }
+
+/// Sent by the coordinator instead of the [`EscrowRegistration`] to the trader who submitted a
+/// contract it doesn't take.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContractRejection {
+    /// Hex encoded hash of the rejected contract.
+    pub contract_hash: String,
+    pub reason: String,
+}
+
+/// Sent by a trader to the trade partner before the registration, to hand over the trade pubkey.
+///
+/// The signature of the trader's nostr key binds the ecash pubkey to the trader, so the
+/// partner can put it into the contract without comparing it out of band.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EcashPubkeyAnnouncement {
+    pub nostr_pubkey: NostrPubkey,
+    /// The trade partner, so the announcement can't be forwarded to other traders.
+    pub recipient: NostrPubkey,
+    #[serde(with = "crate::cdk_pubkey_serde")]
+    pub ecash_pubkey: CDKPubkey,
+    pub created_at: Timestamp,
+    /// Random nonce of the sender's handshake, the partner's answer has to echo it.
+    pub nonce: String,
+    /// The nonce of the partner's announcement this answers. Only answers to the nonce of
+    /// the running handshake are taken, so announcements of earlier handshakes can't be replayed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    pub signature: Signature,
+}
+
+impl EcashPubkeyAnnouncement {
+    const SIGNING_TAG: &'static str = "cashu-escrow/ecash-pubkey-announcement";
+
+    pub fn new(
+        keys: &Keys,
+        recipient: NostrPubkey,
+        ecash_pubkey: CDKPubkey,
+        nonce: String,
+        reply_to: Option<String>,
+    ) -> Self {
+        let created_at = Timestamp::now();
+        let data = Self::signed_data(
+            &keys.public_key(),
+            &recipient,
+            &ecash_pubkey,
+            created_at,
+            &nonce,
+            reply_to.as_deref(),
+        );
+        Self {
+            nostr_pubkey: keys.public_key(),
+            recipient,
+            ecash_pubkey,
+            created_at,
+            nonce,
+            reply_to,
+            signature: signing::sign(keys, Self::SIGNING_TAG, data.as_bytes()),
+        }
+    }
+
+    /// Checks the signature of the announcing trader.
+    pub fn verify(&self) -> Result<()> {
+        let data = Self::signed_data(
+            &self.nostr_pubkey,
+            &self.recipient,
+            &self.ecash_pubkey,
+            self.created_at,
+            &self.nonce,
+            self.reply_to.as_deref(),
+        );
+        signing::verify(
+            &self.nostr_pubkey,
+            Self::SIGNING_TAG,
+            data.as_bytes(),
+            &self.signature,
+        )
+    }
+
+    fn signed_data(
+        nostr_pubkey: &NostrPubkey,
+        recipient: &NostrPubkey,
+        ecash_pubkey: &CDKPubkey,
+        created_at: Timestamp,
+        nonce: &str,
+        reply_to: Option<&str>,
+    ) -> String {
+        format!(
+            "{}:{}:{}:{}:{}:{}",
+            nostr_pubkey.to_hex(),
+            recipient.to_hex(),
+            ecash_pubkey.to_hex(),
+            created_at.as_u64(),
+            nonce,
+            reply_to.unwrap_or_default()
+        )
+    }
+}
+
+/// Sent by the buyer to the seller to release the escrowed funds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EscrowRelease {
+    pub escrow_id_hex: String,
+    /// The escrow token carrying the buyer's signature.
+    #[serde(with = "crate::token::token_serde")]
+    pub signed_token: Token,
+}
+
+/// Outcome of an escrow mediation decided by the coordinator.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Verdict {
+    BuyerWins,
+    SellerWins,
+    /// The buyer receives `buyer_amount` of the escrowed funds and the seller the rest.
+    ///
+    /// The amount is denominated in the currency unit of the contract.
+    Split {
+        buyer_amount: u64,
+    },
+}
+
+/// Sent by a trader to the coordinator to open a dispute or to respond to an open one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DisputeClaim {
+    pub escrow_id_hex: String,
+    pub requested_verdict: Verdict,
+    pub statement: String,
+    pub evidence: Vec<String>,
+    /// The escrow token, needed by the coordinator to co-sign the payout.
+    #[serde(default, with = "crate::token::token_serde::option")]
+    pub escrow_token: Option<Token>,
+    /// Reveals the private terms of a contract in privacy mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms_opening: Option<TermsOpening>,
+}
+
+/// The coordinator's decision, sent to both traders once the dispute is resolved.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DisputeVerdict {
+    pub escrow_id_hex: String,
+    pub verdict: Verdict,
+    /// The escrow proofs the receiving trader is entitled to, already signed by the coordinator.
+    #[serde(default, with = "crate::token::token_serde::option")]
+    pub cosigned_token: Option<Token>,
+}
+
+/// Sent to both traders instead of the verdict if the coordinator couldn't carry out the
+/// resolution, e.g. because no escrow token was submitted. A new claim reopens the dispute.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DisputeFailure {
+    pub escrow_id_hex: String,
+    pub reason: String,
+}
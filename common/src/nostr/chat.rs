@@ -0,0 +1,141 @@
+//! Chat between buyer, seller and coordinator about a disputed trade.
+//!
+//! A chat message is a private direct message (NIP-17) addressed to all participants, gift
+//! wrapped for each of them and for the sender, so it also shows up in the sender's history.
+//! The message carries the escrow id in an `escrow` tag, which keeps it apart from the escrow
+//! messages of the protocol.
+
+use crate::error::Result;
+use nostr_sdk::prelude::*;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Tag of the chat messages holding the escrow id.
+pub const ESCROW_CHAT_TAG: &str = "escrow";
+
+/// Decrypted message of a dispute chat.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChatMessage {
+    pub sender: PublicKey,
+    pub content: String,
+    pub created_at: Timestamp,
+}
+
+/// The chat about one escrow, see [`crate::nostr::NostrClient::dispute_chat`].
+#[derive(Clone)]
+pub struct DisputeChat {
+    client: Client,
+    own_pubkey: PublicKey,
+    escrow_id_hex: String,
+    participants: Vec<PublicKey>,
+}
+
+impl DisputeChat {
+    /// The participants are the other members of the chat, we are always part of it.
+    pub fn new(
+        client: Client,
+        own_pubkey: PublicKey,
+        escrow_id_hex: String,
+        participants: Vec<PublicKey>,
+    ) -> Self {
+        let mut members = vec![own_pubkey];
+        for participant in participants {
+            if !members.contains(&participant) {
+                members.push(participant);
+            }
+        }
+        Self {
+            client,
+            own_pubkey,
+            escrow_id_hex,
+            participants: members,
+        }
+    }
+
+    pub fn escrow_id_hex(&self) -> &str {
+        &self.escrow_id_hex
+    }
+
+    pub fn participants(&self) -> &[PublicKey] {
+        &self.participants
+    }
+
+    /// Sends the message to all participants and a copy to ourselves.
+    pub async fn send(&self, content: &str) -> Result<()> {
+        let rumor = chat_rumor(&self.escrow_id_hex, &self.participants, content);
+        for participant in &self.participants {
+            self.client
+                .gift_wrap(participant, rumor.clone(), None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the messages of the chat from the relays, oldest first.
+    ///
+    /// Only messages of the participants are returned, messages sent to several of our relays
+    /// are returned once.
+    pub async fn history(&self, timeout_secs: u64) -> Result<Vec<ChatMessage>> {
+        let filter = Filter::new().kind(Kind::GiftWrap).pubkey(self.own_pubkey);
+        let events = self
+            .client
+            .get_events_of(
+                vec![filter],
+                EventSource::relays(Some(Duration::from_secs(timeout_secs))),
+            )
+            .await?;
+        let mut seen = HashSet::new();
+        let mut messages = Vec::new();
+        for event in events {
+            let Ok(unwrapped) = self.client.unwrap_gift_wrap(&event).await else {
+                continue;
+            };
+            if let Some(message) = self.chat_message(unwrapped.rumor) {
+                if seen.insert(message.clone()) {
+                    messages.push(message);
+                }
+            }
+        }
+        messages.sort_by_key(|message| message.created_at);
+        Ok(messages)
+    }
+
+    /// The message if the rumor belongs to this chat.
+    pub fn chat_message(&self, rumor: UnsignedEvent) -> Option<ChatMessage> {
+        if chat_escrow_id(&rumor) != Some(self.escrow_id_hex.as_str())
+            || !self.participants.contains(&rumor.pubkey)
+        {
+            return None;
+        }
+        Some(ChatMessage {
+            sender: rumor.pubkey,
+            content: rumor.content,
+            created_at: rumor.created_at,
+        })
+    }
+}
+
+/// Builds the rumor of a chat message addressed to all participants.
+pub fn chat_rumor(escrow_id_hex: &str, participants: &[PublicKey], content: &str) -> EventBuilder {
+    let mut tags: Vec<Tag> = participants
+        .iter()
+        .map(|participant| Tag::public_key(*participant))
+        .collect();
+    tags.push(Tag::custom(
+        TagKind::custom(ESCROW_CHAT_TAG),
+        [escrow_id_hex],
+    ));
+    EventBuilder::new(Kind::PrivateDirectMessage, content, tags)
+}
+
+/// The escrow id of a chat message, `None` for all other direct messages.
+pub fn chat_escrow_id(rumor: &UnsignedEvent) -> Option<&str> {
+    if rumor.kind != Kind::PrivateDirectMessage {
+        return None;
+    }
+    rumor
+        .tags
+        .iter()
+        .find(|tag| tag.kind() == TagKind::custom(ESCROW_CHAT_TAG))
+        .and_then(Tag::content)
+}
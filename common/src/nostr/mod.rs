@@ -1,11 +1,20 @@
-use crate::model::EscrowRegistration;
-use anyhow::Context;
+use crate::error::{EscrowError, Result};
+use crate::model::{EcashPubkeyAnnouncement, EscrowRegistration, TradeContract};
+use crate::payment_request::{PaymentRequest, PaymentRequestPayload};
+use crate::token::decode_token;
+use cdk::nuts::Token;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 use nostr_sdk::prelude::*;
+use nostr_sdk::secp256k1::schnorr::Signature;
 use serde::de::DeserializeOwned;
+use std::str::FromStr;
 use tokio::sync::broadcast::{error::RecvError, Receiver};
 
+pub mod chat;
+
+use chat::{chat_escrow_id, DisputeChat};
+
 pub struct NostrClient {
     keys: Keys,
     pub client: Client,
@@ -14,21 +23,28 @@ pub struct NostrClient {
     /// The nostr network is in general very fuzzy and makes only a few guaranties about message delivery.
     /// Messages can be posted several times and it is better no to do assumptions about the order of the messages.
     /// Therefore, we use a small cache of the last messages received for the case we'll need them later on.
-    messages_cache: Vec<String>,
+    messages_cache: Vec<DirectMessage>,
 }
 
 pub const CACHE_SIZE: usize = 10;
 
+/// Decrypted direct message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectMessage {
+    pub sender: PublicKey,
+    pub content: String,
+    pub created_at: Timestamp,
+}
+
 impl NostrClient {
-    pub async fn new(keys: Keys, relays: Vec<String>) -> anyhow::Result<Self> {
+    pub async fn new(keys: Keys, relays: Vec<String>) -> Result<Self> {
         let client = Client::new(&keys);
 
         // Connect to relays
         for relay in &relays {
-            client
-                .add_relay(relay)
-                .await
-                .context(format!("Error adding nostr relay: {}", relay))?;
+            client.add_relay(relay).await.map_err(|e| {
+                EscrowError::Transport(format!("Error adding nostr relay {}: {}", relay, e))
+            })?;
         }
         client.connect().await;
 
@@ -48,15 +64,71 @@ impl NostrClient {
     }
 
     pub async fn receive_escrow_message<T: DeserializeOwned>(
+        &mut self,
+        timeout_secs: u64,
+    ) -> Result<T> {
+        self.receive_message(timeout_secs, |message| {
+            Ok(serde_json::from_str(&message.content)?)
+        })
+        .await
+    }
+
+    /// Like [`NostrClient::receive_escrow_message`], but only takes messages of the sender.
+    ///
+    /// Messages of others stay in the cache.
+    pub async fn receive_escrow_message_from<T: DeserializeOwned>(
+        &mut self,
+        sender: PublicKey,
+        timeout_secs: u64,
+    ) -> Result<T> {
+        self.receive_message(timeout_secs, |message| {
+            if message.sender != sender {
+                return Err(EscrowError::Protocol(format!(
+                    "Message from {} instead of {}",
+                    message.sender, sender
+                )));
+            }
+            Ok(serde_json::from_str(&message.content)?)
+        })
+        .await
+    }
+
+    /// Receives an escrow token sent as `cashuA` or `cashuB` token string,
+    /// or as payload of a payment request (NUT-18).
+    pub async fn receive_escrow_token(&mut self, timeout_secs: u64) -> Result<Token> {
+        self.receive_message(timeout_secs, |message| {
+            decode_token(&message.content).or_else(|_| {
+                let payload: PaymentRequestPayload = serde_json::from_str(&message.content)?;
+                Ok(Token::new(
+                    payload.mint,
+                    payload.proofs,
+                    payload.memo,
+                    Some(payload.unit),
+                ))
+            })
+        })
+        .await
+    }
+
+    /// Receives a payment request (NUT-18) sent as `creqA` string.
+    pub async fn receive_payment_request(&mut self, timeout_secs: u64) -> Result<PaymentRequest> {
+        self.receive_message(timeout_secs, |message| {
+            PaymentRequest::from_str(&message.content)
+        })
+        .await
+    }
+
+    async fn receive_message<T>(
         &mut self,
         _timeout_secs: u64,
-    ) -> anyhow::Result<T> {
+        parse: impl Fn(&DirectMessage) -> Result<T>,
+    ) -> Result<T> {
         let hit_idx_res = self
             .messages_cache
             .iter()
             .enumerate()
             .find_map(|(idx, message)| {
-                let result = serde_json::from_str::<T>(message).map_err(anyhow::Error::new);
+                let result = parse(message);
                 match result {
                     Ok(_) => Some((idx, result)),
                     _ => None,
@@ -71,37 +143,21 @@ impl NostrClient {
         trace!("No hit in messages cache, waiting for new messages...");
         let loop_future = async {
             loop {
-                match self.notifications_receiver.recv().await {
-                    Ok(notification) => {
-                        if let RelayPoolNotification::Event { event, .. } = notification {
-                            let rumor = self.client.unwrap_gift_wrap(&event).await?.rumor;
-                            if rumor.kind == Kind::PrivateDirectMessage {
-                                let result = serde_json::from_str::<T>(&rumor.content)
-                                    .map_err(anyhow::Error::new);
-                                match result {
-                                    Ok(_) => break result,
-                                    _ => {
-                                        trace!("Got an in this state unexpected escrow message, putting event in cache: {}", event.id);
-                                        if self.messages_cache.contains(&rumor.content) {
-                                            continue;
-                                        }
-                                        if self.messages_cache.len() == CACHE_SIZE {
-                                            self.messages_cache.remove(0);
-                                        }
-                                        self.messages_cache.push(rumor.content);
-                                    }
-                                }
-                            }
+                let message = self.receive_direct_message().await?;
+                let result = parse(&message);
+                match result {
+                    Ok(_) => break result,
+                    _ => {
+                        trace!(
+                            "Got an in this state unexpected escrow message, putting it in cache"
+                        );
+                        if self.messages_cache.contains(&message) {
+                            continue;
                         }
-                    }
-                    Err(RecvError::Closed) => {
-                        error!("Relay pool closed subscription, restarting a new one...");
-                        self.client.unsubscribe(self.subscription_id.clone()).await;
-                        (self.subscription_id, self.notifications_receiver) =
-                            init_subscription(&self.keys, &self.client).await?;
-                    }
-                    Err(RecvError::Lagged(count)) => {
-                        warn!("Lost {} events, proceeding after that...", count);
+                        if self.messages_cache.len() == CACHE_SIZE {
+                            self.messages_cache.remove(0);
+                        }
+                        self.messages_cache.push(message);
                     }
                 }
             }
@@ -111,24 +167,57 @@ impl NostrClient {
         match tokio::time::timeout(std::time::Duration::from_secs(_timeout_secs), loop_future).await
         {
             Ok(result) => result,
-            Err(e) => Err(anyhow::anyhow!("Timeout, {}", e)),
+            Err(e) => Err(EscrowError::Timeout(e.to_string())),
         }
         // TODO: Improve this workaround for wasm. For now we resign to timeout if it takes too long.
         #[cfg(target_arch = "wasm32")]
         loop_future.await
     }
 
+    /// Waits for the next direct message (NIP-17) of any content, e.g. to monitor the incoming messages.
+    ///
+    /// The message doesn't go through the cache of the escrow messages. Messages of a dispute
+    /// chat are skipped, they are read with [`DisputeChat::history`].
+    pub async fn receive_direct_message(&mut self) -> Result<DirectMessage> {
+        loop {
+            match self.notifications_receiver.recv().await {
+                Ok(RelayPoolNotification::Event { event, .. }) => {
+                    let rumor = self.client.unwrap_gift_wrap(&event).await?.rumor;
+                    if rumor.kind == Kind::PrivateDirectMessage && chat_escrow_id(&rumor).is_none()
+                    {
+                        return Ok(DirectMessage {
+                            sender: rumor.pubkey,
+                            content: rumor.content,
+                            created_at: rumor.created_at,
+                        });
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Closed) => {
+                    error!("Relay pool closed subscription, restarting a new one...");
+                    self.client.unsubscribe(self.subscription_id.clone()).await;
+                    (self.subscription_id, self.notifications_receiver) =
+                        init_subscription(&self.keys, &self.client).await?;
+                }
+                Err(RecvError::Lagged(count)) => {
+                    warn!("Lost {} events, proceeding after that...", count);
+                }
+            }
+        }
+    }
+
     // coordinator specific function?
     pub async fn send_escrow_registration(
         &self,
         receivers: (PublicKey, PublicKey),
         id: &[u8; 32],
         trade_pk: &str,
-    ) -> anyhow::Result<()> {
+        escrow_start_time: Timestamp,
+    ) -> Result<()> {
         let registration_json = serde_json::to_string(&EscrowRegistration {
             escrow_id_hex: hex::encode(id),
             coordinator_escrow_pubkey: cdk::nuts::PublicKey::from_hex(trade_pk)?,
-            escrow_start_time: Timestamp::now(),
+            escrow_start_time,
         })?;
         // todo: replace deprecated method
         self.client
@@ -140,6 +229,45 @@ impl NostrClient {
         Ok(())
     }
 
+    /// Signs the contract with our nostr key, see [`TradeContract::sign`].
+    pub fn sign_contract(&self, contract: &TradeContract) -> Result<Signature> {
+        contract.sign(&self.keys)
+    }
+
+    /// Sends our trade pubkey signed by our nostr key to the trade partner.
+    ///
+    /// `reply_to` is the nonce of the partner's announcement we answer.
+    pub async fn announce_ecash_pubkey(
+        &self,
+        recipient: PublicKey,
+        ecash_pubkey: cdk::nuts::PublicKey,
+        nonce: &str,
+        reply_to: Option<&str>,
+    ) -> Result<()> {
+        let announcement = EcashPubkeyAnnouncement::new(
+            &self.keys,
+            recipient,
+            ecash_pubkey,
+            nonce.to_string(),
+            reply_to.map(str::to_string),
+        );
+        self.client
+            .send_private_msg(recipient, serde_json::to_string(&announcement)?, None)
+            .await?;
+        Ok(())
+    }
+
+    /// The chat with the other participants about the escrow, e.g. the trade partner and the
+    /// coordinator during a dispute.
+    pub fn dispute_chat(&self, escrow_id_hex: &str, participants: Vec<PublicKey>) -> DisputeChat {
+        DisputeChat::new(
+            self.client.clone(),
+            self.public_key(),
+            escrow_id_hex.to_string(),
+            participants,
+        )
+    }
+
     pub fn messages_cache_len(&self) -> usize {
         self.messages_cache.len()
     }
@@ -148,7 +276,7 @@ impl NostrClient {
 async fn init_subscription(
     keys: &Keys,
     client: &Client,
-) -> Result<(SubscriptionId, Receiver<RelayPoolNotification>), anyhow::Error> {
+) -> Result<(SubscriptionId, Receiver<RelayPoolNotification>)> {
     let message_filter = Filter::new()
         .kind(Kind::GiftWrap)
         .pubkey(keys.public_key())
@@ -0,0 +1,153 @@
+//! Cashu payment requests (NUT-18), so any wallet supporting them can fund an escrow.
+
+use crate::error::{EscrowError, Result};
+use base64::{engine::general_purpose, Engine};
+use cdk::mint_url::MintUrl;
+use cdk::nuts::{CurrencyUnit, Kind, Nut10Secret, Proofs, SecretData, SpendingConditions};
+use cdk::Amount;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+const PAYMENT_REQUEST_PREFIX: &str = "creqA";
+
+/// Request for a cashu payment, encoded as `creqA` string.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentRequest {
+    #[serde(rename = "i", default, skip_serializing_if = "Option::is_none")]
+    pub payment_id: Option<String>,
+    #[serde(rename = "a", default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Amount>,
+    #[serde(rename = "u", default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<CurrencyUnit>,
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub single_use: Option<bool>,
+    /// Mints the payment is accepted from.
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub mints: Option<Vec<MintUrl>>,
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Ways to deliver the payment, an empty list means out of band.
+    #[serde(rename = "t", default)]
+    pub transports: Vec<Transport>,
+    /// Spending condition the proofs have to be locked to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nut10: Option<Nut10Condition>,
+}
+
+impl PaymentRequest {
+    /// Spending conditions of the requested proofs, if any.
+    pub fn spending_conditions(&self) -> Result<Option<SpendingConditions>> {
+        self.nut10
+            .as_ref()
+            .map(SpendingConditions::try_from)
+            .transpose()
+    }
+}
+
+impl fmt::Display for PaymentRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = Vec::new();
+        ciborium::into_writer(self, &mut data).map_err(|_| fmt::Error)?;
+        let encoded = general_purpose::URL_SAFE.encode(data);
+        write!(f, "{}{}", PAYMENT_REQUEST_PREFIX, encoded)
+    }
+}
+
+impl FromStr for PaymentRequest {
+    type Err = EscrowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let encoded =
+            s.trim()
+                .strip_prefix(PAYMENT_REQUEST_PREFIX)
+                .ok_or(EscrowError::Validation(
+                    "Payment request doesn't start with creqA".to_string(),
+                ))?;
+        let decode_config = general_purpose::GeneralPurposeConfig::new()
+            .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent);
+        let data = general_purpose::GeneralPurpose::new(&base64::alphabet::URL_SAFE, decode_config)
+            .decode(encoded)
+            .map_err(|e| EscrowError::Validation(format!("Invalid payment request: {}", e)))?;
+        ciborium::from_reader(&data[..])
+            .map_err(|e| EscrowError::Validation(format!("Invalid payment request: {}", e)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// NIP-17 direct message to the nprofile in the target.
+    Nostr,
+    /// HTTP POST of the payload to the url in the target.
+    Post,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transport {
+    #[serde(rename = "t")]
+    pub kind: TransportKind,
+    #[serde(rename = "a")]
+    pub target: String,
+    #[serde(rename = "g", default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<Vec<String>>>,
+}
+
+impl Transport {
+    /// Transport over NIP-17 direct messages to the nprofile.
+    pub fn nostr(nprofile: String) -> Self {
+        Self {
+            kind: TransportKind::Nostr,
+            target: nprofile,
+            tags: Some(vec![vec!["n".to_string(), "17".to_string()]]),
+        }
+    }
+}
+
+/// NUT-10 spending condition of a payment request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Nut10Condition {
+    #[serde(rename = "k")]
+    pub kind: Kind,
+    #[serde(rename = "d")]
+    pub data: String,
+    #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<Vec<String>>>,
+}
+
+impl From<SpendingConditions> for Nut10Condition {
+    fn from(conditions: SpendingConditions) -> Self {
+        let secret = Nut10Secret::from(conditions);
+        Self {
+            kind: secret.kind,
+            data: secret.secret_data.data,
+            tags: secret.secret_data.tags,
+        }
+    }
+}
+
+impl TryFrom<&Nut10Condition> for SpendingConditions {
+    type Error = EscrowError;
+
+    fn try_from(condition: &Nut10Condition) -> Result<Self> {
+        let secret = Nut10Secret {
+            kind: condition.kind,
+            secret_data: SecretData {
+                nonce: String::new(),
+                data: condition.data.clone(),
+                tags: condition.tags.clone(),
+            },
+        };
+        Ok(secret.try_into()?)
+    }
+}
+
+/// Payment sent in response to a [`PaymentRequest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentRequestPayload {
+    pub id: Option<String>,
+    pub memo: Option<String>,
+    pub mint: MintUrl,
+    pub unit: CurrencyUnit,
+    pub proofs: Proofs,
+}
@@ -0,0 +1,32 @@
+//! Schnorr signatures of the traders' nostr keys over escrow messages.
+//!
+//! The signed data is hashed with a tag (BIP-340 tagged hash), so a signature made for one
+//! kind of message can't be passed off as a signature of another one.
+
+use crate::error::{EscrowError, Result};
+use nostr_sdk::hashes::{sha256, Hash, HashEngine};
+use nostr_sdk::secp256k1::{schnorr::Signature, Message};
+use nostr_sdk::{Keys, PublicKey, SECP256K1};
+
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_ref());
+    engine.input(tag_hash.as_ref());
+    engine.input(data);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+pub fn sign(keys: &Keys, tag: &str, data: &[u8]) -> Signature {
+    keys.sign_schnorr(&Message::from_digest(tagged_hash(tag, data)))
+}
+
+pub fn verify(pubkey: &PublicKey, tag: &str, data: &[u8], signature: &Signature) -> Result<()> {
+    SECP256K1
+        .verify_schnorr(
+            signature,
+            &Message::from_digest(tagged_hash(tag, data)),
+            pubkey,
+        )
+        .map_err(|_| EscrowError::Validation(format!("Invalid signature of {}", pubkey)))
+}
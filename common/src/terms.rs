@@ -0,0 +1,155 @@
+//! Structured obligations of buyer and seller, the optional terms section of a [`TradeContract`].
+//!
+//! The terms carry a schema version, so clients and coordinators can evolve them. A newer
+//! schema than the one of this crate is rejected by [`TradeTerms::validate`].
+
+use crate::error::{EscrowError, Result};
+use crate::model::TradeContract;
+use serde::{Deserialize, Serialize};
+
+/// Version of the terms schema implemented by this crate.
+pub const TERMS_SCHEMA_VERSION: u16 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TradeTerms {
+    pub schema_version: u16,
+    pub line_items: Vec<LineItem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<Delivery>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_policy: Option<ReturnPolicy>,
+    /// How the seller wants to be paid out, the escrow token by default.
+    #[serde(default)]
+    pub seller_payout: SellerPayout,
+    /// Seconds after the delivery deadline the buyer has to open a dispute about the delivery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispute_window: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineItem {
+    pub description: String,
+    pub quantity: u64,
+    /// Price per unit in the currency unit of the contract. If all items have a price, they
+    /// have to add up to the trade amount.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_price: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Delivery {
+    pub method: DeliveryMethod,
+    /// Seconds after the registration of the escrow the seller has to deliver in.
+    pub deadline: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryMethod {
+    Shipping,
+    Pickup,
+    Digital,
+    Service,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReturnPolicy {
+    pub returns_accepted: bool,
+    /// Seconds after the delivery the buyer can return the goods in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_window: Option<u64>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub conditions: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum SellerPayout {
+    /// The seller redeems the ecash of the escrow token.
+    #[default]
+    Ecash,
+    /// The seller melts the escrow token to a lightning address.
+    Lightning { address: String },
+}
+
+impl TradeTerms {
+    /// Checks the terms on their own and against the rest of the contract.
+    pub fn validate(&self, contract: &TradeContract) -> Result<()> {
+        if self.schema_version == 0 || self.schema_version > TERMS_SCHEMA_VERSION {
+            return Err(invalid(format!(
+                "Unsupported schema version {}, supported up to {}",
+                self.schema_version, TERMS_SCHEMA_VERSION
+            )));
+        }
+        if self.line_items.is_empty() {
+            return Err(invalid("No line items".to_string()));
+        }
+        for item in &self.line_items {
+            if item.description.trim().is_empty() {
+                return Err(invalid("Line item without description".to_string()));
+            }
+            if item.quantity == 0 {
+                return Err(invalid(format!(
+                    "Line item '{}' has no quantity",
+                    item.description
+                )));
+            }
+        }
+        if let Some(total) = self.items_total()? {
+            if total != contract.trade_amount {
+                return Err(invalid(format!(
+                    "The line items add up to {} {}, the trade amount is {}",
+                    total, contract.currency_unit, contract.trade_amount
+                )));
+            }
+        }
+        if let Some(delivery) = &self.delivery {
+            if delivery.deadline == 0 || delivery.deadline >= contract.time_limit {
+                return Err(invalid(
+                    "The delivery deadline has to be before the buyer can refund the escrow"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(return_policy) = &self.return_policy {
+            if !return_policy.returns_accepted && return_policy.return_window.is_some() {
+                return Err(invalid(
+                    "Return window without accepting returns".to_string(),
+                ));
+            }
+        }
+        if let SellerPayout::Lightning { address } = &self.seller_payout {
+            if address
+                .split_once('@')
+                .is_none_or(|(user, domain)| user.is_empty() || domain.is_empty())
+            {
+                return Err(invalid(format!("Invalid lightning address {}", address)));
+            }
+        }
+        if self.dispute_window == Some(0) {
+            return Err(invalid("The dispute window has to be positive".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Sum of the line items, `None` unless all of them have a price.
+    pub fn items_total(&self) -> Result<Option<u64>> {
+        let mut total = 0u64;
+        for item in &self.line_items {
+            let Some(unit_price) = item.unit_price else {
+                return Ok(None);
+            };
+            total = unit_price
+                .checked_mul(item.quantity)
+                .and_then(|price| total.checked_add(price))
+                .ok_or(invalid(
+                    "The line items exceed the maximum amount".to_string(),
+                ))?;
+        }
+        Ok(Some(total))
+    }
+}
+
+fn invalid(reason: String) -> EscrowError {
+    EscrowError::Validation(format!("Invalid trade terms: {}", reason))
+}
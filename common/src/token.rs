@@ -0,0 +1,85 @@
+//! String encoding of cashu tokens, so escrow tokens can be inspected in any cashu wallet.
+
+use crate::error::Result;
+use cdk::nuts::{Token, TokenV4};
+use std::str::FromStr;
+
+/// Encodes the token as V4 `cashuB` string.
+///
+/// Tokens with proofs of several mints can't be represented in V4 and are encoded as V3 `cashuA`.
+pub fn encode_token(token: &Token) -> String {
+    match token {
+        Token::TokenV3(token_v3) => match TokenV4::try_from(token_v3.clone()) {
+            Ok(token_v4) => token_v4.to_string(),
+            Err(_) => token_v3.to_string(),
+        },
+        Token::TokenV4(token_v4) => token_v4.to_string(),
+    }
+}
+
+/// Decodes a `cashuA` or `cashuB` token string.
+pub fn decode_token(encoded: &str) -> Result<Token> {
+    Ok(Token::from_str(encoded.trim())?)
+}
+
+/// Serializes tokens as encoded token strings, accepts the former JSON representation too.
+pub(crate) mod token_serde {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub(super) enum TokenRepr {
+        Encoded(String),
+        Json(Token),
+    }
+
+    impl TokenRepr {
+        pub(super) fn into_token<E: serde::de::Error>(self) -> std::result::Result<Token, E> {
+            match self {
+                TokenRepr::Encoded(encoded) => decode_token(&encoded).map_err(E::custom),
+                TokenRepr::Json(token) => Ok(token),
+            }
+        }
+    }
+
+    pub fn serialize<S>(token: &Token, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&encode_token(token))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Token, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        TokenRepr::deserialize(deserializer)?.into_token()
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S>(
+            token: &Option<Token>,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match token {
+                Some(token) => serializer.serialize_some(&encode_token(token)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> std::result::Result<Option<Token>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Option::<TokenRepr>::deserialize(deserializer)?
+                .map(TokenRepr::into_token)
+                .transpose()
+        }
+    }
+}
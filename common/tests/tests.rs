@@ -1,7 +1,27 @@
 mod common;
 
-use cashu_escrow_common::nostr::CACHE_SIZE;
+use cashu_escrow_common::{
+    model::{
+        ContractSignatures, EcashPubkeyAnnouncement, EscrowRelease, FeePayer, SignedTradeContract,
+        TradeContract,
+    },
+    nostr::{
+        chat::{chat_escrow_id, chat_rumor, DisputeChat},
+        CACHE_SIZE,
+    },
+    payment_request::{PaymentRequest, Transport},
+    terms::{Delivery, DeliveryMethod, LineItem, SellerPayout, TradeTerms, TERMS_SCHEMA_VERSION},
+    token::{decode_token, encode_token},
+};
+use cdk::{
+    mint_url::MintUrl,
+    nuts::{Conditions, CurrencyUnit, Id, Proof, SecretKey, SigFlag, SpendingConditions, Token},
+    secret::Secret,
+    Amount,
+};
 use common::*;
+use nostr_sdk::{Client, EventBuilder, Keys, Timestamp};
+use std::str::FromStr;
 
 /// Receive a message when only one message was sent by the escrow.
 #[tokio::test]
@@ -38,3 +58,329 @@ async fn spam_protection() -> anyhow::Result<()> {
     assert_eq!(buyer_nostr_client.messages_cache_len(), CACHE_SIZE);
     Ok(())
 }
+
+#[test]
+fn verify_ecash_pubkey_announcement() -> anyhow::Result<()> {
+    let trader = Keys::generate();
+    let partner = Keys::generate();
+    let announcement = EcashPubkeyAnnouncement::new(
+        &trader,
+        partner.public_key(),
+        SecretKey::generate().public_key(),
+        Secret::generate().to_string(),
+        None,
+    );
+    let received: EcashPubkeyAnnouncement =
+        serde_json::from_str(&serde_json::to_string(&announcement)?)?;
+    assert_eq!(received, announcement);
+    assert!(received.verify().is_ok());
+
+    // the answer is bound to the nonce of the announcement it answers
+    let answer = EcashPubkeyAnnouncement::new(
+        &partner,
+        trader.public_key(),
+        SecretKey::generate().public_key(),
+        Secret::generate().to_string(),
+        Some(announcement.nonce.clone()),
+    );
+    assert!(answer.verify().is_ok());
+    let mut replayed = answer;
+    replayed.reply_to = Some(Secret::generate().to_string());
+    assert!(replayed.verify().is_err());
+
+    let mut forged = announcement.clone();
+    forged.ecash_pubkey = SecretKey::generate().public_key();
+    assert!(forged.verify().is_err());
+    let mut forwarded = announcement;
+    forwarded.recipient = Keys::generate().public_key();
+    assert!(forwarded.verify().is_err());
+    Ok(())
+}
+
+fn trade_contract(buyer: &Keys, seller: &Keys) -> anyhow::Result<TradeContract> {
+    Ok(TradeContract {
+        trade_description: "One watermelon".to_string(),
+        trade_amount: 5000,
+        npubkey_seller: seller.public_key(),
+        npubkey_buyer: buyer.public_key(),
+        npubkey_coordinator: Keys::generate().public_key(),
+        time_limit: 3600,
+        seller_ecash_public_key: SecretKey::generate().public_key().to_string(),
+        buyer_ecash_public_key: SecretKey::generate().public_key().to_string(),
+        mint_url: MintUrl::from_str("http://localhost:3338")?,
+        currency_unit: CurrencyUnit::Sat,
+        allowed_keysets: None,
+        fee_payer: FeePayer::default(),
+        terms: None,
+        privacy: None,
+    })
+}
+
+#[test]
+fn parse_contract_without_mint() -> anyhow::Result<()> {
+    let buyer = Keys::generate();
+    let seller = Keys::generate();
+    // the format of contracts before the mint and the unit were part of them
+    let json = format!(
+        r#"{{"trade_description":"Purchase of one Watermelon for 5000 satoshi. 3 days delivery to ...","trade_amount_sat":5000,"npubkey_seller":"{}","npubkey_buyer":"{}","npubkey_coordinator":"{}","time_limit":259200,"seller_ecash_public_key":"{}","buyer_ecash_public_key":"{}"}}"#,
+        seller.public_key(),
+        buyer.public_key(),
+        Keys::generate().public_key(),
+        SecretKey::generate().public_key(),
+        SecretKey::generate().public_key(),
+    );
+    let mint_url = MintUrl::from_str("http://localhost:3338")?;
+    assert!(serde_json::from_str::<TradeContract>(&json).is_err());
+
+    let contract = TradeContract::from_json(&json, &mint_url)?;
+    assert_eq!(contract.mint_url, mint_url);
+    assert_eq!(contract.trade_amount, 5000);
+    assert_eq!(contract.currency_unit, CurrencyUnit::Sat);
+    assert_eq!(contract.fee_payer, FeePayer::default());
+    // the mint of the contract takes precedence
+    let contract = trade_contract(&buyer, &seller)?;
+    let other_mint = MintUrl::from_str("https://mint.example.com")?;
+    assert_eq!(
+        TradeContract::from_json(&serde_json::to_string(&contract)?, &other_mint)?,
+        contract
+    );
+    Ok(())
+}
+
+#[test]
+fn sign_negotiated_contract() -> anyhow::Result<()> {
+    let buyer = Keys::generate();
+    let seller = Keys::generate();
+    let contract = trade_contract(&buyer, &seller)?;
+    let signatures = ContractSignatures {
+        buyer: contract.sign(&buyer)?,
+        seller: contract.sign(&seller)?,
+    };
+    assert!(signatures.verify(&contract).is_ok());
+
+    let mut countered = contract.clone();
+    countered.trade_amount = 4500;
+    assert!(countered.check_negotiable_changes(&contract).is_ok());
+    assert!(signatures.verify(&countered).is_err());
+    let mut other_mint = contract.clone();
+    other_mint.mint_url = MintUrl::from_str("http://localhost:3339")?;
+    assert!(other_mint.check_negotiable_changes(&contract).is_err());
+    Ok(())
+}
+
+#[test]
+fn verify_signed_trade_contract() -> anyhow::Result<()> {
+    let buyer = Keys::generate();
+    let seller = Keys::generate();
+    let contract = trade_contract(&buyer, &seller)?;
+    let signatures = ContractSignatures {
+        buyer: contract.sign(&buyer)?,
+        seller: contract.sign(&seller)?,
+    };
+    let signed = SignedTradeContract::new(contract.clone(), signatures.clone())?;
+    let received: SignedTradeContract = serde_json::from_str(&serde_json::to_string(&signed)?)?;
+    assert!(received.verify().is_ok());
+    assert_eq!(received.contract_hash()?, contract.contract_hash()?);
+
+    let swapped = ContractSignatures {
+        buyer: signatures.seller,
+        seller: signatures.buyer,
+    };
+    assert!(SignedTradeContract::new(contract.clone(), swapped).is_err());
+    let mut forged = received;
+    forged.contract.trade_amount = 1;
+    assert!(forged.verify().is_err());
+    Ok(())
+}
+
+#[test]
+fn reveal_committed_terms() -> anyhow::Result<()> {
+    let buyer = Keys::generate();
+    let seller = Keys::generate();
+    let mut contract = trade_contract(&buyer, &seller)?;
+    contract.make_private();
+    let coordinator_contract = contract.coordinator_contract()?;
+    assert!(coordinator_contract.trade_description.is_empty());
+    assert_eq!(
+        coordinator_contract.contract_hash()?,
+        contract.contract_hash()?
+    );
+    // the coordinator verifies the signatures of the traders' copy
+    let signatures = ContractSignatures {
+        buyer: contract.sign(&buyer)?,
+        seller: contract.sign(&seller)?,
+    };
+    assert!(signatures.verify(&coordinator_contract).is_ok());
+
+    let opening = contract.terms_opening().unwrap();
+    assert_eq!(coordinator_contract.reveal(&opening)?, contract);
+    let mut forged = opening;
+    forged.terms.trade_description = "Two watermelons".to_string();
+    assert!(coordinator_contract.reveal(&forged).is_err());
+    Ok(())
+}
+
+#[test]
+fn adopt_salt_only_in_privacy_mode() -> anyhow::Result<()> {
+    let buyer = Keys::generate();
+    let seller = Keys::generate();
+    let mut proposal = trade_contract(&buyer, &seller)?;
+    proposal.make_private();
+
+    let mut public_contract = proposal.clone();
+    public_contract.privacy = None;
+    public_contract.adopt_salt(&proposal);
+    assert_ne!(public_contract, proposal);
+    assert!(public_contract.check_negotiable_changes(&proposal).is_err());
+
+    let mut private_contract = proposal.clone();
+    private_contract.make_private();
+    assert_ne!(private_contract, proposal);
+    private_contract.adopt_salt(&proposal);
+    assert_eq!(private_contract, proposal);
+    Ok(())
+}
+
+#[test]
+fn validate_trade_terms() -> anyhow::Result<()> {
+    let mut contract = trade_contract(&Keys::generate(), &Keys::generate())?;
+    contract.terms = Some(TradeTerms {
+        schema_version: TERMS_SCHEMA_VERSION,
+        line_items: vec![LineItem {
+            description: "Watermelon".to_string(),
+            quantity: 2,
+            unit_price: Some(2500),
+        }],
+        delivery: Some(Delivery {
+            method: DeliveryMethod::Shipping,
+            deadline: 1800,
+        }),
+        return_policy: None,
+        seller_payout: SellerPayout::Lightning {
+            address: "seller@example.com".to_string(),
+        },
+        dispute_window: Some(600),
+    });
+    let received: TradeContract = serde_json::from_str(&contract.canonical_json()?)?;
+    assert_eq!(received, contract);
+    assert!(received.validate_terms().is_ok());
+
+    contract.trade_amount = 4000;
+    assert!(contract.validate_terms().is_err());
+    contract.trade_amount = 5000;
+    let terms = contract.terms.as_mut().unwrap();
+    terms.schema_version = TERMS_SCHEMA_VERSION + 1;
+    assert!(contract.validate_terms().is_err());
+    Ok(())
+}
+
+#[test]
+fn tag_dispute_chat_messages() -> anyhow::Result<()> {
+    let (buyer, seller, coordinator) = (Keys::generate(), Keys::generate(), Keys::generate());
+    let escrow_id_hex = "ab".repeat(32);
+    let chat = DisputeChat::new(
+        Client::new(&buyer),
+        buyer.public_key(),
+        escrow_id_hex.clone(),
+        vec![seller.public_key(), coordinator.public_key()],
+    );
+    assert_eq!(chat.participants().len(), 3);
+
+    let rumor = chat_rumor(&escrow_id_hex, chat.participants(), "Where is the parcel?")
+        .to_unsigned_event(coordinator.public_key());
+    assert_eq!(chat_escrow_id(&rumor), Some(escrow_id_hex.as_str()));
+    let message = chat.chat_message(rumor).expect("message of the chat");
+    assert_eq!(message.sender, coordinator.public_key());
+    assert_eq!(message.content, "Where is the parcel?");
+
+    let other_escrow = chat_rumor(&"cd".repeat(32), chat.participants(), "Hello")
+        .to_unsigned_event(seller.public_key());
+    assert!(chat.chat_message(other_escrow).is_none());
+    let stranger = chat_rumor(&escrow_id_hex, chat.participants(), "Hello")
+        .to_unsigned_event(Keys::generate().public_key());
+    assert!(chat.chat_message(stranger).is_none());
+
+    // escrow messages aren't taken for chat messages
+    let direct_message = EventBuilder::private_msg_rumor(seller.public_key(), "{}", None)
+        .to_unsigned_event(buyer.public_key());
+    assert_eq!(chat_escrow_id(&direct_message), None);
+    Ok(())
+}
+
+#[test]
+fn encode_escrow_token() -> anyhow::Result<()> {
+    let proof = Proof::new(
+        Amount::from(8),
+        Id::from_str("009a1f293253e41e")?,
+        Secret::generate(),
+        SecretKey::generate().public_key(),
+    );
+    let token = Token::new(
+        MintUrl::from_str("http://localhost:3338")?,
+        vec![proof],
+        Some("escrow".to_string()),
+        Some(CurrencyUnit::Sat),
+    );
+
+    let encoded = encode_token(&token);
+    assert!(encoded.starts_with("cashuB"));
+    assert_eq!(decode_token(&encoded)?.proofs(), token.proofs());
+    let decoded_v3 = decode_token(&token.to_v3_string())?;
+    assert_eq!(decoded_v3.proofs(), token.proofs());
+    assert!(encode_token(&decoded_v3).starts_with("cashuB"));
+
+    let release = EscrowRelease {
+        escrow_id_hex: "00".repeat(32),
+        signed_token: token.clone(),
+    };
+    let release_json = serde_json::to_string(&release)?;
+    assert!(release_json.contains(&encoded));
+    assert_eq!(
+        serde_json::from_str::<EscrowRelease>(&release_json)?.signed_token,
+        token
+    );
+    // releases from before the string encoding still parse
+    let legacy_json = serde_json::json!({
+        "escrow_id_hex": release.escrow_id_hex,
+        "signed_token": token,
+    });
+    assert_eq!(
+        serde_json::from_value::<EscrowRelease>(legacy_json)?.signed_token,
+        token
+    );
+    Ok(())
+}
+
+#[test]
+fn encode_payment_request() -> anyhow::Result<()> {
+    let seller = SecretKey::generate().public_key();
+    let buyer = SecretKey::generate().public_key();
+    let conditions = SpendingConditions::new_p2pk(
+        seller,
+        Some(Conditions::new(
+            Some(Timestamp::now().as_u64() + 3600),
+            Some(vec![buyer]),
+            Some(vec![buyer]),
+            Some(2),
+            Some(SigFlag::SigAll),
+        )?),
+    );
+    let request = PaymentRequest {
+        payment_id: Some("00".repeat(32)),
+        amount: Some(Amount::from(5000)),
+        unit: Some(CurrencyUnit::Sat),
+        single_use: Some(true),
+        mints: Some(vec![MintUrl::from_str("http://localhost:3338")?]),
+        description: Some("Test trade".to_string()),
+        transports: vec![Transport::nostr("nprofile1test".to_string())],
+        nut10: Some(conditions.clone().into()),
+    };
+
+    let encoded = request.to_string();
+    assert!(encoded.starts_with("creqA"));
+    let decoded = PaymentRequest::from_str(&encoded)?;
+    assert_eq!(decoded, request);
+    assert_eq!(decoded.spending_conditions()?, Some(conditions));
+    assert!(PaymentRequest::from_str("cashuBo2F0").is_err());
+    Ok(())
+}
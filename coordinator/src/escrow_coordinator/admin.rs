@@ -0,0 +1,140 @@
+use super::*;
+use cashu_escrow_common::evidence::AttachmentHeader;
+use cashu_escrow_common::nostr::chat::DisputeChat;
+use cashu_escrow_common::nostr::NostrClient;
+use dispute::{DisputeCase, DisputeState, Evidence, ManualDisputeResolver};
+
+/// Access to the trades and disputes for the operator of the coordinator.
+///
+/// Obtained by [`EscrowCoordinator::admin`], it stays usable while the coordinator runs.
+#[derive(Clone)]
+pub struct CoordinatorAdmin {
+    storage: Arc<dyn CoordinatorStorage>,
+    nostr_client: Client,
+    public_key: PublicKey,
+    manual_resolver: Option<ManualDisputeResolver>,
+}
+
+impl CoordinatorAdmin {
+    pub(super) fn new(
+        storage: Arc<dyn CoordinatorStorage>,
+        nostr_client: &NostrClient,
+        manual_resolver: Option<ManualDisputeResolver>,
+    ) -> Self {
+        Self {
+            storage,
+            nostr_client: nostr_client.client.clone(),
+            public_key: nostr_client.public_key(),
+            manual_resolver,
+        }
+    }
+
+    /// The active trades by their escrow id.
+    pub fn active_trades(&self) -> Result<Vec<(String, ActiveTrade)>> {
+        Ok(self
+            .storage
+            .active_trades()?
+            .into_iter()
+            .map(|(contract_hash, trade)| (hex::encode(contract_hash), trade))
+            .collect())
+    }
+
+    /// The evidence the traders submitted on its own, in the order it was received.
+    pub fn evidence(&self, escrow_id_hex: &str) -> Result<Vec<Evidence>> {
+        self.storage.evidence(&parse_escrow_id(escrow_id_hex)?)
+    }
+
+    /// The verified content of an attachment by its hash, `None` until it was received
+    /// completely.
+    pub fn attachment(
+        &self,
+        escrow_id_hex: &str,
+        attachment_sha256: &str,
+    ) -> Result<Option<(AttachmentHeader, Vec<u8>)>> {
+        let contract_hash = parse_escrow_id(escrow_id_hex)?;
+        let header = self
+            .storage
+            .evidence(&contract_hash)?
+            .into_iter()
+            .flat_map(|evidence| evidence.attachments)
+            .find(|header| header.sha256 == attachment_sha256)
+            .ok_or(EscrowError::Validation(format!(
+                "No attachment {} for {}",
+                attachment_sha256, escrow_id_hex
+            )))?;
+        Ok(
+            dispute::load_attachment(self.storage.as_ref(), &contract_hash, &header)?
+                .map(|content| (header, content)),
+        )
+    }
+
+    /// The disputes waiting for a decision of the operator.
+    ///
+    /// Fails if the coordinator was built with its own [`DisputeResolver`].
+    pub fn pending_disputes(&self) -> Result<Vec<DisputeCase>> {
+        self.manual_resolver()?.pending_disputes()
+    }
+
+    /// Decides a pending dispute, the verdict is signed and sent to the traders.
+    pub fn decide(&self, escrow_id_hex: &str, verdict: Verdict) -> Result<()> {
+        self.manual_resolver()?.decide(escrow_id_hex, verdict)
+    }
+
+    fn manual_resolver(&self) -> Result<&ManualDisputeResolver> {
+        self.manual_resolver.as_ref().ok_or(EscrowError::Validation(
+            "Disputes are decided by the resolver of the host application".to_string(),
+        ))
+    }
+
+    /// Hands a failed dispute to the resolver again, e.g. after the cause of the failure was
+    /// fixed.
+    pub fn reopen_dispute(&self, escrow_id_hex: &str) -> Result<()> {
+        let contract_hash = parse_escrow_id(escrow_id_hex)?;
+        let mut trade =
+            self.storage
+                .get_active_trade(&contract_hash)?
+                .ok_or(EscrowError::Validation(format!(
+                    "No active trade {}",
+                    escrow_id_hex
+                )))?;
+        match &mut trade.dispute {
+            Some(dispute) if matches!(dispute.state, DisputeState::Failed(_)) => {
+                dispute.state = DisputeState::Open;
+            }
+            _ => {
+                return Err(EscrowError::Validation(format!(
+                    "No failed dispute for {}",
+                    escrow_id_hex
+                )))
+            }
+        }
+        self.storage.update_active_trade(&contract_hash, trade)
+    }
+
+    /// The chat with buyer and seller about a disputed trade, available once a dispute was
+    /// opened.
+    pub fn dispute_chat(&self, escrow_id_hex: &str) -> Result<DisputeChat> {
+        let trade = self
+            .storage
+            .get_active_trade(&parse_escrow_id(escrow_id_hex)?)?
+            .ok_or(EscrowError::Validation(format!(
+                "No active trade {}",
+                escrow_id_hex
+            )))?;
+        if trade.dispute.is_none() {
+            return Err(EscrowError::Validation(format!(
+                "No dispute is open for {}",
+                escrow_id_hex
+            )));
+        }
+        Ok(DisputeChat::new(
+            self.nostr_client.clone(),
+            self.public_key,
+            escrow_id_hex.to_string(),
+            vec![
+                trade.trade_contract.npubkey_buyer,
+                trade.trade_contract.npubkey_seller,
+            ],
+        ))
+    }
+}
@@ -0,0 +1,87 @@
+use super::*;
+use key_source::RandomKeySource;
+use storage::MemoryCoordinatorStorage;
+
+/// Assembles an [`EscrowCoordinator`], only the transport is mandatory.
+///
+/// Storage defaults to [`MemoryCoordinatorStorage`], the policy to [`CoordinatorPolicy::default`],
+/// escrow keys are generated by [`RandomKeySource`] and disputes are queued in a
+/// [`ManualDisputeResolver`], which is worked off through [`EscrowCoordinator::admin`].
+#[derive(Default)]
+pub struct EscrowCoordinatorBuilder {
+    nostr_client: Option<NostrClient>,
+    storage: Option<Arc<dyn CoordinatorStorage>>,
+    policy: Option<CoordinatorPolicy>,
+    key_source: Option<Box<dyn EscrowKeySource>>,
+    dispute_resolver: Option<Arc<dyn DisputeResolver>>,
+    event_hooks: Vec<TradeEventHook>,
+}
+
+impl EscrowCoordinatorBuilder {
+    /// The nostr client used to communicate with the traders.
+    pub fn transport(mut self, nostr_client: NostrClient) -> Self {
+        self.nostr_client = Some(nostr_client);
+        self
+    }
+
+    pub fn storage(mut self, storage: Arc<dyn CoordinatorStorage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn policy(mut self, policy: CoordinatorPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Source of the coordinator keys the escrow tokens get locked to.
+    pub fn key_source(mut self, key_source: impl EscrowKeySource + 'static) -> Self {
+        self.key_source = Some(Box::new(key_source));
+        self
+    }
+
+    pub fn dispute_resolver(mut self, dispute_resolver: Arc<dyn DisputeResolver>) -> Self {
+        self.dispute_resolver = Some(dispute_resolver);
+        self
+    }
+
+    /// Registers a callback which is invoked for every [`TradeEvent`].
+    ///
+    /// Hooks are called from within the coordinator loop, so they should return quickly.
+    pub fn on_event(mut self, hook: impl Fn(&TradeEvent) + Send + Sync + 'static) -> Self {
+        self.event_hooks.push(Arc::new(hook));
+        self
+    }
+
+    pub fn build(self) -> Result<EscrowCoordinator> {
+        let nostr_client = self.nostr_client.ok_or(EscrowError::Validation(
+            "No transport set for the escrow coordinator".to_string(),
+        ))?;
+        let (dispute_resolver, manual_resolver) = match self.dispute_resolver {
+            Some(dispute_resolver) => (dispute_resolver, None),
+            None => {
+                let manual_resolver = ManualDisputeResolver::default();
+                let dispute_resolver: Arc<dyn DisputeResolver> = Arc::new(manual_resolver.clone());
+                (dispute_resolver, Some(manual_resolver))
+            }
+        };
+        let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+        let (verdict_sender, verdict_receiver) = mpsc::unbounded_channel();
+        Ok(EscrowCoordinator {
+            nostr_client,
+            storage: self
+                .storage
+                .unwrap_or_else(|| Arc::new(MemoryCoordinatorStorage::default())),
+            policy: self.policy.unwrap_or_default(),
+            key_source: self.key_source.unwrap_or_else(|| Box::new(RandomKeySource)),
+            dispute_resolver,
+            manual_resolver,
+            verdict_sender,
+            verdict_receiver,
+            event_hooks: self.event_hooks,
+            shutdown_sender: Arc::new(shutdown_sender),
+            shutdown_receiver,
+            received_events: HashSet::new(),
+        })
+    }
+}
@@ -0,0 +1,368 @@
+use super::*;
+use async_trait::async_trait;
+use cashu_escrow_common::evidence::AttachmentHeader;
+use cashu_escrow_common::model::{ContractSignatures, DisputeClaim, TermsOpening, Verdict};
+use cdk::nuts::Token;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Party {
+    Buyer,
+    Seller,
+}
+
+impl Party {
+    /// The trading partner of the party.
+    pub fn other(self) -> Party {
+        match self {
+            Party::Buyer => Party::Seller,
+            Party::Seller => Party::Buyer,
+        }
+    }
+}
+
+/// A piece of evidence together with the trader who submitted it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evidence {
+    pub submitted_by: Party,
+    pub content: String,
+    /// Their content is kept in the [`CoordinatorStorage`], see [`DisputeCase::attachments`].
+    pub attachments: Vec<AttachmentHeader>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisputeState {
+    /// Waiting for the claims of the traders.
+    Open,
+    /// The case was handed to the [`DisputeResolver`].
+    Resolving,
+    Resolved(Verdict),
+    /// The verdict couldn't be carried out, a new claim or the administrator reopens it.
+    Failed(String),
+}
+
+/// The dispute of an active trade as tracked by the coordinator.
+#[derive(Debug, Clone)]
+pub struct Dispute {
+    pub opened_by: Party,
+    pub opened_at: Timestamp,
+    pub buyer_claim: Option<DisputeClaim>,
+    pub seller_claim: Option<DisputeClaim>,
+    pub state: DisputeState,
+}
+
+impl Dispute {
+    pub fn new(opened_by: Party) -> Self {
+        Self {
+            opened_by,
+            opened_at: Timestamp::now(),
+            buyer_claim: None,
+            seller_claim: None,
+            state: DisputeState::Open,
+        }
+    }
+
+    pub fn claim(&self, party: Party) -> Option<&DisputeClaim> {
+        match party {
+            Party::Buyer => self.buyer_claim.as_ref(),
+            Party::Seller => self.seller_claim.as_ref(),
+        }
+    }
+
+    pub fn set_claim(&mut self, party: Party, claim: DisputeClaim) {
+        match party {
+            Party::Buyer => self.buyer_claim = Some(claim),
+            Party::Seller => self.seller_claim = Some(claim),
+        }
+    }
+
+    /// The escrow token submitted with the claims, both claims carry the same proofs.
+    pub fn escrow_token(&self) -> Option<&Token> {
+        self.buyer_claim
+            .iter()
+            .chain(self.seller_claim.iter())
+            .find_map(|claim| claim.escrow_token.as_ref())
+    }
+
+    /// The private terms revealed with one of the claims, if the contract is in privacy mode.
+    pub fn terms_opening(&self) -> Option<&TermsOpening> {
+        self.buyer_claim
+            .iter()
+            .chain(self.seller_claim.iter())
+            .find_map(|claim| claim.terms_opening.as_ref())
+    }
+}
+
+/// Everything a [`DisputeResolver`] gets to know about a disputed trade.
+#[derive(Debug, Clone)]
+pub struct DisputeCase {
+    pub escrow_id_hex: String,
+    /// In privacy mode the contract with the revealed private terms, if a trader revealed them.
+    pub contract: TradeContract,
+    /// Proof that both traders agreed on the contract, if they submitted it signed.
+    pub contract_signatures: Option<ContractSignatures>,
+    pub timeline: Vec<TimelineEntry>,
+    /// The evidence of the claims followed by the evidence submitted on its own.
+    pub evidence: Vec<Evidence>,
+    /// The content of the attachments which were received completely, by their hash.
+    pub attachments: HashMap<String, Vec<u8>>,
+    pub buyer_claim: Option<DisputeClaim>,
+    pub seller_claim: Option<DisputeClaim>,
+    /// Amounts of the escrow proofs, a split has to be payable with a subset of them.
+    pub escrow_amounts: Vec<u64>,
+}
+
+impl DisputeCase {
+    /// Whether the coordinator can carry out the verdict with the escrow proofs.
+    pub fn is_payable(&self, verdict: &Verdict) -> bool {
+        match verdict {
+            Verdict::Split { buyer_amount } => {
+                select_split(&self.escrow_amounts, *buyer_amount).is_some()
+            }
+            _ => !self.escrow_amounts.is_empty(),
+        }
+    }
+
+    pub(super) fn new(
+        contract_hash: &[u8; 32],
+        trade: &ActiveTrade,
+        dispute: &Dispute,
+        storage: &dyn CoordinatorStorage,
+    ) -> Result<Self> {
+        let mut evidence: Vec<Evidence> = [
+            (Party::Buyer, &dispute.buyer_claim),
+            (Party::Seller, &dispute.seller_claim),
+        ]
+        .into_iter()
+        .filter_map(|(party, claim)| claim.as_ref().map(|claim| (party, claim)))
+        .flat_map(|(party, claim)| {
+            claim.evidence.iter().map(move |content| Evidence {
+                submitted_by: party,
+                content: content.clone(),
+                attachments: vec![],
+            })
+        })
+        .collect();
+        evidence.extend(storage.evidence(contract_hash)?);
+        let mut attachments = HashMap::new();
+        for header in evidence.iter().flat_map(|evidence| &evidence.attachments) {
+            match load_attachment(storage, contract_hash, header) {
+                Ok(Some(content)) => {
+                    attachments.insert(header.sha256.clone(), content);
+                }
+                Ok(None) => debug!("Attachment {} is incomplete", header.name),
+                Err(e) => warn!("Skipping attachment {}: {}", header.name, e),
+            }
+        }
+
+        // the openings were verified when the claims were received
+        let contract = dispute
+            .terms_opening()
+            .and_then(|opening| trade.trade_contract.reveal(opening).ok())
+            .unwrap_or_else(|| trade.trade_contract.clone());
+
+        Ok(Self {
+            escrow_id_hex: hex::encode(contract_hash),
+            contract,
+            contract_signatures: trade.contract_signatures.clone(),
+            timeline: trade.timeline.clone(),
+            evidence,
+            attachments,
+            buyer_claim: dispute.buyer_claim.clone(),
+            seller_claim: dispute.seller_claim.clone(),
+            escrow_amounts: dispute
+                .escrow_token()
+                .map(escrow_amounts)
+                .unwrap_or_default(),
+        })
+    }
+}
+
+pub(super) fn escrow_amounts(escrow_token: &Token) -> Vec<u64> {
+    escrow_token
+        .proofs()
+        .values()
+        .flatten()
+        .map(|proof| u64::from(proof.amount))
+        .collect()
+}
+
+/// Indices of the amounts which add up to the buyer's share of a split, `None` if the
+/// split isn't payable.
+///
+/// Takes the largest amounts which still fit, that always succeeds for the denominations
+/// of escrow tokens created by the client.
+pub(super) fn select_split(amounts: &[u64], buyer_amount: u64) -> Option<Vec<usize>> {
+    let mut indices: Vec<usize> = (0..amounts.len()).collect();
+    indices.sort_by_key(|index| std::cmp::Reverse(amounts[*index]));
+    let mut remaining = buyer_amount;
+    let selected: Vec<usize> = indices
+        .into_iter()
+        .filter(|index| {
+            let fits = amounts[*index] <= remaining;
+            if fits {
+                remaining -= amounts[*index];
+            }
+            fits
+        })
+        .collect();
+    (remaining == 0).then_some(selected)
+}
+
+/// The verified content of an attachment, `None` until all of its chunks were received.
+pub(super) fn load_attachment(
+    storage: &dyn CoordinatorStorage,
+    contract_hash: &[u8; 32],
+    header: &AttachmentHeader,
+) -> Result<Option<Vec<u8>>> {
+    let Some(content) = storage.attachment(contract_hash, header)? else {
+        return Ok(None);
+    };
+    header.verify(&content)?;
+    Ok(Some(content))
+}
+
+/// Decides disputes on behalf of the coordinator.
+///
+/// The coordinator signs whatever [`Verdict`] is returned, so implementations can be a manual
+/// review, an automatic rule set or a call to an external arbitration service.
+#[async_trait]
+pub trait DisputeResolver: Send + Sync {
+    async fn resolve(&self, case: DisputeCase) -> Result<Verdict>;
+}
+
+struct QueuedDispute {
+    case: DisputeCase,
+    decision_sender: oneshot::Sender<Verdict>,
+}
+
+/// Puts every dispute into a queue which has to be worked off by an administrator.
+///
+/// Clones share the same queue, so keep one in the host application to decide the cases.
+#[derive(Clone, Default)]
+pub struct ManualDisputeResolver {
+    queue: Arc<Mutex<HashMap<String, QueuedDispute>>>,
+}
+
+impl ManualDisputeResolver {
+    /// The cases waiting for a decision.
+    pub fn pending_disputes(&self) -> Result<Vec<DisputeCase>> {
+        Ok(self
+            .queue
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .values()
+            .map(|queued| queued.case.clone())
+            .collect())
+    }
+
+    pub fn decide(&self, escrow_id_hex: &str, verdict: Verdict) -> Result<()> {
+        let queued = self
+            .queue
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .remove(escrow_id_hex)
+            .ok_or(EscrowError::Validation(format!(
+                "No pending dispute for {}",
+                escrow_id_hex
+            )))?;
+        queued.decision_sender.send(verdict).map_err(|_| {
+            EscrowError::Protocol("Coordinator stopped waiting for the decision".to_string())
+        })
+    }
+}
+
+#[async_trait]
+impl DisputeResolver for ManualDisputeResolver {
+    async fn resolve(&self, case: DisputeCase) -> Result<Verdict> {
+        let (decision_sender, decision_receiver) = oneshot::channel();
+        info!("Dispute {} queued for manual review", &case.escrow_id_hex);
+        self.queue
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .insert(
+                case.escrow_id_hex.clone(),
+                QueuedDispute {
+                    case,
+                    decision_sender,
+                },
+            );
+        decision_receiver
+            .await
+            .map_err(|_| EscrowError::Protocol("Dispute was dropped from the queue".to_string()))
+    }
+}
+
+pub type DisputeRule = Box<dyn Fn(&DisputeCase) -> Option<Verdict> + Send + Sync>;
+
+/// Applies the rules in the order they were added, the first rule returning a verdict wins.
+///
+/// If no rule matches, the fallback verdict is returned.
+pub struct RulesBasedDisputeResolver {
+    rules: Vec<DisputeRule>,
+    fallback: Verdict,
+}
+
+impl RulesBasedDisputeResolver {
+    pub fn new(fallback: Verdict) -> Self {
+        Self {
+            rules: Vec::new(),
+            fallback,
+        }
+    }
+
+    /// Resolver using [`rules::parties_agree`] and [`rules::unanswered_claim`].
+    pub fn with_default_rules(fallback: Verdict) -> Self {
+        Self::new(fallback)
+            .with_rule(rules::parties_agree)
+            .with_rule(rules::unanswered_claim)
+    }
+
+    pub fn with_rule(
+        mut self,
+        rule: impl Fn(&DisputeCase) -> Option<Verdict> + Send + Sync + 'static,
+    ) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Rules deciding on a split which isn't payable with the escrow proofs are skipped.
+    pub fn decide(&self, case: &DisputeCase) -> Verdict {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule(case))
+            .find(|verdict| case.escrow_amounts.is_empty() || case.is_payable(verdict))
+            .unwrap_or_else(|| self.fallback.clone())
+    }
+}
+
+#[async_trait]
+impl DisputeResolver for RulesBasedDisputeResolver {
+    async fn resolve(&self, case: DisputeCase) -> Result<Verdict> {
+        Ok(self.decide(&case))
+    }
+}
+
+pub mod rules {
+    use super::*;
+
+    /// Both traders requested the same verdict.
+    pub fn parties_agree(case: &DisputeCase) -> Option<Verdict> {
+        match (&case.buyer_claim, &case.seller_claim) {
+            (Some(buyer), Some(seller)) if buyer.requested_verdict == seller.requested_verdict => {
+                Some(buyer.requested_verdict.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Only one trader submitted a claim, the other one didn't respond in time.
+    pub fn unanswered_claim(case: &DisputeCase) -> Option<Verdict> {
+        match (&case.buyer_claim, &case.seller_claim) {
+            (Some(claim), None) | (None, Some(claim)) => Some(claim.requested_verdict.clone()),
+            _ => None,
+        }
+    }
+}
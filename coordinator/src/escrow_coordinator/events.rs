@@ -0,0 +1,54 @@
+use super::*;
+use cashu_escrow_common::model::Verdict;
+use cdk::nuts::PublicKey as CDKPubkey;
+use dispute::Party;
+
+/// Trade lifecycle events reported to the hooks registered at the [`EscrowCoordinatorBuilder`].
+#[derive(Debug, Clone)]
+pub enum TradeEvent {
+    /// A trader submitted a trade contract.
+    ContractReceived {
+        contract_hash: [u8; 32],
+        sender: PublicKey,
+    },
+    /// A submitted contract was not accepted, e.g. by the [`CoordinatorPolicy`], the submitter
+    /// was told the reason.
+    ContractRejected {
+        contract_hash: [u8; 32],
+        reason: String,
+    },
+    /// Both traders submitted the same contract, or one of them the contract signed by both,
+    /// and the escrow registration was sent to them.
+    TradeStarted {
+        contract_hash: [u8; 32],
+        contract: Box<TradeContract>,
+        coordinator_escrow_pubkey: CDKPubkey,
+    },
+    /// A trader opened a dispute for an active trade.
+    DisputeOpened {
+        contract_hash: [u8; 32],
+        opened_by: Party,
+    },
+    /// A trader submitted or updated the claim of an open dispute.
+    ClaimSubmitted {
+        contract_hash: [u8; 32],
+        submitted_by: Party,
+    },
+    /// A trader submitted evidence for an open dispute, the attachments follow in chunks.
+    EvidenceSubmitted {
+        contract_hash: [u8; 32],
+        submitted_by: Party,
+    },
+    /// The verdict of the [`dispute::DisputeResolver`] was signed and sent to the traders.
+    VerdictSigned {
+        contract_hash: [u8; 32],
+        verdict: Verdict,
+    },
+    /// The dispute couldn't be resolved, the traders were notified with the reason.
+    DisputeFailed {
+        contract_hash: [u8; 32],
+        reason: String,
+    },
+}
+
+pub type TradeEventHook = Arc<dyn Fn(&TradeEvent) + Send + Sync>;
@@ -0,0 +1,38 @@
+use super::*;
+use cdk::nuts::SecretKey as CDKSecretKey;
+
+/// Provides the coordinator secret a trade's escrow token gets locked to.
+pub trait EscrowKeySource: Send + Sync {
+    fn escrow_secret(&self, contract_hash: &[u8; 32]) -> Result<CDKSecretKey>;
+}
+
+/// Generates a new random key for every trade, the keys only live in the coordinator storage.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomKeySource;
+
+impl EscrowKeySource for RandomKeySource {
+    fn escrow_secret(&self, _contract_hash: &[u8; 32]) -> Result<CDKSecretKey> {
+        Ok(CDKSecretKey::generate())
+    }
+}
+
+/// Derives the trade keys from a master secret, so they can be recomputed after a restart.
+pub struct DerivedKeySource {
+    master_secret: [u8; 32],
+}
+
+impl DerivedKeySource {
+    pub fn new(master_secret: [u8; 32]) -> Self {
+        Self { master_secret }
+    }
+}
+
+impl EscrowKeySource for DerivedKeySource {
+    fn escrow_secret(&self, contract_hash: &[u8; 32]) -> Result<CDKSecretKey> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.master_secret);
+        hasher.update(contract_hash);
+        let secret_bytes: [u8; 32] = hasher.finalize().into();
+        Ok(CDKSecretKey::from_slice(&secret_bytes)?)
+    }
+}
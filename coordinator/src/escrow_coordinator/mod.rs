@@ -1,37 +1,113 @@
+pub mod admin;
+pub mod builder;
+pub mod dispute;
+pub mod events;
+pub mod key_source;
+pub mod policy;
+pub mod storage;
+
 use super::*;
-use cashu_escrow_common::model::TradeContract;
-use cdk::nuts::SecretKey as CDKSecretKey;
+use admin::CoordinatorAdmin;
+use builder::EscrowCoordinatorBuilder;
+use cashu_escrow_common::error::{EscrowError, Result};
+use cashu_escrow_common::evidence::{EvidenceChunk, EvidenceSubmission};
+use cashu_escrow_common::model::{
+    ContractRejection, ContractSignatures, DisputeClaim, DisputeFailure, DisputeVerdict, FeePayer,
+    SignedTradeContract, TradeContract, Verdict,
+};
+use cashu_escrow_common::nostr::chat::chat_escrow_id;
+use cdk::amount::Amount;
+use cdk::nuts::{Id, Proofs, SecretKey as CDKSecretKey, SpendingConditions, Token};
+use dispute::{
+    Dispute, DisputeCase, DisputeResolver, DisputeState, Evidence, ManualDisputeResolver, Party,
+};
+use events::{TradeEvent, TradeEventHook};
 use hashes::hex::DisplayHex;
+use key_source::EscrowKeySource;
 use ndk::prelude::*;
 use ndk::{Filter, Kind, RelayPoolNotification};
 use nostr_sdk as ndk;
+use policy::CoordinatorPolicy;
 use sha2::{Digest, Sha256};
-use std::collections::{hash_map::Entry, HashMap, HashSet};
-use tokio::sync::broadcast::error::RecvError;
+use std::collections::HashSet;
+use std::sync::Arc;
+use storage::{ActiveTrade, CoordinatorStorage, PendingContract, TimelineEvent};
+use tokio::sync::{broadcast::error::RecvError, mpsc, watch};
+
+/// Interval in which open disputes are checked for an expired response window.
+const DISPUTE_CHECK_INTERVAL_SECS: u64 = 60;
+
+type VerdictResult = ([u8; 32], Result<Verdict>);
 
 pub struct EscrowCoordinator {
     nostr_client: NostrClient,
-    pending_contracts: HashMap<[u8; 32], TradeContract>, // k: hash of contract json
-    active_contracts: HashMap<[u8; 32], ActiveTade>,
+    storage: Arc<dyn CoordinatorStorage>,
+    policy: CoordinatorPolicy,
+    key_source: Box<dyn EscrowKeySource>,
+    dispute_resolver: Arc<dyn DisputeResolver>,
+    /// The queue of the default resolver, `None` if the host set its own resolver.
+    manual_resolver: Option<ManualDisputeResolver>,
+    verdict_sender: mpsc::UnboundedSender<VerdictResult>,
+    verdict_receiver: mpsc::UnboundedReceiver<VerdictResult>,
+    event_hooks: Vec<TradeEventHook>,
+    shutdown_sender: Arc<watch::Sender<bool>>,
+    shutdown_receiver: watch::Receiver<bool>,
     received_events: HashSet<EventId>,
 }
 
-struct ActiveTade {
-    _trade_contract: TradeContract,
-    _coordinator_secret: CDKSecretKey,
+/// Handle to stop a running [`EscrowCoordinator`] from the host application.
+#[derive(Clone)]
+pub struct CoordinatorHandle {
+    shutdown_sender: Arc<watch::Sender<bool>>,
+}
+
+impl CoordinatorHandle {
+    /// Signals the coordinator to stop, [`EscrowCoordinator::run`] returns after this.
+    pub fn shutdown(&self) {
+        self.shutdown_sender.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.shutdown_sender.borrow()
+    }
 }
 
 impl EscrowCoordinator {
-    pub fn new(nostr_client: NostrClient) -> anyhow::Result<Self> {
-        Ok(Self {
-            nostr_client,
-            pending_contracts: HashMap::new(),
-            active_contracts: HashMap::new(),
-            received_events: HashSet::new(),
-        })
+    pub fn new(nostr_client: NostrClient) -> Result<Self> {
+        Self::builder().transport(nostr_client).build()
+    }
+
+    pub fn builder() -> EscrowCoordinatorBuilder {
+        EscrowCoordinatorBuilder::default()
+    }
+
+    /// Returns a handle which can be used to shut down the coordinator while it is running.
+    pub fn handle(&self) -> CoordinatorHandle {
+        CoordinatorHandle {
+            shutdown_sender: self.shutdown_sender.clone(),
+        }
+    }
+
+    /// Shared access to the trade storage, e.g. to inspect the active trades.
+    pub fn storage(&self) -> Arc<dyn CoordinatorStorage> {
+        self.storage.clone()
+    }
+
+    /// Access for the operator, e.g. to review the evidence of disputes.
+    pub fn admin(&self) -> CoordinatorAdmin {
+        CoordinatorAdmin::new(
+            self.storage.clone(),
+            &self.nostr_client,
+            self.manual_resolver.clone(),
+        )
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.nostr_client.public_key()
     }
 
-    pub async fn run(&mut self) -> anyhow::Result<()> {
+    /// Processes incoming trade contracts and disputes until [`CoordinatorHandle::shutdown`] is called.
+    pub async fn run(&mut self) -> Result<()> {
         let my_pubkey = self.nostr_client.public_key();
         let filter_note = Filter::new()
             .kind(Kind::GiftWrap)
@@ -43,9 +119,33 @@ impl EscrowCoordinator {
             .subscribe(vec![filter_note], None)
             .await?;
         let mut notifications = self.nostr_client.client.notifications();
+        let mut shutdown_receiver = self.shutdown_receiver.clone();
+        let mut dispute_check =
+            tokio::time::interval(std::time::Duration::from_secs(DISPUTE_CHECK_INTERVAL_SECS));
 
         loop {
-            match notifications.recv().await {
+            if *shutdown_receiver.borrow_and_update() {
+                info!("Coordinator shut down");
+                return Ok(());
+            }
+            let received = tokio::select! {
+                received = notifications.recv() => received,
+                Some((contract_hash, verdict)) = self.verdict_receiver.recv() => {
+                    let _ = self
+                        .handle_verdict(&contract_hash, verdict)
+                        .await
+                        .inspect_err(|e| error!("Got error while signing a verdict: {}", e));
+                    continue;
+                }
+                _ = dispute_check.tick() => {
+                    let _ = self
+                        .resolve_due_disputes()
+                        .inspect_err(|e| error!("Got error while checking disputes: {}", e));
+                    continue;
+                }
+                _ = shutdown_receiver.changed() => continue,
+            };
+            match received {
                 Ok(notification) => {
                     if let RelayPoolNotification::Event { event, .. } = notification {
                         // check if we already processed this event previously
@@ -58,25 +158,15 @@ impl EscrowCoordinator {
                             self.nostr_client.client.unwrap_gift_wrap(&event).await
                         {
                             let rumor = unwrapped_gift.rumor;
-                            if rumor.kind == Kind::PrivateDirectMessage {
-                                if let Ok((contract_hash, contract)) =
-                                    EscrowCoordinator::parse_contract(&rumor.content)
-                                {
-                                    debug!("Received contract: {}", &contract.trade_description);
-                                    if let Entry::Vacant(e) =
-                                        self.pending_contracts.entry(contract_hash)
-                                    {
-                                        e.insert(contract);
-                                    } else {
-                                        self.pending_contracts.remove(&contract_hash);
-                                        let _ = self
-                                            .begin_trade(&contract_hash, &contract)
-                                            .await
-                                            .inspect_err(|e| {
-                                                error!("Got error while beginning a trade: {}", e);
-                                            });
-                                    }
-                                }
+                            if rumor.kind == Kind::PrivateDirectMessage
+                                && chat_escrow_id(&rumor).is_none()
+                            {
+                                let _ = self
+                                    .handle_message(&rumor.content, rumor.pubkey)
+                                    .await
+                                    .inspect_err(|e| {
+                                        error!("Got error while handling a message: {}", e);
+                                    });
                             }
                         }
                     } else if RelayPoolNotification::Shutdown == notification {
@@ -95,35 +185,487 @@ impl EscrowCoordinator {
         }
     }
 
+    async fn handle_message(&mut self, content: &str, sender: PublicKey) -> Result<()> {
+        if let Ok(claim) = serde_json::from_str::<DisputeClaim>(content) {
+            self.handle_dispute_claim(claim, sender)
+        } else if let Ok(submission) = serde_json::from_str::<EvidenceSubmission>(content) {
+            self.handle_evidence(submission, sender)
+        } else if let Ok(chunk) = serde_json::from_str::<EvidenceChunk>(content) {
+            self.handle_evidence_chunk(chunk, sender)
+        } else if let Ok(signed_contract) = serde_json::from_str::<SignedTradeContract>(content) {
+            self.handle_signed_contract(signed_contract, sender).await
+        } else if let Ok((contract_hash, contract)) = self.parse_contract(content) {
+            self.handle_contract(contract_hash, contract, sender).await
+        } else {
+            trace!("Ignoring unknown message from {}", sender);
+            Ok(())
+        }
+    }
+
+    async fn handle_contract(
+        &mut self,
+        contract_hash: [u8; 32],
+        contract: TradeContract,
+        sender: PublicKey,
+    ) -> Result<()> {
+        debug!("Received contract: {}", &contract.trade_description);
+        self.emit(&TradeEvent::ContractReceived {
+            contract_hash,
+            sender,
+        });
+
+        let checked = submitting_party(&contract, &sender).and_then(|party| {
+            self.policy
+                .check_contract(&contract, &self.public_key())
+                .map(|_| party)
+        });
+        let party = match checked {
+            Ok(party) => party,
+            Err(e) => return self.reject_contract(contract_hash, sender, e).await,
+        };
+        // a started trade must keep its coordinator key and dispute
+        if self.storage.get_active_trade(&contract_hash)?.is_some() {
+            let e = EscrowError::Protocol("The trade was already started".to_string());
+            return self.reject_contract(contract_hash, sender, e).await;
+        }
+
+        match self.storage.take_pending_contract(&contract_hash)? {
+            Some(pending) if pending.submitted_by != party => {
+                self.begin_trade(&contract_hash, &contract, None).await
+            }
+            pending => {
+                if pending.is_some() {
+                    debug!("The {:?} submitted the contract again", party);
+                }
+                self.storage.add_pending_contract(
+                    contract_hash,
+                    PendingContract {
+                        contract,
+                        submitted_by: party,
+                    },
+                )
+            }
+        }
+    }
+
+    /// Tells the submitter why its contract wasn't taken.
+    async fn reject_contract(
+        &self,
+        contract_hash: [u8; 32],
+        submitter: PublicKey,
+        error: EscrowError,
+    ) -> Result<()> {
+        warn!("Rejected contract: {}", error);
+        self.emit(&TradeEvent::ContractRejected {
+            contract_hash,
+            reason: error.to_string(),
+        });
+        let rejection = ContractRejection {
+            contract_hash: contract_hash.to_hex_string(hashes::hex::Case::Lower),
+            reason: error.to_string(),
+        };
+        self.nostr_client
+            .client
+            .send_private_msg(submitter, serde_json::to_string(&rejection)?, None)
+            .await?;
+        Ok(())
+    }
+
+    /// Starts the trade of a contract signed by both traders, one submission is enough.
+    async fn handle_signed_contract(
+        &mut self,
+        signed_contract: SignedTradeContract,
+        sender: PublicKey,
+    ) -> Result<()> {
+        let contract_hash = signed_contract.contract_hash()?;
+        let contract = &signed_contract.contract;
+        debug!("Received signed contract: {}", &contract.trade_description);
+        self.emit(&TradeEvent::ContractReceived {
+            contract_hash,
+            sender,
+        });
+
+        let checked = submitting_party(contract, &sender)
+            .and_then(|_| signed_contract.verify())
+            .and_then(|_| self.policy.check_contract(contract, &self.public_key()));
+        if let Err(e) = checked {
+            return self.reject_contract(contract_hash, sender, e).await;
+        }
+
+        if self.storage.get_active_trade(&contract_hash)?.is_some() {
+            debug!("Trade was already started by the other trader");
+            return Ok(());
+        }
+        // an unsigned submission of the same contract is superseded
+        self.storage.take_pending_contract(&contract_hash)?;
+        self.begin_trade(
+            &contract_hash,
+            contract,
+            Some(signed_contract.signatures.clone()),
+        )
+        .await
+    }
+
     async fn begin_trade(
         &mut self,
         contract_hash: &[u8; 32],
         trade: &TradeContract,
-    ) -> anyhow::Result<()> {
+        contract_signatures: Option<ContractSignatures>,
+    ) -> Result<()> {
         debug!(
             "Beginning trade: {}",
             contract_hash.to_hex_string(hashes::hex::Case::Lower)
         );
-        let contract_secret = CDKSecretKey::generate();
-        self.active_contracts.insert(
+        let contract_secret = self.key_source.escrow_secret(contract_hash)?;
+        let escrow_start_time = Timestamp::now();
+        self.storage.add_active_trade(
             *contract_hash,
-            ActiveTade {
-                _trade_contract: trade.clone(),
-                _coordinator_secret: contract_secret.clone(),
+            ActiveTrade {
+                trade_contract: trade.clone(),
+                contract_signatures,
+                coordinator_secret: contract_secret.clone(),
+                escrow_start_time,
+                timeline: vec![],
+                dispute: None,
             },
-        );
+        )?;
         self.nostr_client
             .send_escrow_registration(
                 (trade.npubkey_buyer, trade.npubkey_seller),
                 contract_hash,
                 &contract_secret.public_key().to_hex(),
+                escrow_start_time,
             )
             .await?;
+        if let Some(mut active_trade) = self.storage.get_active_trade(contract_hash)? {
+            active_trade.record(TimelineEvent::EscrowRegistered);
+            self.storage
+                .update_active_trade(contract_hash, active_trade)?;
+        }
+        self.emit(&TradeEvent::TradeStarted {
+            contract_hash: *contract_hash,
+            contract: Box::new(trade.clone()),
+            coordinator_escrow_pubkey: contract_secret.public_key(),
+        });
         Ok(())
     }
 
-    fn parse_contract(content: &str) -> anyhow::Result<([u8; 32], TradeContract)> {
-        let contract: TradeContract = serde_json::from_str(content)?;
+    fn handle_dispute_claim(&mut self, claim: DisputeClaim, sender: PublicKey) -> Result<()> {
+        let contract_hash = parse_escrow_id(&claim.escrow_id_hex)?;
+        let mut trade =
+            self.storage
+                .get_active_trade(&contract_hash)?
+                .ok_or(EscrowError::Protocol(format!(
+                    "Dispute for unknown trade {}",
+                    claim.escrow_id_hex
+                )))?;
+        let party = trade
+            .party_of(&sender)
+            .ok_or(EscrowError::Protocol(format!(
+                "Dispute claim from a stranger: {}",
+                sender
+            )))?;
+        if let Some(escrow_token) = &claim.escrow_token {
+            check_escrow_token(escrow_token, &trade)?;
+            // the proofs of both claims are co-signed together, they have to be the same escrow
+            let other_token = trade
+                .dispute
+                .as_ref()
+                .and_then(|dispute| dispute.claim(party.other()))
+                .and_then(|claim| claim.escrow_token.as_ref());
+            if other_token.is_some_and(|other| proof_ids(other) != proof_ids(escrow_token)) {
+                return Err(EscrowError::Validation(format!(
+                    "The escrow token differs from the one of the {:?}",
+                    party.other()
+                )));
+            }
+        }
+        if let Some(terms_opening) = &claim.terms_opening {
+            trade.trade_contract.reveal(terms_opening)?;
+        }
+        if let Verdict::Split { buyer_amount } = claim.requested_verdict {
+            let escrow_token = claim
+                .escrow_token
+                .as_ref()
+                .or(trade.dispute.as_ref().and_then(Dispute::escrow_token));
+            if let Some(escrow_token) = escrow_token {
+                let amounts = dispute::escrow_amounts(escrow_token);
+                if dispute::select_split(&amounts, buyer_amount).is_none() {
+                    return Err(unpayable_split(buyer_amount, escrow_token));
+                }
+            }
+        }
+        debug!(
+            "Received dispute claim for {} from {:?}",
+            claim.escrow_id_hex, party
+        );
+
+        let dispute = match &mut trade.dispute {
+            Some(dispute)
+                if !matches!(dispute.state, DisputeState::Open | DisputeState::Failed(_)) =>
+            {
+                return Err(EscrowError::Protocol(format!(
+                    "Dispute {} is already being resolved",
+                    claim.escrow_id_hex
+                )));
+            }
+            Some(dispute) => {
+                // a new claim gives a failed dispute another try
+                dispute.state = DisputeState::Open;
+                dispute
+            }
+            None => {
+                trade.record(TimelineEvent::DisputeOpened { by: party });
+                self.emit(&TradeEvent::DisputeOpened {
+                    contract_hash,
+                    opened_by: party,
+                });
+                trade.dispute.insert(Dispute::new(party))
+            }
+        };
+        dispute.set_claim(party, claim);
+        trade.record(TimelineEvent::ClaimSubmitted { by: party });
+        self.storage.update_active_trade(&contract_hash, trade)?;
+        self.emit(&TradeEvent::ClaimSubmitted {
+            contract_hash,
+            submitted_by: party,
+        });
+
+        self.resolve_due_disputes()
+    }
+
+    fn handle_evidence(&mut self, submission: EvidenceSubmission, sender: PublicKey) -> Result<()> {
+        let (contract_hash, mut trade, party) =
+            self.evidence_trade(&submission.escrow_id_hex, &sender)?;
+        let submitted: Vec<Evidence> = self
+            .storage
+            .evidence(&contract_hash)?
+            .into_iter()
+            .filter(|evidence| evidence.submitted_by == party)
+            .collect();
+        self.policy.check_evidence(&submitted, &submission)?;
+        debug!(
+            "Received evidence for {} from {:?} with {} attachments",
+            submission.escrow_id_hex,
+            party,
+            submission.attachments.len()
+        );
+        self.storage.add_evidence(
+            &contract_hash,
+            Evidence {
+                submitted_by: party,
+                content: submission.message,
+                attachments: submission.attachments,
+            },
+        )?;
+        trade.record(TimelineEvent::EvidenceSubmitted { by: party });
+        self.storage.update_active_trade(&contract_hash, trade)?;
+        self.emit(&TradeEvent::EvidenceSubmitted {
+            contract_hash,
+            submitted_by: party,
+        });
+        Ok(())
+    }
+
+    fn handle_evidence_chunk(&mut self, chunk: EvidenceChunk, sender: PublicKey) -> Result<()> {
+        let (contract_hash, _, party) = self.evidence_trade(&chunk.escrow_id_hex, &sender)?;
+        let header = self
+            .storage
+            .evidence(&contract_hash)?
+            .into_iter()
+            .filter(|evidence| evidence.submitted_by == party)
+            .flat_map(|evidence| evidence.attachments)
+            .find(|header| header.sha256 == chunk.attachment_sha256)
+            .ok_or(EscrowError::Protocol(format!(
+                "Chunk of an unannounced attachment {}",
+                chunk.attachment_sha256
+            )))?;
+        let data = chunk.decode_data()?;
+        header.check_chunk(chunk.index, &data)?;
+        self.storage
+            .add_attachment_chunk(&contract_hash, &header.sha256, chunk.index, data)?;
+        if dispute::load_attachment(self.storage.as_ref(), &contract_hash, &header)?.is_some() {
+            debug!(
+                "Received attachment {} for {}",
+                header.name, chunk.escrow_id_hex
+            );
+        }
+        Ok(())
+    }
+
+    /// The disputed trade the evidence is for and the party of the sender, fails if the
+    /// dispute doesn't take evidence anymore.
+    fn evidence_trade(
+        &self,
+        escrow_id_hex: &str,
+        sender: &PublicKey,
+    ) -> Result<([u8; 32], ActiveTrade, Party)> {
+        let contract_hash = parse_escrow_id(escrow_id_hex)?;
+        let trade = self
+            .storage
+            .get_active_trade(&contract_hash)?
+            .ok_or(EscrowError::Protocol(format!(
+                "Evidence for unknown trade {}",
+                escrow_id_hex
+            )))?;
+        let party = trade.party_of(sender).ok_or(EscrowError::Protocol(format!(
+            "Evidence from a stranger: {}",
+            sender
+        )))?;
+        let dispute = trade.dispute.as_ref().ok_or(EscrowError::Protocol(format!(
+            "No dispute is open for {}",
+            escrow_id_hex
+        )))?;
+        self.policy.check_evidence_deadline(dispute)?;
+        Ok((contract_hash, trade, party))
+    }
+
+    /// Hands the disputes to the [`DisputeResolver`] for which both claims are present
+    /// or the response window of the policy expired.
+    fn resolve_due_disputes(&mut self) -> Result<()> {
+        let now = Timestamp::now().as_u64();
+        for (contract_hash, mut trade) in self.storage.active_trades()? {
+            let Some(dispute) = &trade.dispute else {
+                continue;
+            };
+            let both_claimed = dispute.buyer_claim.is_some() && dispute.seller_claim.is_some();
+            let window_expired = self
+                .policy
+                .dispute_response_window
+                .is_some_and(|window| dispute.opened_at.as_u64() + window <= now);
+            if dispute.state != DisputeState::Open || !(both_claimed || window_expired) {
+                continue;
+            }
+
+            let case = DisputeCase::new(&contract_hash, &trade, dispute, self.storage.as_ref())?;
+            if let Some(dispute) = &mut trade.dispute {
+                dispute.state = DisputeState::Resolving;
+            }
+            self.storage.update_active_trade(&contract_hash, trade)?;
+
+            let resolver = self.dispute_resolver.clone();
+            let verdict_sender = self.verdict_sender.clone();
+            tokio::spawn(async move {
+                let verdict = resolver.resolve(case).await;
+                let _ = verdict_sender.send((contract_hash, verdict));
+            });
+        }
+        Ok(())
+    }
+
+    /// Signs the verdict and sends it to the traders.
+    ///
+    /// If the resolver failed or the verdict can't be carried out, the dispute is marked as
+    /// failed and the traders are notified, a new claim reopens it.
+    async fn handle_verdict(
+        &mut self,
+        contract_hash: &[u8; 32],
+        verdict: Result<Verdict>,
+    ) -> Result<()> {
+        let mut trade =
+            self.storage
+                .get_active_trade(contract_hash)?
+                .ok_or(EscrowError::Storage(
+                    "Verdict for unknown trade".to_string(),
+                ))?;
+        let signed = match verdict {
+            Ok(verdict) => self
+                .send_verdict(contract_hash, &trade, &verdict)
+                .await
+                .map(|_| verdict),
+            Err(e) => Err(e),
+        };
+        let dispute = trade.dispute.as_mut().ok_or(EscrowError::Storage(
+            "Verdict for a trade without dispute".to_string(),
+        ))?;
+        match signed {
+            Ok(verdict) => {
+                dispute.state = DisputeState::Resolved(verdict.clone());
+                trade.record(TimelineEvent::VerdictSigned {
+                    verdict: verdict.clone(),
+                });
+                self.storage.update_active_trade(contract_hash, trade)?;
+                self.emit(&TradeEvent::VerdictSigned {
+                    contract_hash: *contract_hash,
+                    verdict,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                let reason = e.to_string();
+                dispute.state = DisputeState::Failed(reason.clone());
+                trade.record(TimelineEvent::DisputeFailed {
+                    reason: reason.clone(),
+                });
+                let receivers = [
+                    trade.trade_contract.npubkey_buyer,
+                    trade.trade_contract.npubkey_seller,
+                ];
+                self.storage.update_active_trade(contract_hash, trade)?;
+                self.emit(&TradeEvent::DisputeFailed {
+                    contract_hash: *contract_hash,
+                    reason: reason.clone(),
+                });
+                let failure_message = serde_json::to_string(&DisputeFailure {
+                    escrow_id_hex: hex::encode(contract_hash),
+                    reason,
+                })?;
+                for receiver in receivers {
+                    self.nostr_client
+                        .client
+                        .send_private_msg(receiver, &failure_message, None)
+                        .await?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Co-signs the escrow proofs according to the verdict and sends them to the traders.
+    async fn send_verdict(
+        &self,
+        contract_hash: &[u8; 32],
+        trade: &ActiveTrade,
+        verdict: &Verdict,
+    ) -> Result<()> {
+        let escrow_token = trade
+            .dispute
+            .as_ref()
+            .and_then(Dispute::escrow_token)
+            .ok_or(EscrowError::Protocol(
+                "No escrow token was submitted with the claims".to_string(),
+            ))?;
+        let (buyer_token, seller_token) =
+            cosign_escrow_token(escrow_token, verdict, &trade.coordinator_secret)?;
+
+        let escrow_id_hex = hex::encode(contract_hash);
+        for (receiver, cosigned_token) in [
+            (trade.trade_contract.npubkey_buyer, buyer_token),
+            (trade.trade_contract.npubkey_seller, seller_token),
+        ] {
+            let verdict_message = serde_json::to_string(&DisputeVerdict {
+                escrow_id_hex: escrow_id_hex.clone(),
+                verdict: verdict.clone(),
+                cosigned_token,
+            })?;
+            self.nostr_client
+                .client
+                .send_private_msg(receiver, verdict_message, None)
+                .await?;
+        }
+        debug!("Signed verdict {:?} for {}", verdict, escrow_id_hex);
+        Ok(())
+    }
+
+    /// Parses a submitted contract, its hash is taken over the JSON as sent by the traders.
+    ///
+    /// Contracts from before the mint was agreed don't name it. A coordinator supporting a
+    /// single mint assumes that one, otherwise contracts without `mint_url` are ignored.
+    fn parse_contract(&self, content: &str) -> Result<([u8; 32], TradeContract)> {
+        let contract = match self.policy.supported_mints.as_deref() {
+            Some([mint_url]) => TradeContract::from_json(content, mint_url)?,
+            _ => serde_json::from_str(content)?,
+        };
 
         // create a Sha256 object
         let mut hasher = Sha256::new();
@@ -134,7 +676,13 @@ impl EscrowCoordinator {
         Ok((trade_hash, contract))
     }
 
-    async fn reconnect_nostr_client(&self) -> anyhow::Result<()> {
+    fn emit(&self, event: &TradeEvent) {
+        for hook in &self.event_hooks {
+            hook(event);
+        }
+    }
+
+    async fn reconnect_nostr_client(&self) -> Result<()> {
         self.nostr_client.client.disconnect().await?;
         warn!("Reconnecting nostr client in 60 seconds...");
         tokio::time::sleep(std::time::Duration::from_secs(60)).await;
@@ -143,3 +691,154 @@ impl EscrowCoordinator {
         Ok(())
     }
 }
+
+/// The party of the trader who submitted the contract, buyer and seller have to be different
+/// traders.
+fn submitting_party(contract: &TradeContract, sender: &PublicKey) -> Result<Party> {
+    if contract.npubkey_buyer == contract.npubkey_seller {
+        return Err(EscrowError::Validation(
+            "Buyer and seller are the same trader".to_string(),
+        ));
+    }
+    if *sender == contract.npubkey_buyer {
+        Ok(Party::Buyer)
+    } else if *sender == contract.npubkey_seller {
+        Ok(Party::Seller)
+    } else {
+        Err(EscrowError::Validation(format!(
+            "Contract submitted by a stranger: {}",
+            sender
+        )))
+    }
+}
+
+fn parse_escrow_id(escrow_id_hex: &str) -> Result<[u8; 32]> {
+    hex::decode(escrow_id_hex)?
+        .try_into()
+        .map_err(|_| EscrowError::Validation(format!("Invalid escrow id: {}", escrow_id_hex)))
+}
+
+/// Ensures the token is the escrow of the trade: all proofs are locked to the spending
+/// conditions of the contract and come from the agreed mint, unit and keysets, and the token
+/// is worth the trade amount, plus the redemption fee if the buyer pays it.
+fn check_escrow_token(escrow_token: &Token, trade: &ActiveTrade) -> Result<()> {
+    let contract = &trade.trade_contract;
+    let conditions = contract.escrow_conditions(
+        &trade.coordinator_secret.public_key(),
+        trade.escrow_start_time,
+    )?;
+    for (mint_url, proofs) in escrow_token.proofs() {
+        if mint_url != contract.mint_url {
+            return Err(EscrowError::Validation(format!(
+                "Escrow proofs from {} instead of {}",
+                mint_url, contract.mint_url
+            )));
+        }
+        for proof in proofs {
+            if SpendingConditions::try_from(&proof.secret)? != conditions {
+                return Err(EscrowError::Validation(
+                    "Escrow proofs are not locked to the conditions of the trade".to_string(),
+                ));
+            }
+            if let Some(allowed_keysets) = &contract.allowed_keysets {
+                if !allowed_keysets.contains(&proof.keyset_id) {
+                    return Err(EscrowError::Validation(format!(
+                        "Escrow proofs from keyset {} which is not allowed",
+                        proof.keyset_id
+                    )));
+                }
+            }
+        }
+    }
+    let unit = (*escrow_token.unit()).unwrap_or_default();
+    if unit != contract.currency_unit {
+        return Err(EscrowError::Validation(format!(
+            "Escrow token in {} instead of {}",
+            unit, contract.currency_unit
+        )));
+    }
+    let value = u64::from(escrow_token.value()?);
+    // the redemption fee depends on the keyset fees, which the coordinator doesn't know
+    let whole = match contract.fee_payer {
+        FeePayer::Seller => value == contract.trade_amount,
+        FeePayer::Buyer => value >= contract.trade_amount,
+    };
+    if !whole {
+        return Err(EscrowError::Validation(format!(
+            "Escrow token worth {} doesn't hold the trade amount of {}",
+            value, contract.trade_amount
+        )));
+    }
+    Ok(())
+}
+
+/// Identifies the proofs of a token independent of their signatures.
+fn proof_ids(token: &Token) -> HashSet<(Id, Amount, String)> {
+    token
+        .proofs()
+        .into_values()
+        .flatten()
+        .map(|proof| (proof.keyset_id, proof.amount, proof.secret.to_string()))
+        .collect()
+}
+
+/// Splits the escrow proofs according to the verdict and signs them with the coordinator key.
+///
+/// Returns the tokens for the buyer and the seller, `None` if a party receives nothing.
+/// A split must be payable with a subset of the escrow proofs, see [`dispute::select_split`].
+fn cosign_escrow_token(
+    escrow_token: &Token,
+    verdict: &Verdict,
+    coordinator_secret: &CDKSecretKey,
+) -> Result<(Option<Token>, Option<Token>)> {
+    let mut token_proofs = escrow_token.proofs().into_iter();
+    let (mint_url, mut proofs) = token_proofs.next().ok_or(EscrowError::Validation(
+        "Escrow token contains no proofs".to_string(),
+    ))?;
+    if token_proofs.next().is_some() {
+        return Err(EscrowError::Validation(
+            "Escrow tokens from multiple mints are not supported".to_string(),
+        ));
+    }
+    for proof in proofs.iter_mut() {
+        proof.sign_p2pk(coordinator_secret.clone())?;
+    }
+
+    let (buyer_proofs, seller_proofs): (Proofs, Proofs) = match verdict {
+        Verdict::BuyerWins => (proofs, vec![]),
+        Verdict::SellerWins => (vec![], proofs),
+        Verdict::Split { buyer_amount } => {
+            let amounts: Vec<u64> = proofs.iter().map(|proof| u64::from(proof.amount)).collect();
+            let selected = dispute::select_split(&amounts, *buyer_amount)
+                .ok_or_else(|| unpayable_split(*buyer_amount, escrow_token))?;
+            let (buyer_proofs, seller_proofs): (Vec<_>, Vec<_>) = proofs
+                .into_iter()
+                .enumerate()
+                .partition(|(index, _)| selected.contains(index));
+            (
+                buyer_proofs.into_iter().map(|(_, proof)| proof).collect(),
+                seller_proofs.into_iter().map(|(_, proof)| proof).collect(),
+            )
+        }
+    };
+
+    let to_token = |proofs: Proofs| {
+        (!proofs.is_empty()).then(|| {
+            Token::new(
+                mint_url.clone(),
+                proofs,
+                escrow_token.memo().clone(),
+                *escrow_token.unit(),
+            )
+        })
+    };
+    Ok((to_token(buyer_proofs), to_token(seller_proofs)))
+}
+
+fn unpayable_split(buyer_amount: u64, escrow_token: &Token) -> EscrowError {
+    EscrowError::Validation(format!(
+        "Split of {} {} is not payable with the escrow proofs",
+        buyer_amount,
+        escrow_token.unit().unwrap_or_default()
+    ))
+}
@@ -0,0 +1,202 @@
+use super::*;
+use cashu_escrow_common::evidence::{AttachmentHeader, EvidenceSubmission, EVIDENCE_CHUNK_SIZE};
+use cdk::{mint_url::MintUrl, nuts::CurrencyUnit};
+use dispute::{Dispute, DisputeState, Evidence};
+use std::collections::{HashMap, HashSet};
+
+pub const DEFAULT_DISPUTE_RESPONSE_WINDOW: u64 = 24 * 60 * 60;
+pub const DEFAULT_EVIDENCE_DEADLINE: u64 = 3 * 24 * 60 * 60;
+
+/// Bounds of the trade amount, denominated in the unit they are configured for.
+#[derive(Debug, Clone, Default)]
+pub struct AmountLimits {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+/// Limits of the evidence each trader can submit in a dispute.
+#[derive(Debug, Clone)]
+pub struct EvidenceLimits {
+    /// Seconds after the dispute was opened in which evidence is taken, `None` takes it
+    /// until the verdict.
+    pub deadline: Option<u64>,
+    pub max_attachments: usize,
+    /// Size of a single attachment in bytes.
+    pub max_attachment_size: u64,
+    /// Size of all messages and attachments of a trader in bytes.
+    pub max_total_size: u64,
+}
+
+impl Default for EvidenceLimits {
+    fn default() -> Self {
+        Self {
+            deadline: Some(DEFAULT_EVIDENCE_DEADLINE),
+            max_attachments: 20,
+            max_attachment_size: 5 * 1024 * 1024,
+            max_total_size: 20 * 1024 * 1024,
+        }
+    }
+}
+
+/// Limits for the trades a coordinator accepts, `None` means unrestricted.
+#[derive(Debug, Clone)]
+pub struct CoordinatorPolicy {
+    /// Amount limits per currency unit, amounts of units without an entry are unrestricted.
+    pub amount_limits: HashMap<CurrencyUnit, AmountLimits>,
+    pub max_time_limit: Option<u64>,
+    /// Mints the coordinator is willing to handle escrow tokens of.
+    pub supported_mints: Option<Vec<MintUrl>>,
+    /// Seconds the counterparty has to submit its claim after a dispute was opened.
+    /// After that the dispute is resolved with the claims present, `None` waits for both claims.
+    pub dispute_response_window: Option<u64>,
+    pub evidence_limits: EvidenceLimits,
+}
+
+impl Default for CoordinatorPolicy {
+    fn default() -> Self {
+        Self {
+            amount_limits: HashMap::new(),
+            max_time_limit: None,
+            supported_mints: None,
+            dispute_response_window: Some(DEFAULT_DISPUTE_RESPONSE_WINDOW),
+            evidence_limits: EvidenceLimits::default(),
+        }
+    }
+}
+
+impl CoordinatorPolicy {
+    pub fn check_contract(
+        &self,
+        contract: &TradeContract,
+        coordinator_pubkey: &PublicKey,
+    ) -> Result<()> {
+        if contract.npubkey_coordinator != *coordinator_pubkey {
+            return Err(EscrowError::Policy(
+                "Contract is addressed to another coordinator".to_string(),
+            ));
+        }
+        // the terms of a contract in privacy mode are only validated once revealed
+        contract.validate_terms()?;
+        if let Some(limits) = self.amount_limits.get(&contract.currency_unit) {
+            if let Some(min) = limits.min.filter(|min| contract.trade_amount < *min) {
+                return Err(EscrowError::Policy(format!(
+                    "Trade amount below the minimum of {} {}",
+                    min, contract.currency_unit
+                )));
+            }
+            if let Some(max) = limits.max.filter(|max| contract.trade_amount > *max) {
+                return Err(EscrowError::Policy(format!(
+                    "Trade amount above the maximum of {} {}",
+                    max, contract.currency_unit
+                )));
+            }
+        }
+        if let Some(max) = self.max_time_limit {
+            if contract.time_limit > max {
+                return Err(EscrowError::Policy(format!(
+                    "Time limit exceeds the maximum of {} seconds",
+                    max
+                )));
+            }
+        }
+        if let Some(supported_mints) = &self.supported_mints {
+            if !supported_mints.contains(&contract.mint_url) {
+                return Err(EscrowError::Policy(format!(
+                    "Mint {} is not supported",
+                    contract.mint_url
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the dispute still takes evidence.
+    ///
+    /// Evidence is only taken until the case is handed to the resolver, which doesn't see
+    /// anything submitted later.
+    pub fn check_evidence_deadline(&self, dispute: &Dispute) -> Result<()> {
+        match dispute.state {
+            DisputeState::Open | DisputeState::Failed(_) => {}
+            DisputeState::Resolving => {
+                return Err(EscrowError::Policy(
+                    "The dispute was already handed to the resolver".to_string(),
+                ))
+            }
+            DisputeState::Resolved(_) => {
+                return Err(EscrowError::Policy(
+                    "The dispute was already decided".to_string(),
+                ))
+            }
+        }
+        if let Some(deadline) = self.evidence_limits.deadline {
+            if dispute.opened_at.as_u64() + deadline < Timestamp::now().as_u64() {
+                return Err(EscrowError::Policy(
+                    "The deadline for evidence expired".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a submission against the evidence the trader submitted before.
+    pub fn check_evidence(
+        &self,
+        submitted: &[Evidence],
+        submission: &EvidenceSubmission,
+    ) -> Result<()> {
+        let limits = &self.evidence_limits;
+        let attachments = submitted
+            .iter()
+            .flat_map(|evidence| evidence.attachments.iter());
+        if attachments.clone().count() + submission.attachments.len() > limits.max_attachments {
+            return Err(EscrowError::Policy(format!(
+                "No more than {} attachments",
+                limits.max_attachments
+            )));
+        }
+        let mut announced = HashSet::new();
+        for attachment in &submission.attachments {
+            if !announced.insert(&attachment.sha256) {
+                return Err(EscrowError::Validation(format!(
+                    "Attachment {} is announced twice",
+                    attachment.name
+                )));
+            }
+            if attachment.size > limits.max_attachment_size {
+                return Err(EscrowError::Policy(format!(
+                    "Attachment {} exceeds the maximum size of {} bytes",
+                    attachment.name, limits.max_attachment_size
+                )));
+            }
+            if u64::from(attachment.chunk_count)
+                != attachment.size.div_ceil(EVIDENCE_CHUNK_SIZE as u64)
+            {
+                return Err(EscrowError::Validation(format!(
+                    "Wrong chunk count of attachment {}",
+                    attachment.name
+                )));
+            }
+            if attachments.clone().any(|a| a.sha256 == attachment.sha256) {
+                return Err(EscrowError::Validation(format!(
+                    "Attachment {} was already submitted",
+                    attachment.name
+                )));
+            }
+        }
+        let size = |message: &str, attachments: &[AttachmentHeader]| {
+            message.len() as u64 + attachments.iter().map(|a| a.size).sum::<u64>()
+        };
+        let total_size = submitted
+            .iter()
+            .map(|evidence| size(&evidence.content, &evidence.attachments))
+            .sum::<u64>()
+            + size(&submission.message, &submission.attachments);
+        if total_size > limits.max_total_size {
+            return Err(EscrowError::Policy(format!(
+                "The evidence exceeds the maximum size of {} bytes",
+                limits.max_total_size
+            )));
+        }
+        Ok(())
+    }
+}
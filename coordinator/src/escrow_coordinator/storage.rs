@@ -0,0 +1,247 @@
+use super::*;
+use cashu_escrow_common::evidence::AttachmentHeader;
+use cashu_escrow_common::model::{ContractSignatures, Verdict};
+use cdk::nuts::SecretKey as CDKSecretKey;
+use dispute::{Dispute, Evidence, Party};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// A contract submitted by one trader, the trade starts once the other submits it as well.
+#[derive(Debug, Clone)]
+pub struct PendingContract {
+    pub contract: TradeContract,
+    pub submitted_by: Party,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveTrade {
+    pub trade_contract: TradeContract,
+    /// Signatures of buyer and seller, `None` if both traders submitted the unsigned contract.
+    pub contract_signatures: Option<ContractSignatures>,
+    pub coordinator_secret: CDKSecretKey,
+    pub escrow_start_time: Timestamp,
+    pub timeline: Vec<TimelineEntry>,
+    pub dispute: Option<Dispute>,
+}
+
+impl ActiveTrade {
+    pub fn record(&mut self, event: TimelineEvent) {
+        self.timeline.push(TimelineEntry {
+            timestamp: Timestamp::now(),
+            event,
+        });
+    }
+
+    /// The role of a trader in this trade, `None` if the key belongs to neither of them.
+    pub fn party_of(&self, pubkey: &PublicKey) -> Option<Party> {
+        if *pubkey == self.trade_contract.npubkey_buyer {
+            Some(Party::Buyer)
+        } else if *pubkey == self.trade_contract.npubkey_seller {
+            Some(Party::Seller)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimelineEvent {
+    EscrowRegistered,
+    DisputeOpened { by: Party },
+    ClaimSubmitted { by: Party },
+    EvidenceSubmitted { by: Party },
+    VerdictSigned { verdict: Verdict },
+    DisputeFailed { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEntry {
+    pub timestamp: Timestamp,
+    pub event: TimelineEvent,
+}
+
+/// Persistence of the coordinator's trades, keyed by the hash of the contract json.
+///
+/// The storage is shared with the host application, so implementations have to be thread safe.
+pub trait CoordinatorStorage: Send + Sync {
+    fn add_pending_contract(&self, contract_hash: [u8; 32], pending: PendingContract)
+        -> Result<()>;
+
+    /// Removes and returns the pending contract, if one was submitted before.
+    fn take_pending_contract(&self, contract_hash: &[u8; 32]) -> Result<Option<PendingContract>>;
+
+    /// Fails if the trade was already added, its coordinator key and dispute must not be replaced.
+    fn add_active_trade(&self, contract_hash: [u8; 32], trade: ActiveTrade) -> Result<()>;
+
+    /// Replaces the stored state of an already active trade.
+    fn update_active_trade(&self, contract_hash: &[u8; 32], trade: ActiveTrade) -> Result<()>;
+
+    fn get_active_trade(&self, contract_hash: &[u8; 32]) -> Result<Option<ActiveTrade>>;
+
+    fn active_trades(&self) -> Result<Vec<([u8; 32], ActiveTrade)>>;
+
+    /// Evidence is kept apart from the trade, the attachments can be large.
+    fn add_evidence(&self, contract_hash: &[u8; 32], evidence: Evidence) -> Result<()>;
+
+    /// The evidence of both traders in the order it was submitted.
+    fn evidence(&self, contract_hash: &[u8; 32]) -> Result<Vec<Evidence>>;
+
+    fn add_attachment_chunk(
+        &self,
+        contract_hash: &[u8; 32],
+        attachment_sha256: &str,
+        index: u32,
+        data: Vec<u8>,
+    ) -> Result<()>;
+
+    /// The content of the attachment assembled from its chunks, `None` until all of them
+    /// were received. The content isn't verified against the header.
+    fn attachment(
+        &self,
+        contract_hash: &[u8; 32],
+        header: &AttachmentHeader,
+    ) -> Result<Option<Vec<u8>>>;
+}
+
+type AttachmentChunks = BTreeMap<u32, Vec<u8>>;
+
+#[derive(Debug, Default)]
+pub struct MemoryCoordinatorStorage {
+    pending_contracts: Mutex<HashMap<[u8; 32], PendingContract>>,
+    active_trades: Mutex<HashMap<[u8; 32], ActiveTrade>>,
+    evidence: Mutex<HashMap<[u8; 32], Vec<Evidence>>>,
+    attachments: Mutex<HashMap<([u8; 32], String), AttachmentChunks>>,
+}
+
+impl CoordinatorStorage for MemoryCoordinatorStorage {
+    fn add_pending_contract(
+        &self,
+        contract_hash: [u8; 32],
+        pending: PendingContract,
+    ) -> Result<()> {
+        self.pending_contracts
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .insert(contract_hash, pending);
+        Ok(())
+    }
+
+    fn take_pending_contract(&self, contract_hash: &[u8; 32]) -> Result<Option<PendingContract>> {
+        Ok(self
+            .pending_contracts
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .remove(contract_hash))
+    }
+
+    fn add_active_trade(&self, contract_hash: [u8; 32], trade: ActiveTrade) -> Result<()> {
+        match self
+            .active_trades
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .entry(contract_hash)
+        {
+            Entry::Occupied(_) => Err(EscrowError::Storage(
+                "The trade was already started".to_string(),
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(trade);
+                Ok(())
+            }
+        }
+    }
+
+    fn update_active_trade(&self, contract_hash: &[u8; 32], trade: ActiveTrade) -> Result<()> {
+        match self
+            .active_trades
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .get_mut(contract_hash)
+        {
+            Some(stored) => {
+                *stored = trade;
+                Ok(())
+            }
+            None => Err(EscrowError::Storage("Trade is not active".to_string())),
+        }
+    }
+
+    fn get_active_trade(&self, contract_hash: &[u8; 32]) -> Result<Option<ActiveTrade>> {
+        Ok(self
+            .active_trades
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .get(contract_hash)
+            .cloned())
+    }
+
+    fn active_trades(&self) -> Result<Vec<([u8; 32], ActiveTrade)>> {
+        Ok(self
+            .active_trades
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .iter()
+            .map(|(hash, trade)| (*hash, trade.clone()))
+            .collect())
+    }
+
+    fn add_evidence(&self, contract_hash: &[u8; 32], evidence: Evidence) -> Result<()> {
+        self.evidence
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .entry(*contract_hash)
+            .or_default()
+            .push(evidence);
+        Ok(())
+    }
+
+    fn evidence(&self, contract_hash: &[u8; 32]) -> Result<Vec<Evidence>> {
+        Ok(self
+            .evidence
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .get(contract_hash)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn add_attachment_chunk(
+        &self,
+        contract_hash: &[u8; 32],
+        attachment_sha256: &str,
+        index: u32,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        self.attachments
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?
+            .entry((*contract_hash, attachment_sha256.to_string()))
+            .or_default()
+            .insert(index, data);
+        Ok(())
+    }
+
+    fn attachment(
+        &self,
+        contract_hash: &[u8; 32],
+        header: &AttachmentHeader,
+    ) -> Result<Option<Vec<u8>>> {
+        let attachments = self
+            .attachments
+            .lock()
+            .map_err(|e| EscrowError::Storage(e.to_string()))?;
+        let chunks = attachments.get(&(*contract_hash, header.sha256.clone()));
+        let received = chunks.map_or(0, |chunks| chunks.len());
+        if received < header.chunk_count as usize {
+            return Ok(None);
+        }
+        Ok(Some(
+            chunks
+                .into_iter()
+                .flat_map(|chunks| chunks.values())
+                .flatten()
+                .copied()
+                .collect(),
+        ))
+    }
+}
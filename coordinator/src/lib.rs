@@ -0,0 +1,46 @@
+//! Escrow coordinator service which can be run standalone or embedded into another application.
+//!
+//! Use [`EscrowCoordinatorBuilder`] to assemble a coordinator and [`CoordinatorHandle`]
+//! to stop it again from the host application.
+//!
+//! ```no_run
+//! # async fn embed(nostr_client: cashu_escrow_common::nostr::NostrClient) -> cashu_escrow_common::error::Result<()> {
+//! use cashu_escrow_coordinator::{EscrowCoordinator, TradeEvent};
+//!
+//! let mut coordinator = EscrowCoordinator::builder()
+//!     .transport(nostr_client)
+//!     .on_event(|event| {
+//!         if let TradeEvent::TradeStarted { contract, .. } = event {
+//!             println!("trade started: {}", contract.trade_description);
+//!         }
+//!     })
+//!     .build()?;
+//! let handle = coordinator.handle();
+//! tokio::spawn(async move { coordinator.run().await });
+//! // ...
+//! handle.shutdown();
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod escrow_coordinator;
+
+use cashu_escrow_common::nostr::NostrClient;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+pub use escrow_coordinator::{
+    admin::CoordinatorAdmin,
+    builder::EscrowCoordinatorBuilder,
+    dispute::{
+        DisputeCase, DisputeResolver, Evidence, ManualDisputeResolver, Party,
+        RulesBasedDisputeResolver,
+    },
+    events::{TradeEvent, TradeEventHook},
+    key_source::{DerivedKeySource, EscrowKeySource, RandomKeySource},
+    policy::{AmountLimits, CoordinatorPolicy, EvidenceLimits},
+    storage::{
+        ActiveTrade, CoordinatorStorage, MemoryCoordinatorStorage, PendingContract, TimelineEntry,
+    },
+    CoordinatorHandle, EscrowCoordinator,
+};
@@ -1,10 +1,8 @@
-mod escrow_coordinator;
-
 use std::{env, str::FromStr};
 
 use cashu_escrow_common::nostr::NostrClient;
+use cashu_escrow_coordinator::EscrowCoordinator;
 use dotenvy::dotenv;
-use escrow_coordinator::EscrowCoordinator;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 use nostr_sdk::{Keys, ToBech32};
@@ -28,5 +26,10 @@ async fn main() -> anyhow::Result<()> {
         nostr_client.public_key().to_bech32()?
     );
     info!("Starting service and waiting for trades...");
-    return EscrowCoordinator::new(nostr_client)?.run().await;
+    EscrowCoordinator::builder()
+        .transport(nostr_client)
+        .build()?
+        .run()
+        .await?;
+    Ok(())
 }
@@ -0,0 +1,79 @@
+use cashu_escrow_common::model::TradeContract;
+use cashu_escrow_common::nostr::NostrClient;
+use cashu_escrow_coordinator::{CoordinatorStorage, EscrowCoordinator, TradeEvent};
+use nostr_sdk::{Keys, PublicKey};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub(crate) async fn create_nostr_client() -> NostrClient {
+    let keys = Keys::generate();
+    let relays: Vec<String> = vec!["ws://localhost:4736".to_string()];
+    NostrClient::new(keys, relays).await.unwrap()
+}
+
+/// A coordinator on the local test relay, its events are forwarded to `events`.
+pub(crate) struct TestCoordinator {
+    pub(crate) coordinator: EscrowCoordinator,
+    pub(crate) storage: Arc<dyn CoordinatorStorage>,
+    pub(crate) events: mpsc::UnboundedReceiver<TradeEvent>,
+}
+
+impl TestCoordinator {
+    pub(crate) async fn new() -> Self {
+        let (sender, events) = mpsc::unbounded_channel();
+        let coordinator = EscrowCoordinator::builder()
+            .transport(create_nostr_client().await)
+            .on_event(move |event| {
+                let _ = sender.send(event.clone());
+            })
+            .build()
+            .unwrap();
+        let storage = coordinator.storage();
+        Self {
+            coordinator,
+            storage,
+            events,
+        }
+    }
+}
+
+/// Runs the coordinator until the steps of the traders are done.
+pub(crate) async fn run_coordinator(
+    coordinator: &mut EscrowCoordinator,
+    steps: impl Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    let handle = coordinator.handle();
+    let (run, steps) = tokio::join!(coordinator.run(), async {
+        // wait till the relay set up the subscription of the coordinator
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let result = steps.await;
+        handle.shutdown();
+        result
+    });
+    run?;
+    steps
+}
+
+/// Next event of the coordinator, fails after 10 seconds.
+pub(crate) async fn next_event(
+    events: &mut mpsc::UnboundedReceiver<TradeEvent>,
+) -> anyhow::Result<TradeEvent> {
+    tokio::time::timeout(Duration::from_secs(10), events.recv())
+        .await?
+        .ok_or(anyhow::anyhow!("The coordinator stopped"))
+}
+
+/// Sends the contract to the coordinator without signatures.
+pub(crate) async fn submit_contract(
+    trader: &NostrClient,
+    coordinator: PublicKey,
+    contract: &TradeContract,
+) -> anyhow::Result<()> {
+    trader
+        .client
+        .send_private_msg(coordinator, contract.canonical_json()?, None)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,433 @@
+mod common;
+
+use cashu_escrow_common::evidence::{Attachment, EvidenceSubmission, EVIDENCE_CHUNK_SIZE};
+use cashu_escrow_common::model::{
+    ContractRejection, DisputeClaim, EscrowRegistration, FeePayer, TradeContract, Verdict,
+};
+use cashu_escrow_common::nostr::NostrClient;
+use cashu_escrow_coordinator::escrow_coordinator::dispute::{Dispute, DisputeState};
+use cashu_escrow_coordinator::{
+    ActiveTrade, AmountLimits, CoordinatorPolicy, CoordinatorStorage, DisputeCase, Evidence,
+    EvidenceLimits, MemoryCoordinatorStorage, Party, RulesBasedDisputeResolver, TradeEvent,
+};
+use cdk::{
+    mint_url::MintUrl,
+    nuts::{CurrencyUnit, SecretKey},
+};
+use common::*;
+use nostr_sdk::{util::hex, Keys, PublicKey, Timestamp};
+use std::{collections::HashMap, str::FromStr};
+
+fn trade_contract() -> TradeContract {
+    TradeContract {
+        trade_description: "Test trade".to_string(),
+        trade_amount: 5000,
+        npubkey_seller: Keys::generate().public_key(),
+        npubkey_buyer: Keys::generate().public_key(),
+        npubkey_coordinator: Keys::generate().public_key(),
+        time_limit: 60,
+        seller_ecash_public_key: String::new(),
+        buyer_ecash_public_key: String::new(),
+        mint_url: MintUrl::from_str("http://localhost:3338").unwrap(),
+        currency_unit: CurrencyUnit::Sat,
+        allowed_keysets: None,
+        fee_payer: FeePayer::Buyer,
+        terms: None,
+        privacy: None,
+    }
+}
+
+fn dispute_case(buyer: Option<Verdict>, seller: Option<Verdict>) -> DisputeCase {
+    let claim = |requested_verdict: Verdict| DisputeClaim {
+        escrow_id_hex: "00".repeat(32),
+        requested_verdict,
+        statement: "statement".to_string(),
+        evidence: vec![],
+        escrow_token: None,
+        terms_opening: None,
+    };
+    DisputeCase {
+        escrow_id_hex: "00".repeat(32),
+        contract: trade_contract(),
+        contract_signatures: None,
+        timeline: vec![],
+        evidence: vec![],
+        attachments: HashMap::new(),
+        buyer_claim: buyer.map(claim),
+        seller_claim: seller.map(claim),
+        // 5000 sat in powers of two
+        escrow_amounts: vec![4096, 512, 256, 128, 8],
+    }
+}
+
+#[test]
+fn rules_resolver_follows_agreement() {
+    let resolver = RulesBasedDisputeResolver::with_default_rules(Verdict::SellerWins);
+    let case = dispute_case(Some(Verdict::BuyerWins), Some(Verdict::BuyerWins));
+    assert_eq!(resolver.decide(&case), Verdict::BuyerWins);
+}
+
+#[test]
+fn rules_resolver_awards_unanswered_claim() {
+    let resolver = RulesBasedDisputeResolver::with_default_rules(Verdict::BuyerWins);
+    let case = dispute_case(None, Some(Verdict::SellerWins));
+    assert_eq!(resolver.decide(&case), Verdict::SellerWins);
+}
+
+#[test]
+fn rules_resolver_falls_back_on_conflict() {
+    let fallback = Verdict::Split { buyer_amount: 2500 };
+    let resolver = RulesBasedDisputeResolver::with_default_rules(fallback.clone());
+    let case = dispute_case(Some(Verdict::BuyerWins), Some(Verdict::SellerWins));
+    assert_eq!(resolver.decide(&case), fallback);
+}
+
+#[test]
+fn rules_resolver_skips_unpayable_split() {
+    let half = Verdict::Split { buyer_amount: 2500 };
+    let resolver = RulesBasedDisputeResolver::with_default_rules(Verdict::SellerWins);
+    let mut case = dispute_case(Some(half.clone()), Some(half.clone()));
+    assert!(!case.is_payable(&half));
+    assert_eq!(resolver.decide(&case), Verdict::SellerWins);
+
+    // the denominations of the escrow tokens created by the client
+    case.escrow_amounts = vec![
+        2048, 1024, 512, 512, 256, 256, 128, 128, 64, 32, 16, 8, 8, 4, 2, 1, 1,
+    ];
+    assert!((0..=5000).all(|buyer_amount| case.is_payable(&Verdict::Split { buyer_amount })));
+    assert_eq!(resolver.decide(&case), half);
+}
+
+#[test]
+fn refuse_to_replace_active_trade() {
+    let storage = MemoryCoordinatorStorage::default();
+    let trade = ActiveTrade {
+        trade_contract: trade_contract(),
+        contract_signatures: None,
+        coordinator_secret: SecretKey::generate(),
+        escrow_start_time: Timestamp::now(),
+        timeline: vec![],
+        dispute: None,
+    };
+    storage.add_active_trade([1u8; 32], trade.clone()).unwrap();
+    let mut restarted = trade.clone();
+    restarted.coordinator_secret = SecretKey::generate();
+    assert!(storage.add_active_trade([1u8; 32], restarted).is_err());
+    assert_eq!(
+        storage
+            .get_active_trade(&[1u8; 32])
+            .unwrap()
+            .unwrap()
+            .coordinator_secret,
+        trade.coordinator_secret
+    );
+}
+
+#[test]
+fn policy_rejects_unsupported_mint() {
+    let contract = trade_contract();
+    let policy = CoordinatorPolicy {
+        supported_mints: Some(vec![MintUrl::from_str("https://mint.example.com").unwrap()]),
+        ..Default::default()
+    };
+    let result = policy.check_contract(&contract, &contract.npubkey_coordinator);
+    assert_eq!(result.unwrap_err().code(), "POLICY");
+
+    let policy = CoordinatorPolicy {
+        supported_mints: Some(vec![contract.mint_url.clone()]),
+        ..Default::default()
+    };
+    assert!(policy
+        .check_contract(&contract, &contract.npubkey_coordinator)
+        .is_ok());
+}
+
+#[test]
+fn policy_limits_amount_per_unit() {
+    let mut contract = trade_contract();
+    let policy = CoordinatorPolicy {
+        amount_limits: HashMap::from([(
+            CurrencyUnit::Sat,
+            AmountLimits {
+                min: None,
+                max: Some(1000),
+            },
+        )]),
+        ..Default::default()
+    };
+    let result = policy.check_contract(&contract, &contract.npubkey_coordinator);
+    assert_eq!(result.unwrap_err().code(), "POLICY");
+
+    contract.currency_unit = CurrencyUnit::Usd;
+    assert!(policy
+        .check_contract(&contract, &contract.npubkey_coordinator)
+        .is_ok());
+}
+
+#[test]
+fn deserialize_sat_denominated_contract() {
+    let mut json = serde_json::to_value(trade_contract()).unwrap();
+    let contract = json.as_object_mut().unwrap();
+    let amount = contract.remove("trade_amount").unwrap();
+    contract.insert("trade_amount_sat".to_string(), amount);
+    contract.remove("currency_unit");
+    contract.remove("fee_payer");
+
+    let contract: TradeContract = serde_json::from_value(json).unwrap();
+    assert_eq!(contract.trade_amount, 5000);
+    assert_eq!(contract.currency_unit, CurrencyUnit::Sat);
+    assert_eq!(contract.fee_payer, FeePayer::Seller);
+}
+
+fn attachment(size: usize) -> Attachment {
+    Attachment {
+        name: "photo.jpg".to_string(),
+        mime_type: "image/jpeg".to_string(),
+        content: (0..size).map(|i| i as u8).collect(),
+    }
+}
+
+#[test]
+fn policy_limits_evidence() {
+    let policy = CoordinatorPolicy {
+        evidence_limits: EvidenceLimits {
+            max_attachment_size: 1000,
+            max_total_size: 1500,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let submission = |attachment: Attachment| EvidenceSubmission {
+        escrow_id_hex: "00".repeat(32),
+        message: "Tracking number 123".to_string(),
+        attachments: vec![attachment.header()],
+    };
+    let first = submission(attachment(800));
+    assert!(policy.check_evidence(&[], &first).is_ok());
+    assert!(policy
+        .check_evidence(&[], &submission(attachment(1200)))
+        .is_err());
+
+    let submitted = [Evidence {
+        submitted_by: Party::Buyer,
+        content: first.message.clone(),
+        attachments: first.attachments.clone(),
+    }];
+    // the same attachment again, and above the total size
+    assert!(policy.check_evidence(&submitted, &first).is_err());
+    let mut twice = submission(attachment(400));
+    twice.attachments.push(twice.attachments[0].clone());
+    assert!(policy.check_evidence(&[], &twice).is_err());
+    assert!(policy
+        .check_evidence(&submitted, &submission(attachment(700)))
+        .is_err());
+}
+
+#[test]
+fn reject_evidence_after_hand_off() {
+    let policy = CoordinatorPolicy::default();
+    let mut dispute = Dispute::new(Party::Buyer);
+    assert!(policy.check_evidence_deadline(&dispute).is_ok());
+    dispute.state = DisputeState::Resolving;
+    assert!(policy.check_evidence_deadline(&dispute).is_err());
+    dispute.state = DisputeState::Failed("Mint unreachable".to_string());
+    assert!(policy.check_evidence_deadline(&dispute).is_ok());
+}
+
+#[test]
+fn assemble_attachment_from_chunks() -> anyhow::Result<()> {
+    let storage = MemoryCoordinatorStorage::default();
+    let contract_hash = [1u8; 32];
+    let attachment = attachment(2 * EVIDENCE_CHUNK_SIZE + 10);
+    let header = attachment.header();
+    assert_eq!(header.chunk_count, 3);
+
+    let mut chunks = attachment.chunks(&"01".repeat(32));
+    chunks.reverse();
+    for chunk in chunks {
+        assert!(storage.attachment(&contract_hash, &header)?.is_none());
+        let data = chunk.decode_data()?;
+        header.check_chunk(chunk.index, &data)?;
+        assert!(header
+            .check_chunk(chunk.index, &data[..data.len() - 1])
+            .is_err());
+        storage.add_attachment_chunk(
+            &contract_hash,
+            &chunk.attachment_sha256,
+            chunk.index,
+            chunk.decode_data()?,
+        )?;
+    }
+    assert!(header.check_chunk(3, &[]).is_err());
+    let content = storage.attachment(&contract_hash, &header)?.unwrap();
+    header.verify(&content)?;
+    assert_eq!(content, attachment.content);
+    Ok(())
+}
+
+/// Contract between the two traders, addressed to the coordinator.
+fn submitted_contract(
+    buyer: &NostrClient,
+    seller: &NostrClient,
+    coordinator: PublicKey,
+) -> TradeContract {
+    TradeContract {
+        npubkey_buyer: buyer.public_key(),
+        npubkey_seller: seller.public_key(),
+        npubkey_coordinator: coordinator,
+        ..trade_contract()
+    }
+}
+
+/// Both traders submit the contract and receive the registration of the escrow.
+async fn start_trade(
+    buyer: &mut NostrClient,
+    seller: &mut NostrClient,
+    coordinator: PublicKey,
+    contract: &TradeContract,
+) -> anyhow::Result<EscrowRegistration> {
+    submit_contract(buyer, coordinator, contract).await?;
+    submit_contract(seller, coordinator, contract).await?;
+    let registration: EscrowRegistration =
+        buyer.receive_escrow_message_from(coordinator, 10).await?;
+    let seller_registration: EscrowRegistration =
+        seller.receive_escrow_message_from(coordinator, 10).await?;
+    assert_eq!(registration, seller_registration);
+    assert_eq!(
+        registration.escrow_id_hex,
+        hex::encode(contract.contract_hash()?)
+    );
+    Ok(registration)
+}
+
+#[tokio::test]
+async fn report_rejected_contract_to_submitter() -> anyhow::Result<()> {
+    let TestCoordinator {
+        mut coordinator,
+        mut events,
+        ..
+    } = TestCoordinator::new().await;
+    let coordinator_pubkey = coordinator.public_key();
+    let mut buyer = create_nostr_client().await;
+    let seller = create_nostr_client().await;
+    let contract = submitted_contract(&buyer, &seller, Keys::generate().public_key());
+
+    run_coordinator(&mut coordinator, async {
+        submit_contract(&buyer, coordinator_pubkey, &contract).await?;
+        let rejection: ContractRejection = buyer
+            .receive_escrow_message_from(coordinator_pubkey, 10)
+            .await?;
+        assert_eq!(
+            rejection.contract_hash,
+            hex::encode(contract.contract_hash()?)
+        );
+        assert!(matches!(
+            next_event(&mut events).await?,
+            TradeEvent::ContractReceived { .. }
+        ));
+        assert!(matches!(
+            next_event(&mut events).await?,
+            TradeEvent::ContractRejected { .. }
+        ));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn keep_single_submission_pending() -> anyhow::Result<()> {
+    let TestCoordinator {
+        mut coordinator,
+        storage,
+        mut events,
+    } = TestCoordinator::new().await;
+    let coordinator_pubkey = coordinator.public_key();
+    let buyer = create_nostr_client().await;
+    let seller = create_nostr_client().await;
+    let contract = submitted_contract(&buyer, &seller, coordinator_pubkey);
+    let contract_hash = contract.contract_hash()?;
+
+    run_coordinator(&mut coordinator, async {
+        submit_contract(&buyer, coordinator_pubkey, &contract).await?;
+        assert!(matches!(
+            next_event(&mut events).await?,
+            TradeEvent::ContractReceived { .. }
+        ));
+        Ok(())
+    })
+    .await?;
+    assert!(events.try_recv().is_err());
+    assert!(storage.get_active_trade(&contract_hash)?.is_none());
+    let pending = storage.take_pending_contract(&contract_hash)?.unwrap();
+    assert_eq!(pending.submitted_by, Party::Buyer);
+    Ok(())
+}
+
+#[tokio::test]
+async fn start_trade_once_both_submitted() -> anyhow::Result<()> {
+    let TestCoordinator {
+        mut coordinator,
+        storage,
+        mut events,
+    } = TestCoordinator::new().await;
+    let coordinator_pubkey = coordinator.public_key();
+    let mut buyer = create_nostr_client().await;
+    let mut seller = create_nostr_client().await;
+    let contract = submitted_contract(&buyer, &seller, coordinator_pubkey);
+    let contract_hash = contract.contract_hash()?;
+
+    run_coordinator(&mut coordinator, async {
+        start_trade(&mut buyer, &mut seller, coordinator_pubkey, &contract).await?;
+        Ok(())
+    })
+    .await?;
+    let mut started = 0;
+    while let Ok(event) = events.try_recv() {
+        if let TradeEvent::TradeStarted {
+            contract_hash: hash,
+            ..
+        } = event
+        {
+            assert_eq!(hash, contract_hash);
+            started += 1;
+        }
+    }
+    assert_eq!(started, 1);
+    assert!(storage.get_active_trade(&contract_hash)?.is_some());
+    assert!(storage.take_pending_contract(&contract_hash)?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn refuse_contract_of_started_trade() -> anyhow::Result<()> {
+    let TestCoordinator {
+        mut coordinator,
+        storage,
+        ..
+    } = TestCoordinator::new().await;
+    let coordinator_pubkey = coordinator.public_key();
+    let mut buyer = create_nostr_client().await;
+    let mut seller = create_nostr_client().await;
+    let contract = submitted_contract(&buyer, &seller, coordinator_pubkey);
+    let contract_hash = contract.contract_hash()?;
+
+    let mut registration = None;
+    run_coordinator(&mut coordinator, async {
+        registration =
+            Some(start_trade(&mut buyer, &mut seller, coordinator_pubkey, &contract).await?);
+        submit_contract(&buyer, coordinator_pubkey, &contract).await?;
+        let rejection: ContractRejection = buyer
+            .receive_escrow_message_from(coordinator_pubkey, 10)
+            .await?;
+        assert_eq!(rejection.contract_hash, hex::encode(contract_hash));
+        Ok(())
+    })
+    .await?;
+    // the trade keeps the key the traders lock the escrow to
+    let trade = storage.get_active_trade(&contract_hash)?.unwrap();
+    assert_eq!(
+        trade.coordinator_secret.public_key(),
+        registration.unwrap().coordinator_escrow_pubkey
+    );
+    Ok(())
+}